@@ -0,0 +1,909 @@
+//! The [authorization rules](https://matrix.org/docs/spec/rooms/v1#authorization-rules) that
+//! decide whether an event is allowed in a room.
+//!
+//! The rules are checked against the state of the room before the event, given as a map from
+//! `(event_type, state_key)` to the state event with that type and state key. Only the events the
+//! rules refer to are used: *m.room.create*, *m.room.power_levels*, *m.room.join_rules*, the
+//! *m.room.member* events of the sender and target, and *m.room.third_party_invite* events.
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+use js_int::Int;
+use ruma_identifiers::{RoomVersionId, UserId};
+
+use crate::{
+    collections::all::{RoomEvent, StateEvent},
+    room::{
+        create::CreateEventContent,
+        join_rules::JoinRule,
+        member::{MemberEvent, MembershipState},
+        power_levels::PowerLevelsEventContent,
+        third_party_invite::ThirdPartyInviteEvent,
+    },
+    EventType,
+};
+
+/// The state of a room, as a map from `(event_type, state_key)` to state events.
+pub type StateMap = HashMap<(EventType, String), StateEvent>;
+
+/// The reason an event is not allowed by the authorization rules.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthError(String);
+
+impl AuthError {
+    /// A message describing which rule the event failed.
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for AuthError {}
+
+/// Checks whether an event is allowed by the authorization rules, given the state of the room
+/// before the event.
+///
+/// The room version is taken from the *m.room.create* event, which is the event itself for
+/// *m.room.create* events and must be in `state` otherwise. Room versions 1 to 5 are supported;
+/// events in rooms with any other version are rejected.
+///
+/// The rules that depend on an event's `prev_events` or `auth_events` can't be checked, since
+/// events don't carry them. The signatures in the `signed` block of third-party invites are
+/// verified with `signatures::verify_third_party_invite`, so third-party invites are always
+/// rejected without the `signatures` feature.
+pub fn auth_check(event: &RoomEvent, state: &StateMap) -> Result<(), AuthError> {
+    if let RoomEvent::RoomCreate(ref create) = *event {
+        return check_create(event, &create.content);
+    }
+
+    let create = match state.get(&(EventType::RoomCreate, String::new())) {
+        Some(StateEvent::RoomCreate(ref create)) => &create.content,
+        _ => return Err(error("the room has no m.room.create event")),
+    };
+
+    let rules = RoomVersionRules::new(&create.room_version)?;
+    let sender = event.sender();
+
+    if rules.special_case_aliases {
+        if let RoomEvent::RoomAliases(ref aliases) = *event {
+            return if server_name(&aliases.state_key) == server_name(sender) {
+                Ok(())
+            } else {
+                Err(error(
+                    "the state key of m.room.aliases events must be the sender's server",
+                ))
+            };
+        }
+    }
+
+    let power_levels = power_levels(state, create);
+
+    if let RoomEvent::RoomMember(ref member) = *event {
+        return check_member(state, create, &power_levels, sender, member);
+    }
+
+    if membership(state, sender) != MembershipState::Join {
+        return Err(error(format!("{} is not in the room", sender)));
+    }
+
+    let sender_level = power_levels.user_level(sender);
+
+    if let RoomEvent::RoomThirdPartyInvite(_) = *event {
+        return require_level(sender_level, power_levels.invite, "invite users");
+    }
+
+    let event_type = event.event_type();
+    let state_key = event.state_key();
+
+    require_level(
+        sender_level,
+        power_levels.event_level(&event_type, state_key.is_some()),
+        &format!("send {} events", event_type),
+    )?;
+
+    if let Some(state_key) = state_key {
+        if state_key.starts_with('@') && state_key != sender.to_string() {
+            return Err(error(format!(
+                "{} cannot send state events with the state key {}",
+                sender, state_key
+            )));
+        }
+    }
+
+    if let RoomEvent::RoomPowerLevels(ref new) = *event {
+        let old = match state.get(&(EventType::RoomPowerLevels, String::new())) {
+            Some(StateEvent::RoomPowerLevels(ref old)) => &old.content,
+            _ => return Ok(()),
+        };
+
+        return check_power_levels(sender, sender_level, old, &new.content);
+    }
+
+    if rules.special_case_redactions {
+        if let RoomEvent::RoomRedaction(ref redaction) = *event {
+            if sender_level >= power_levels.redact
+                || server_name(&redaction.event_id) == server_name(&redaction.redacts)
+            {
+                return Ok(());
+            }
+
+            return Err(error(format!(
+                "{} may only redact events from their own server",
+                sender
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// The authorization rules that differ between room versions.
+struct RoomVersionRules {
+    /// Whether *m.room.aliases* events may only be sent for the sender's own server.
+    special_case_aliases: bool,
+
+    /// Whether *m.room.redaction* events require the redact power level unless they redact an
+    /// event from the sender's server.
+    special_case_redactions: bool,
+}
+
+impl RoomVersionRules {
+    fn new(version: &RoomVersionId) -> Result<Self, AuthError> {
+        if !version.is_official() {
+            return Err(error(format!("unsupported room version {}", version)));
+        }
+
+        Ok(RoomVersionRules {
+            special_case_aliases: true,
+            special_case_redactions: version.is_version_1() || version.is_version_2(),
+        })
+    }
+}
+
+/// Checks an *m.room.create* event.
+fn check_create(event: &RoomEvent, content: &CreateEventContent) -> Result<(), AuthError> {
+    RoomVersionRules::new(&content.room_version)?;
+
+    if let Some(room_id) = event.room_id() {
+        if server_name(room_id) != server_name(event.sender()) {
+            return Err(error(
+                "the room ID of m.room.create events must be on the sender's server",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks an *m.room.member* event.
+fn check_member(
+    state: &StateMap,
+    create: &CreateEventContent,
+    power_levels: &PowerLevelsEventContent,
+    sender: &UserId,
+    member: &MemberEvent,
+) -> Result<(), AuthError> {
+    let target = member.state_key.as_str();
+    let content = &member.content;
+    let target_membership = membership_of(state, target);
+    let sender_membership = membership(state, sender);
+    let sender_level = power_levels.user_level(sender);
+    let target_level = match UserId::try_from(target) {
+        Ok(ref target) => power_levels.user_level(target),
+        Err(_) => power_levels.users_default,
+    };
+    let join_rule = match state.get(&(EventType::RoomJoinRules, String::new())) {
+        Some(StateEvent::RoomJoinRules(ref join_rules)) => join_rules.content.join_rule,
+        _ => JoinRule::Invite,
+    };
+    let is_self = target == sender.to_string();
+
+    match content.membership {
+        MembershipState::Join => {
+            if is_self && *sender == create.creator && state.len() == 1 {
+                return Ok(());
+            }
+
+            if !is_self {
+                return Err(error(format!(
+                    "{} cannot join on behalf of {}",
+                    sender, target
+                )));
+            }
+
+            match sender_membership {
+                MembershipState::Ban => Err(error(format!("{} is banned", sender))),
+                MembershipState::Invite | MembershipState::Join
+                    if join_rule == JoinRule::Invite =>
+                {
+                    Ok(())
+                }
+                _ if join_rule == JoinRule::Public => Ok(()),
+                _ => Err(error(format!("{} has not been invited", sender))),
+            }
+        }
+        MembershipState::Invite => {
+            if let Some(ref third_party_invite) = content.third_party_invite {
+                if target_membership == MembershipState::Ban {
+                    return Err(error(format!("{} is banned", target)));
+                }
+
+                let signed = &third_party_invite.signed;
+
+                if signed.mxid.to_string() != target {
+                    return Err(error(
+                        "the mxid of the third-party invite does not match the state key",
+                    ));
+                }
+
+                return match state.get(&(EventType::RoomThirdPartyInvite, signed.token.clone())) {
+                    Some(StateEvent::RoomThirdPartyInvite(ref invite))
+                        if invite.sender == *sender =>
+                    {
+                        verify_third_party_invite(member, invite)
+                    }
+                    Some(_) => Err(error("the third-party invite was sent by a different user")),
+                    None => Err(error(format!(
+                        "no m.room.third_party_invite event with the token {}",
+                        signed.token
+                    ))),
+                };
+            }
+
+            if sender_membership != MembershipState::Join {
+                return Err(error(format!("{} is not in the room", sender)));
+            }
+
+            match target_membership {
+                MembershipState::Ban => Err(error(format!("{} is banned", target))),
+                MembershipState::Join => Err(error(format!("{} is already in the room", target))),
+                _ => require_level(sender_level, power_levels.invite, "invite users"),
+            }
+        }
+        MembershipState::Leave => {
+            if is_self {
+                return match sender_membership {
+                    MembershipState::Invite | MembershipState::Join => Ok(()),
+                    _ => Err(error(format!("{} is not in the room", sender))),
+                };
+            }
+
+            if sender_membership != MembershipState::Join {
+                return Err(error(format!("{} is not in the room", sender)));
+            }
+
+            if target_membership == MembershipState::Ban {
+                require_level(sender_level, power_levels.ban, "unban users")?;
+            }
+
+            require_level(sender_level, power_levels.kick, "kick users")?;
+            require_higher_level(sender, sender_level, target, target_level)
+        }
+        MembershipState::Ban => {
+            if sender_membership != MembershipState::Join {
+                return Err(error(format!("{} is not in the room", sender)));
+            }
+
+            require_level(sender_level, power_levels.ban, "ban users")?;
+            require_higher_level(sender, sender_level, target, target_level)
+        }
+        MembershipState::Knock => Err(error(
+            "knocking is not supported by the room versions these rules apply to",
+        )),
+        MembershipState::__Nonexhaustive => {
+            panic!("__Nonexhaustive enum variant is not intended for use.")
+        }
+    }
+}
+
+/// Checks a change to the power levels of a room.
+fn check_power_levels(
+    sender: &UserId,
+    sender_level: Int,
+    old: &PowerLevelsEventContent,
+    new: &PowerLevelsEventContent,
+) -> Result<(), AuthError> {
+    let levels = [
+        ("users_default", old.users_default, new.users_default),
+        ("events_default", old.events_default, new.events_default),
+        ("state_default", old.state_default, new.state_default),
+        ("ban", old.ban, new.ban),
+        ("redact", old.redact, new.redact),
+        ("kick", old.kick, new.kick),
+        ("invite", old.invite, new.invite),
+    ];
+
+    for &(name, old_level, new_level) in &levels {
+        check_level_change(name, sender_level, Some(old_level), Some(new_level))?;
+    }
+
+    let event_types: HashSet<&EventType> = old.events.keys().chain(new.events.keys()).collect();
+
+    for event_type in event_types {
+        check_level_change(
+            &format!("events.{}", event_type),
+            sender_level,
+            old.events.get(event_type).cloned(),
+            new.events.get(event_type).cloned(),
+        )?;
+    }
+
+    let users: HashSet<&UserId> = old.users.keys().chain(new.users.keys()).collect();
+
+    for user in users {
+        let old_level = old.users.get(user).cloned();
+        let new_level = new.users.get(user).cloned();
+
+        check_level_change(
+            &format!("users.{}", user),
+            sender_level,
+            old_level,
+            new_level,
+        )?;
+
+        if old_level != new_level && user != sender && old_level == Some(sender_level) {
+            return Err(error(format!(
+                "{} cannot change the power level of {}, who has the same power level",
+                sender, user
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a power level that is added, changed or removed doesn't exceed the sender's.
+fn check_level_change(
+    name: &str,
+    sender_level: Int,
+    old: Option<Int>,
+    new: Option<Int>,
+) -> Result<(), AuthError> {
+    if old == new {
+        return Ok(());
+    }
+
+    if old.map_or(false, |old| old > sender_level) || new.map_or(false, |new| new > sender_level) {
+        return Err(error(format!(
+            "the power level {} cannot be changed to or from a level above the sender's",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+/// Verifies the `signed` block of a third-party invite against the *m.room.third_party_invite*
+/// event it claims.
+#[cfg(feature = "signatures")]
+fn verify_third_party_invite(
+    member: &MemberEvent,
+    invite: &ThirdPartyInviteEvent,
+) -> Result<(), AuthError> {
+    crate::signatures::verify_third_party_invite(member, invite)
+        .map_err(|error| AuthError(format!("the third-party invite is invalid: {}", error)))
+}
+
+/// Rejects every third-party invite, since signatures can only be verified with the `signatures`
+/// feature.
+#[cfg(not(feature = "signatures"))]
+fn verify_third_party_invite(
+    _member: &MemberEvent,
+    _invite: &ThirdPartyInviteEvent,
+) -> Result<(), AuthError> {
+    Err(error(
+        "third-party invite signatures can't be verified without the `signatures` feature",
+    ))
+}
+
+/// The power levels of the room, falling back to the levels of a room without an
+/// *m.room.power_levels* event.
+fn power_levels(state: &StateMap, create: &CreateEventContent) -> PowerLevelsEventContent {
+    match state.get(&(EventType::RoomPowerLevels, String::new())) {
+        Some(StateEvent::RoomPowerLevels(ref power_levels)) => power_levels.content.clone(),
+        _ => PowerLevelsEventContent::without_event(create),
+    }
+}
+
+/// The membership of a user, which is `Leave` if they have no *m.room.member* event.
+fn membership(state: &StateMap, user: &UserId) -> MembershipState {
+    membership_of(state, &user.to_string())
+}
+
+/// The membership of the user with the given ID.
+fn membership_of(state: &StateMap, user: &str) -> MembershipState {
+    match state.get(&(EventType::RoomMember, user.to_string())) {
+        Some(StateEvent::RoomMember(ref member)) => member.content.membership,
+        _ => MembershipState::Leave,
+    }
+}
+
+/// Fails unless the sender's power level is at least the required level for an action.
+fn require_level(sender_level: Int, required_level: Int, action: &str) -> Result<(), AuthError> {
+    if sender_level >= required_level {
+        Ok(())
+    } else {
+        Err(error(format!(
+            "power level {} is required to {}, but the sender has {}",
+            required_level, action, sender_level
+        )))
+    }
+}
+
+/// Fails unless the sender's power level is higher than the target's.
+fn require_higher_level(
+    sender: &UserId,
+    sender_level: Int,
+    target: &str,
+    target_level: Int,
+) -> Result<(), AuthError> {
+    if sender_level > target_level {
+        Ok(())
+    } else {
+        Err(error(format!(
+            "{} does not have a higher power level than {}",
+            sender, target
+        )))
+    }
+}
+
+/// The server name of an identifier, i.e. everything after the first colon, if it has one.
+fn server_name<T: Display>(id: T) -> Option<String> {
+    let id = id.to_string();
+
+    id.find(':').map(|index| id[index + 1..].to_string())
+}
+
+/// Creates an `AuthError` with the given message.
+fn error<T: Into<String>>(message: T) -> AuthError {
+    AuthError(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ruma_identifiers::UserId;
+    use serde_json::{json, Value};
+
+    use super::{auth_check, StateMap};
+    use crate::collections::all::{RoomEvent, StateEvent};
+
+    fn event(json: Value) -> RoomEvent {
+        json.to_string().parse().unwrap()
+    }
+
+    fn state_event(event_type: &str, state_key: &str, sender: &str, content: Value) -> StateEvent {
+        json!({
+            "content": content,
+            "event_id": format!(
+                "${}{}:example.com",
+                event_type,
+                state_key.replace(|c: char| !c.is_ascii_alphanumeric(), "")
+            ),
+            "origin_server_ts": 1,
+            "room_id": "!room:example.com",
+            "sender": sender,
+            "state_key": state_key,
+            "type": event_type
+        })
+        .to_string()
+        .parse()
+        .unwrap()
+    }
+
+    /// The public key of a third-party invite and a `signed` block for it.
+    #[cfg(feature = "signatures")]
+    fn third_party_signed(mxid: &str, token: &str) -> (String, Value) {
+        use crate::signatures::{sign_json, Ed25519KeyPair};
+
+        let key_pair = Ed25519KeyPair::new(&[1; 32], "0".to_string()).unwrap();
+        let mut signed = json!({"mxid": mxid, "token": token});
+
+        sign_json("identity.example.com", &key_pair, &mut signed).unwrap();
+
+        (key_pair.public_key(), signed)
+    }
+
+    /// The public key of a third-party invite and a `signed` block for it.
+    ///
+    /// Signatures can only be created with the `signatures` feature, so these are placeholders.
+    #[cfg(not(feature = "signatures"))]
+    fn third_party_signed(mxid: &str, token: &str) -> (String, Value) {
+        (
+            "key".to_string(),
+            json!({
+                "mxid": mxid,
+                "signatures": {"identity.example.com": {"ed25519:0": "sig"}},
+                "token": token
+            }),
+        )
+    }
+
+    fn member(state_key: &str, sender: &str, membership: &str) -> RoomEvent {
+        state_event(
+            "m.room.member",
+            state_key,
+            sender,
+            json!({ "membership": membership }),
+        )
+        .into()
+    }
+
+    fn message(sender: &str) -> RoomEvent {
+        event(json!({
+            "content": {"body": "hello", "msgtype": "m.text"},
+            "event_id": "$message:example.com",
+            "origin_server_ts": 1,
+            "room_id": "!room:example.com",
+            "sender": sender,
+            "type": "m.room.message"
+        }))
+    }
+
+    fn power_levels(users: Value) -> StateEvent {
+        state_event(
+            "m.room.power_levels",
+            "",
+            "@alice:example.com",
+            json!({ "users": users }),
+        )
+    }
+
+    fn insert(state: &mut StateMap, event: StateEvent) {
+        state.insert((event.event_type(), event.state_key().to_string()), event);
+    }
+
+    /// A room created by Alice, with Bob as a moderator and Carl as a regular member.
+    fn room(version: &str, join_rule: &str) -> StateMap {
+        let mut state = StateMap::new();
+
+        insert(
+            &mut state,
+            state_event(
+                "m.room.create",
+                "",
+                "@alice:example.com",
+                json!({"creator": "@alice:example.com", "room_version": version}),
+            ),
+        );
+        insert(
+            &mut state,
+            power_levels(json!({"@alice:example.com": 100, "@bob:example.com": 50})),
+        );
+        insert(
+            &mut state,
+            state_event(
+                "m.room.join_rules",
+                "",
+                "@alice:example.com",
+                json!({ "join_rule": join_rule }),
+            ),
+        );
+
+        for user in &[
+            "@alice:example.com",
+            "@bob:example.com",
+            "@carl:example.com",
+        ] {
+            insert(
+                &mut state,
+                state_event("m.room.member", user, user, json!({"membership": "join"})),
+            );
+        }
+
+        state
+    }
+
+    #[test]
+    fn create_and_first_join() {
+        let create = state_event(
+            "m.room.create",
+            "",
+            "@alice:example.com",
+            json!({"creator": "@alice:example.com"}),
+        );
+
+        assert!(auth_check(&create.clone().into(), &StateMap::new()).is_ok());
+
+        let mut state = StateMap::new();
+        insert(&mut state, create);
+
+        assert!(auth_check(
+            &member("@alice:example.com", "@alice:example.com", "join"),
+            &state
+        )
+        .is_ok());
+        assert!(auth_check(
+            &member("@bob:example.com", "@bob:example.com", "join"),
+            &state
+        )
+        .is_err());
+        assert!(auth_check(&message("@alice:example.com"), &StateMap::new()).is_err());
+
+        let foreign_create = state_event(
+            "m.room.create",
+            "",
+            "@mallory:evil.example.com",
+            json!({"creator": "@mallory:evil.example.com"}),
+        );
+
+        assert!(auth_check(&foreign_create.into(), &StateMap::new()).is_err());
+    }
+
+    #[test]
+    fn joins() {
+        let invite_only = room("1", "invite");
+        let public = room("1", "public");
+        let dave_joins = member("@dave:example.com", "@dave:example.com", "join");
+
+        assert!(auth_check(&dave_joins, &invite_only).is_err());
+        assert!(auth_check(&dave_joins, &public).is_ok());
+        assert!(auth_check(
+            &member("@dave:example.com", "@carl:example.com", "join"),
+            &public
+        )
+        .is_err());
+
+        let mut invited = invite_only.clone();
+        insert(
+            &mut invited,
+            state_event(
+                "m.room.member",
+                "@dave:example.com",
+                "@bob:example.com",
+                json!({"membership": "invite"}),
+            ),
+        );
+
+        assert!(auth_check(&dave_joins, &invited).is_ok());
+
+        let mut banned = public.clone();
+        insert(
+            &mut banned,
+            state_event(
+                "m.room.member",
+                "@dave:example.com",
+                "@bob:example.com",
+                json!({"membership": "ban"}),
+            ),
+        );
+
+        assert!(auth_check(&dave_joins, &banned).is_err());
+
+        let bob_joins = member("@bob:example.com", "@bob:example.com", "join");
+
+        assert!(auth_check(&bob_joins, &invite_only).is_ok());
+        assert!(auth_check(&bob_joins, &public).is_ok());
+        assert!(auth_check(&bob_joins, &room("1", "private")).is_err());
+        assert!(auth_check(&bob_joins, &room("1", "knock")).is_err());
+    }
+
+    #[test]
+    fn invites_kicks_and_bans() {
+        let state = room("1", "invite");
+
+        assert!(auth_check(
+            &member("@dave:example.com", "@bob:example.com", "invite"),
+            &state
+        )
+        .is_ok());
+        assert!(auth_check(
+            &member("@dave:example.com", "@carl:example.com", "invite"),
+            &state
+        )
+        .is_err());
+        assert!(auth_check(
+            &member("@carl:example.com", "@bob:example.com", "invite"),
+            &state
+        )
+        .is_err());
+        assert!(auth_check(
+            &member("@dave:example.com", "@dave:example.com", "invite"),
+            &state
+        )
+        .is_err());
+
+        assert!(auth_check(
+            &member("@carl:example.com", "@bob:example.com", "leave"),
+            &state
+        )
+        .is_ok());
+        assert!(auth_check(
+            &member("@alice:example.com", "@bob:example.com", "leave"),
+            &state
+        )
+        .is_err());
+        assert!(auth_check(
+            &member("@bob:example.com", "@carl:example.com", "leave"),
+            &state
+        )
+        .is_err());
+        assert!(auth_check(
+            &member("@carl:example.com", "@carl:example.com", "leave"),
+            &state
+        )
+        .is_ok());
+
+        assert!(auth_check(
+            &member("@carl:example.com", "@bob:example.com", "ban"),
+            &state
+        )
+        .is_ok());
+        assert!(auth_check(
+            &member("@bob:example.com", "@carl:example.com", "ban"),
+            &state
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn knocks() {
+        let knock = member("@dave:example.com", "@dave:example.com", "knock");
+
+        assert!(auth_check(&knock, &room("1", "invite")).is_err());
+        assert!(auth_check(&knock, &room("1", "knock")).is_err());
+        assert!(auth_check(&knock, &room("5", "knock"))
+            .unwrap_err()
+            .message()
+            .starts_with("knocking is not supported"));
+    }
+
+    #[test]
+    fn third_party_invites() {
+        let mut state = room("1", "invite");
+        insert(
+            &mut state,
+            state_event(
+                "m.room.third_party_invite",
+                "token",
+                "@bob:example.com",
+                json!({
+                    "display_name": "dave",
+                    "key_validity_url": "https://identity.example.com/isvalid",
+                    "public_key": third_party_signed("", "").0
+                }),
+            ),
+        );
+
+        let invite = |sender: &str, mxid: &str, token: &str| -> RoomEvent {
+            state_event(
+                "m.room.member",
+                "@dave:example.com",
+                sender,
+                json!({
+                    "membership": "invite",
+                    "third_party_invite": {
+                        "display_name": "dave",
+                        "signed": third_party_signed(mxid, token).1
+                    }
+                }),
+            )
+            .into()
+        };
+
+        let valid = auth_check(
+            &invite("@bob:example.com", "@dave:example.com", "token"),
+            &state,
+        );
+
+        if cfg!(feature = "signatures") {
+            assert!(valid.is_ok());
+        } else {
+            assert!(valid
+                .unwrap_err()
+                .message()
+                .contains("`signatures` feature"));
+        }
+
+        assert!(auth_check(
+            &invite("@bob:example.com", "@dave:example.com", "other"),
+            &state
+        )
+        .is_err());
+        assert!(auth_check(
+            &invite("@bob:example.com", "@erin:example.com", "token"),
+            &state
+        )
+        .is_err());
+        assert!(auth_check(
+            &invite("@carl:example.com", "@dave:example.com", "token"),
+            &state
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn events_and_power_levels() {
+        let state = room("1", "invite");
+
+        assert!(auth_check(&message("@carl:example.com"), &state).is_ok());
+        assert!(auth_check(&message("@dave:example.com"), &state).is_err());
+
+        let topic = |sender: &str| -> RoomEvent {
+            state_event("m.room.topic", "", sender, json!({"topic": "hi"})).into()
+        };
+
+        assert!(auth_check(&topic("@bob:example.com"), &state).is_ok());
+        assert!(auth_check(&topic("@carl:example.com"), &state).is_err());
+
+        let change = |sender: &str, users: Value| -> RoomEvent {
+            let mut event = power_levels(users);
+
+            if let StateEvent::RoomPowerLevels(ref mut event) = event {
+                event.sender = UserId::try_from(sender).unwrap();
+            }
+
+            event.into()
+        };
+
+        assert!(auth_check(
+            &change(
+                "@bob:example.com",
+                json!({"@alice:example.com": 100, "@bob:example.com": 50, "@carl:example.com": 50})
+            ),
+            &state
+        )
+        .is_ok());
+        assert!(auth_check(
+            &change(
+                "@bob:example.com",
+                json!({"@alice:example.com": 100, "@bob:example.com": 50, "@carl:example.com": 51})
+            ),
+            &state
+        )
+        .is_err());
+        assert!(auth_check(
+            &change("@bob:example.com", json!({"@alice:example.com": 100})),
+            &state
+        )
+        .is_ok());
+        assert!(auth_check(
+            &change(
+                "@alice:example.com",
+                json!({"@alice:example.com": 100, "@bob:example.com": 0})
+            ),
+            &state
+        )
+        .is_ok());
+        assert!(auth_check(
+            &change("@bob:example.com", json!({"@bob:example.com": 50})),
+            &state
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn redactions() {
+        let redaction = |sender: &str, event_id: &str| -> RoomEvent {
+            event(json!({
+                "content": {},
+                "event_id": event_id,
+                "origin_server_ts": 1,
+                "redacts": "$message:example.com",
+                "room_id": "!room:example.com",
+                "sender": sender,
+                "type": "m.room.redaction"
+            }))
+        };
+
+        let v1 = room("1", "invite");
+        let v3 = room("3", "invite");
+
+        assert!(auth_check(&redaction("@bob:example.com", "$r:other.com"), &v1).is_ok());
+        assert!(auth_check(&redaction("@carl:example.com", "$r:example.com"), &v1).is_ok());
+        assert!(auth_check(&redaction("@carl:example.com", "$r:other.com"), &v1).is_err());
+        assert!(auth_check(&redaction("@carl:example.com", "$r"), &v3).is_ok());
+        assert!(auth_check(&message("@carl:example.com"), &room("custom", "invite")).is_err());
+    }
+}
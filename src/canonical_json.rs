@@ -0,0 +1,265 @@
+//! [Canonical JSON](https://matrix.org/docs/spec/appendices#canonical-json), the encoding that
+//! event signatures and hashes are computed over.
+//!
+//! Canonical JSON has no insignificant whitespace, sorts the keys of objects by their Unicode code
+//! points, writes strings as raw UTF-8 with only the characters JSON requires escaped, and only
+//! allows integers in the range `[-(2**53)+1, (2**53)-1]`, which is the range of `js_int::Int`.
+//! Floating point numbers are not allowed, so events with fields like `tag::TagInfo::order` can
+//! only be encoded if those fields are unset.
+
+use serde::Serialize;
+use serde_json::{to_value, Map, Value};
+
+use crate::InvalidInput;
+
+/// The largest magnitude of an integer allowed in canonical JSON, `(2**53)-1`.
+const MAX_SAFE_INTEGER: u64 = 9_007_199_254_740_991;
+
+/// Encodes any serializable value, such as an event, as canonical JSON.
+///
+/// Returns an error if the value doesn't serialize to JSON or contains a number that is not
+/// allowed in canonical JSON.
+pub fn to_canonical_json<T>(value: &T) -> Result<String, InvalidInput>
+where
+    T: Serialize,
+{
+    let value = to_value(value).map_err(|error| InvalidInput(error.to_string()))?;
+
+    validate_canonical_json(&value)?;
+
+    let mut json = String::new();
+    write_value(&value, &mut json);
+
+    Ok(json)
+}
+
+/// Checks that a JSON value can be encoded as canonical JSON.
+///
+/// This fails if the value contains a floating point number or an integer outside of the range
+/// of `js_int::Int`. The error message includes the path of the offending number.
+pub fn validate_canonical_json(value: &Value) -> Result<(), InvalidInput> {
+    validate(value, &mut Vec::new())
+}
+
+/// Validates a value, with `path` being the keys and indices leading to it.
+fn validate(value: &Value, path: &mut Vec<String>) -> Result<(), InvalidInput> {
+    match *value {
+        Value::Number(ref number) => {
+            let is_safe_integer = match (number.as_u64(), number.as_i64()) {
+                (Some(integer), _) => integer <= MAX_SAFE_INTEGER,
+                (None, Some(integer)) => integer >= -(MAX_SAFE_INTEGER as i64),
+                (None, None) => false,
+            };
+
+            if is_safe_integer {
+                Ok(())
+            } else if number.is_f64() {
+                Err(InvalidInput(format!(
+                    "canonical JSON does not allow floating point numbers, found {} at `{}`",
+                    number,
+                    path.join(".")
+                )))
+            } else {
+                Err(InvalidInput(format!(
+                    "canonical JSON does not allow integers outside of [-(2**53)+1, (2**53)-1], \
+                     found {} at `{}`",
+                    number,
+                    path.join(".")
+                )))
+            }
+        }
+        Value::Array(ref values) => {
+            for (index, value) in values.iter().enumerate() {
+                path.push(index.to_string());
+                validate(value, path)?;
+                path.pop();
+            }
+
+            Ok(())
+        }
+        Value::Object(ref object) => {
+            for (key, value) in object {
+                path.push(key.clone());
+                validate(value, path)?;
+                path.pop();
+            }
+
+            Ok(())
+        }
+        Value::Null | Value::Bool(_) | Value::String(_) => Ok(()),
+    }
+}
+
+/// Writes a validated value as canonical JSON.
+fn write_value(value: &Value, json: &mut String) {
+    match *value {
+        Value::Array(ref values) => {
+            json.push('[');
+
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+
+                write_value(value, json);
+            }
+
+            json.push(']');
+        }
+        Value::Object(ref object) => write_object(object, json),
+        Value::String(ref string) => write_string(string, json),
+        Value::Null | Value::Bool(_) | Value::Number(_) => json.push_str(&value.to_string()),
+    }
+}
+
+/// Writes an object with its keys sorted by their Unicode code points.
+fn write_object(object: &Map<String, Value>, json: &mut String) {
+    // Comparing UTF-8 bytes orders strings the same way as comparing code points.
+    let mut entries: Vec<(&String, &Value)> = object.iter().collect();
+    entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+    json.push('{');
+
+    for (index, (key, value)) in entries.into_iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+
+        write_string(key, json);
+        json.push(':');
+        write_value(value, json);
+    }
+
+    json.push('}');
+}
+
+/// Writes a string, escaping only `"`, `\` and control characters.
+///
+/// The control characters with short escape sequences use them, and the others are written as
+/// `\u00XX` with lowercase hexadecimal digits.
+fn write_string(string: &str, json: &mut String) {
+    json.push('"');
+
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\u{8}' => json.push_str("\\b"),
+            '\u{c}' => json.push_str("\\f"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            '\u{0}'..='\u{1f}' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => json.push(c),
+        }
+    }
+
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::{to_canonical_json, validate_canonical_json};
+    use crate::tag::{TagEvent, TagEventContent, TagInfo};
+
+    #[test]
+    fn spec_examples() {
+        assert_eq!(to_canonical_json(&json!({})).unwrap(), "{}");
+        assert_eq!(
+            to_canonical_json(&json!({"one": 1, "two": "Two"})).unwrap(),
+            r#"{"one":1,"two":"Two"}"#
+        );
+        assert_eq!(
+            to_canonical_json(&json!({"b": "2", "a": "1"})).unwrap(),
+            r#"{"a":"1","b":"2"}"#
+        );
+        assert_eq!(
+            to_canonical_json(&json!({
+                "auth": {
+                    "success": true,
+                    "mxid": "@john.doe:example.com",
+                    "profile": {
+                        "display_name": "John Doe",
+                        "three_pids": [
+                            {"medium": "email", "address": "john.doe@example.org"},
+                            {"medium": "msisdn", "address": "123456789"}
+                        ]
+                    }
+                }
+            }))
+            .unwrap(),
+            r#"{"auth":{"mxid":"@john.doe:example.com","profile":{"display_name":"John Doe","three_pids":[{"address":"john.doe@example.org","medium":"email"},{"address":"123456789","medium":"msisdn"}]},"success":true}}"#
+        );
+        assert_eq!(
+            to_canonical_json(&json!({"a": "日本語"})).unwrap(),
+            r#"{"a":"日本語"}"#
+        );
+        assert_eq!(
+            to_canonical_json(&json!({"本": 2, "日": 1})).unwrap(),
+            r#"{"日":1,"本":2}"#
+        );
+        assert_eq!(
+            to_canonical_json(&json!({"a": "\u{65e5}"})).unwrap(),
+            r#"{"a":"日"}"#
+        );
+        assert_eq!(
+            to_canonical_json(&json!({"a": null})).unwrap(),
+            r#"{"a":null}"#
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            to_canonical_json(&json!("\"\\/\u{8}\u{c}\n\r\t\u{0}\u{1f}\u{7f}\u{2028}")).unwrap(),
+            "\"\\\"\\\\/\\b\\f\\n\\r\\t\\u0000\\u001f\u{7f}\u{2028}\""
+        );
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(
+            to_canonical_json(&json!([
+                9_007_199_254_740_991u64,
+                -9_007_199_254_740_991i64,
+                0
+            ]))
+            .unwrap(),
+            "[9007199254740991,-9007199254740991,0]"
+        );
+        assert!(to_canonical_json(&json!(9_007_199_254_740_992u64)).is_err());
+        assert!(to_canonical_json(&json!(-9_007_199_254_740_992i64)).is_err());
+    }
+
+    #[test]
+    fn floats() {
+        let mut tags = HashMap::new();
+        tags.insert("u.work".to_string(), TagInfo { order: Some(0.5) });
+
+        let event = TagEvent {
+            content: TagEventContent { tags },
+        };
+
+        assert!(to_canonical_json(&event)
+            .unwrap_err()
+            .to_string()
+            .contains("`content.tags.u.work.order`"));
+        assert!(validate_canonical_json(&json!({"a": [1.0]})).is_err());
+
+        let mut tags = HashMap::new();
+        tags.insert("u.work".to_string(), TagInfo { order: None });
+
+        let event = TagEvent {
+            content: TagEventContent { tags },
+        };
+
+        assert_eq!(
+            to_canonical_json(&event).unwrap(),
+            r#"{"content":{"tags":{"u.work":{}}},"type":"m.tag"}"#
+        );
+    }
+}
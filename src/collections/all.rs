@@ -3,8 +3,10 @@
 
 use std::str::FromStr;
 
+use js_int::UInt;
+use ruma_identifiers::{EventId, RoomId, RoomVersionId, UserId};
 use serde::{Serialize, Serializer};
-use serde_json::{from_value, Value};
+use serde_json::{from_value, to_value, Value};
 
 use crate::{
     call::{
@@ -21,6 +23,7 @@ use crate::{
     },
     presence::PresenceEvent,
     push_rules::PushRulesEvent,
+    reaction::ReactionEvent,
     receipt::ReceiptEvent,
     room::{
         aliases::AliasesEvent,
@@ -37,7 +40,7 @@ use crate::{
         name::NameEvent,
         pinned_events::PinnedEventsEvent,
         power_levels::PowerLevelsEvent,
-        redaction::RedactionEvent,
+        redaction::{redact_for_client, RedactionEvent},
         server_acl::ServerAclEvent,
         third_party_invite::ThirdPartyInviteEvent,
         tombstone::TombstoneEvent,
@@ -48,7 +51,8 @@ use crate::{
     sticker::StickerEvent,
     tag::TagEvent,
     typing::TypingEvent,
-    CustomEvent, CustomRoomEvent, CustomStateEvent, EventType, InnerInvalidEvent, InvalidEvent,
+    CustomEvent, CustomRoomEvent, CustomStateEvent, Event as _, EventType, InnerInvalidEvent,
+    InvalidEvent, RoomEvent as _, StateEvent as _,
 };
 
 /// A basic event, room event, or state event.
@@ -106,6 +110,9 @@ pub enum Event {
     /// m.push_rules
     PushRules(PushRulesEvent),
 
+    /// m.reaction
+    Reaction(ReactionEvent),
+
     /// m.receipt
     Receipt(ReceiptEvent),
 
@@ -210,6 +217,9 @@ pub enum RoomEvent {
     /// m.call.invite
     CallInvite(InviteEvent),
 
+    /// m.reaction
+    Reaction(ReactionEvent),
+
     /// m.room.aliases
     RoomAliases(AliasesEvent),
 
@@ -359,6 +369,7 @@ impl Serialize for Event {
             Event::IgnoredUserList(ref event) => event.serialize(serializer),
             Event::Presence(ref event) => event.serialize(serializer),
             Event::PushRules(ref event) => event.serialize(serializer),
+            Event::Reaction(ref event) => event.serialize(serializer),
             Event::Receipt(ref event) => event.serialize(serializer),
             Event::RoomAliases(ref event) => event.serialize(serializer),
             Event::RoomAvatar(ref event) => event.serialize(serializer),
@@ -540,6 +551,13 @@ impl FromStr for Event {
                     message: error.to_string(),
                 })),
             },
+            EventType::Reaction => match json.parse() {
+                Ok(event) => Ok(Event::Reaction(event)),
+                Err(error) => Err(InvalidEvent(InnerInvalidEvent::Validation {
+                    json: value,
+                    message: error.to_string(),
+                })),
+            },
             EventType::Receipt => match json.parse() {
                 Ok(event) => Ok(Event::Receipt(event)),
                 Err(error) => Err(InvalidEvent(InnerInvalidEvent::Validation {
@@ -769,6 +787,7 @@ impl Serialize for RoomEvent {
             RoomEvent::CallCandidates(ref event) => event.serialize(serializer),
             RoomEvent::CallHangup(ref event) => event.serialize(serializer),
             RoomEvent::CallInvite(ref event) => event.serialize(serializer),
+            RoomEvent::Reaction(ref event) => event.serialize(serializer),
             RoomEvent::RoomAliases(ref event) => event.serialize(serializer),
             RoomEvent::RoomAvatar(ref event) => event.serialize(serializer),
             RoomEvent::RoomCanonicalAlias(ref event) => event.serialize(serializer),
@@ -853,6 +872,13 @@ impl FromStr for RoomEvent {
                     message: error.to_string(),
                 })),
             },
+            EventType::Reaction => match json.parse() {
+                Ok(event) => Ok(RoomEvent::Reaction(event)),
+                Err(error) => Err(InvalidEvent(InnerInvalidEvent::Validation {
+                    json: value,
+                    message: error.to_string(),
+                })),
+            },
             EventType::RoomAliases => match json.parse() {
                 Ok(event) => Ok(RoomEvent::RoomAliases(event)),
                 Err(error) => Err(InvalidEvent(InnerInvalidEvent::Validation {
@@ -1238,6 +1264,7 @@ impl FromStr for StateEvent {
             | EventType::IgnoredUserList
             | EventType::Presence
             | EventType::PushRules
+            | EventType::Reaction
             | EventType::Receipt
             | EventType::RoomEncrypted
             | EventType::RoomMessage
@@ -1285,6 +1312,7 @@ impl_from_t_for_event!(StartEvent, KeyVerificationStart);
 impl_from_t_for_event!(IgnoredUserListEvent, IgnoredUserList);
 impl_from_t_for_event!(PresenceEvent, Presence);
 impl_from_t_for_event!(PushRulesEvent, PushRules);
+impl_from_t_for_event!(ReactionEvent, Reaction);
 impl_from_t_for_event!(ReceiptEvent, Receipt);
 impl_from_t_for_event!(AliasesEvent, RoomAliases);
 impl_from_t_for_event!(AvatarEvent, RoomAvatar);
@@ -1329,6 +1357,7 @@ impl_from_t_for_room_event!(AnswerEvent, CallAnswer);
 impl_from_t_for_room_event!(CandidatesEvent, CallCandidates);
 impl_from_t_for_room_event!(HangupEvent, CallHangup);
 impl_from_t_for_room_event!(InviteEvent, CallInvite);
+impl_from_t_for_room_event!(ReactionEvent, Reaction);
 impl_from_t_for_room_event!(AliasesEvent, RoomAliases);
 impl_from_t_for_room_event!(AvatarEvent, RoomAvatar);
 impl_from_t_for_room_event!(CanonicalAliasEvent, RoomCanonicalAlias);
@@ -1380,3 +1409,281 @@ impl_from_t_for_state_event!(ThirdPartyInviteEvent, RoomThirdPartyInvite);
 impl_from_t_for_state_event!(TombstoneEvent, RoomTombstone);
 impl_from_t_for_state_event!(TopicEvent, RoomTopic);
 impl_from_t_for_state_event!(CustomStateEvent, CustomState);
+
+/// Evaluates an expression with the event held by any variant of `RoomEvent`.
+macro_rules! with_room_event {
+    ($self:expr, $event:ident => $body:expr) => {
+        match *$self {
+            RoomEvent::CallAnswer(ref $event) => $body,
+            RoomEvent::CallCandidates(ref $event) => $body,
+            RoomEvent::CallHangup(ref $event) => $body,
+            RoomEvent::CallInvite(ref $event) => $body,
+            RoomEvent::Reaction(ref $event) => $body,
+            RoomEvent::RoomAliases(ref $event) => $body,
+            RoomEvent::RoomAvatar(ref $event) => $body,
+            RoomEvent::RoomCanonicalAlias(ref $event) => $body,
+            RoomEvent::RoomCreate(ref $event) => $body,
+            RoomEvent::RoomEncrypted(ref $event) => $body,
+            RoomEvent::RoomEncryption(ref $event) => $body,
+            RoomEvent::RoomGuestAccess(ref $event) => $body,
+            RoomEvent::RoomHistoryVisibility(ref $event) => $body,
+            RoomEvent::RoomJoinRules(ref $event) => $body,
+            RoomEvent::RoomMember(ref $event) => $body,
+            RoomEvent::RoomMessage(ref $event) => $body,
+            RoomEvent::RoomMessageFeedback(ref $event) => $body,
+            RoomEvent::RoomName(ref $event) => $body,
+            RoomEvent::RoomPinnedEvents(ref $event) => $body,
+            RoomEvent::RoomPowerLevels(ref $event) => $body,
+            RoomEvent::RoomRedaction(ref $event) => $body,
+            RoomEvent::RoomServerAcl(ref $event) => $body,
+            RoomEvent::RoomThirdPartyInvite(ref $event) => $body,
+            RoomEvent::RoomTombstone(ref $event) => $body,
+            RoomEvent::RoomTopic(ref $event) => $body,
+            RoomEvent::Sticker(ref $event) => $body,
+            RoomEvent::CustomRoom(ref $event) => $body,
+            RoomEvent::CustomState(ref $event) => $body,
+        }
+    };
+}
+
+/// Evaluates an expression with the event held by any variant of `StateEvent`.
+macro_rules! with_state_event {
+    ($self:expr, $event:ident => $body:expr) => {
+        match *$self {
+            StateEvent::RoomAliases(ref $event) => $body,
+            StateEvent::RoomAvatar(ref $event) => $body,
+            StateEvent::RoomCanonicalAlias(ref $event) => $body,
+            StateEvent::RoomCreate(ref $event) => $body,
+            StateEvent::RoomEncryption(ref $event) => $body,
+            StateEvent::RoomGuestAccess(ref $event) => $body,
+            StateEvent::RoomHistoryVisibility(ref $event) => $body,
+            StateEvent::RoomJoinRules(ref $event) => $body,
+            StateEvent::RoomMember(ref $event) => $body,
+            StateEvent::RoomName(ref $event) => $body,
+            StateEvent::RoomPinnedEvents(ref $event) => $body,
+            StateEvent::RoomPowerLevels(ref $event) => $body,
+            StateEvent::RoomServerAcl(ref $event) => $body,
+            StateEvent::RoomThirdPartyInvite(ref $event) => $body,
+            StateEvent::RoomTombstone(ref $event) => $body,
+            StateEvent::RoomTopic(ref $event) => $body,
+            StateEvent::CustomState(ref $event) => $body,
+        }
+    };
+}
+
+impl From<StateEvent> for RoomEvent {
+    fn from(event: StateEvent) -> Self {
+        match event {
+            StateEvent::RoomAliases(event) => RoomEvent::RoomAliases(event),
+            StateEvent::RoomAvatar(event) => RoomEvent::RoomAvatar(event),
+            StateEvent::RoomCanonicalAlias(event) => RoomEvent::RoomCanonicalAlias(event),
+            StateEvent::RoomCreate(event) => RoomEvent::RoomCreate(event),
+            StateEvent::RoomEncryption(event) => RoomEvent::RoomEncryption(event),
+            StateEvent::RoomGuestAccess(event) => RoomEvent::RoomGuestAccess(event),
+            StateEvent::RoomHistoryVisibility(event) => RoomEvent::RoomHistoryVisibility(event),
+            StateEvent::RoomJoinRules(event) => RoomEvent::RoomJoinRules(event),
+            StateEvent::RoomMember(event) => RoomEvent::RoomMember(event),
+            StateEvent::RoomName(event) => RoomEvent::RoomName(event),
+            StateEvent::RoomPinnedEvents(event) => RoomEvent::RoomPinnedEvents(event),
+            StateEvent::RoomPowerLevels(event) => RoomEvent::RoomPowerLevels(event),
+            StateEvent::RoomServerAcl(event) => RoomEvent::RoomServerAcl(event),
+            StateEvent::RoomThirdPartyInvite(event) => RoomEvent::RoomThirdPartyInvite(event),
+            StateEvent::RoomTombstone(event) => RoomEvent::RoomTombstone(event),
+            StateEvent::RoomTopic(event) => RoomEvent::RoomTopic(event),
+            StateEvent::CustomState(event) => RoomEvent::CustomState(event),
+        }
+    }
+}
+
+impl Event {
+    /// Redacts the event in response to the given redaction event.
+    ///
+    /// The content is stripped down to the keys the specification preserves for the event's type
+    /// in the given room version, and the redaction event is added to the event's unsigned data
+    /// as `redacted_because`. If the redacted content is no longer valid for the event's type,
+    /// such as for an *m.room.message* event, the result is a `CustomRoom` or `CustomState`
+    /// event. Basic events can't be redacted and are returned unchanged.
+    pub fn redact(&self, redaction: &RedactionEvent, version: &RoomVersionId) -> Self {
+        match *self {
+            Event::Direct(_)
+            | Event::Dummy(_)
+            | Event::ForwardedRoomKey(_)
+            | Event::FullyRead(_)
+            | Event::IgnoredUserList(_)
+            | Event::KeyVerificationAccept(_)
+            | Event::KeyVerificationCancel(_)
+            | Event::KeyVerificationKey(_)
+            | Event::KeyVerificationMac(_)
+            | Event::KeyVerificationRequest(_)
+            | Event::KeyVerificationStart(_)
+            | Event::Presence(_)
+            | Event::PushRules(_)
+            | Event::Receipt(_)
+            | Event::RoomKey(_)
+            | Event::RoomKeyRequest(_)
+            | Event::Tag(_)
+            | Event::Typing(_)
+            | Event::Custom(_) => self.clone(),
+            _ => parse_redacted(redact_serialized(self, redaction, version)),
+        }
+    }
+}
+
+impl RoomEvent {
+    /// The type of the event.
+    pub fn event_type(&self) -> EventType {
+        with_room_event!(self, event => event.event_type())
+    }
+
+    /// The unique identifier for the event.
+    pub fn event_id(&self) -> &EventId {
+        with_room_event!(self, event => event.event_id())
+    }
+
+    /// Timestamp (milliseconds since the UNIX epoch) on originating homeserver when the event was
+    /// sent.
+    pub fn origin_server_ts(&self) -> UInt {
+        with_room_event!(self, event => event.origin_server_ts())
+    }
+
+    /// The unique identifier for the room associated with the event.
+    pub fn room_id(&self) -> Option<&RoomId> {
+        with_room_event!(self, event => event.room_id())
+    }
+
+    /// The unique identifier for the user who sent the event.
+    pub fn sender(&self) -> &UserId {
+        with_room_event!(self, event => event.sender())
+    }
+
+    /// The state key of the event, if it is a state event.
+    pub fn state_key(&self) -> Option<&str> {
+        match *self {
+            RoomEvent::RoomAliases(ref event) => Some(event.state_key()),
+            RoomEvent::RoomAvatar(ref event) => Some(event.state_key()),
+            RoomEvent::RoomCanonicalAlias(ref event) => Some(event.state_key()),
+            RoomEvent::RoomCreate(ref event) => Some(event.state_key()),
+            RoomEvent::RoomEncryption(ref event) => Some(event.state_key()),
+            RoomEvent::RoomGuestAccess(ref event) => Some(event.state_key()),
+            RoomEvent::RoomHistoryVisibility(ref event) => Some(event.state_key()),
+            RoomEvent::RoomJoinRules(ref event) => Some(event.state_key()),
+            RoomEvent::RoomMember(ref event) => Some(event.state_key()),
+            RoomEvent::RoomName(ref event) => Some(event.state_key()),
+            RoomEvent::RoomPinnedEvents(ref event) => Some(event.state_key()),
+            RoomEvent::RoomPowerLevels(ref event) => Some(event.state_key()),
+            RoomEvent::RoomServerAcl(ref event) => Some(event.state_key()),
+            RoomEvent::RoomThirdPartyInvite(ref event) => Some(event.state_key()),
+            RoomEvent::RoomTombstone(ref event) => Some(event.state_key()),
+            RoomEvent::RoomTopic(ref event) => Some(event.state_key()),
+            RoomEvent::CustomState(ref event) => Some(event.state_key()),
+            _ => None,
+        }
+    }
+
+    /// Redacts the event in response to the given redaction event.
+    ///
+    /// See `Event::redact` for details.
+    pub fn redact(&self, redaction: &RedactionEvent, version: &RoomVersionId) -> Self {
+        parse_redacted(redact_serialized(self, redaction, version))
+    }
+
+    /// Redacts the JSON representation of a room event and parses the result.
+    pub(crate) fn from_redacted(
+        json: &Value,
+        redaction: &RedactionEvent,
+        version: &RoomVersionId,
+    ) -> Self {
+        parse_redacted(redact_for_client(json, redaction, version))
+    }
+}
+
+impl StateEvent {
+    /// The type of the event.
+    pub fn event_type(&self) -> EventType {
+        with_state_event!(self, event => event.event_type())
+    }
+
+    /// The unique identifier for the event.
+    pub fn event_id(&self) -> &EventId {
+        with_state_event!(self, event => event.event_id())
+    }
+
+    /// Timestamp (milliseconds since the UNIX epoch) on originating homeserver when the event was
+    /// sent.
+    pub fn origin_server_ts(&self) -> UInt {
+        with_state_event!(self, event => event.origin_server_ts())
+    }
+
+    /// The unique identifier for the room associated with the event.
+    pub fn room_id(&self) -> Option<&RoomId> {
+        with_state_event!(self, event => event.room_id())
+    }
+
+    /// The unique identifier for the user who sent the event.
+    pub fn sender(&self) -> &UserId {
+        with_state_event!(self, event => event.sender())
+    }
+
+    /// A key that determines which piece of room state the event represents.
+    pub fn state_key(&self) -> &str {
+        with_state_event!(self, event => event.state_key())
+    }
+
+    /// Redacts the event in response to the given redaction event.
+    ///
+    /// See `Event::redact` for details.
+    pub fn redact(&self, redaction: &RedactionEvent, version: &RoomVersionId) -> Self {
+        let json = redact_serialized(self, redaction, version).to_string();
+
+        json.parse().unwrap_or_else(|_| {
+            StateEvent::CustomState(
+                json.parse()
+                    .expect("redacted state events are valid custom state events"),
+            )
+        })
+    }
+}
+
+/// Serializes an event and redacts it for clients.
+fn redact_serialized<T>(event: &T, redaction: &RedactionEvent, version: &RoomVersionId) -> Value
+where
+    T: Serialize,
+{
+    let json = to_value(event).expect("events always serialize to JSON");
+
+    redact_for_client(&json, redaction, version)
+}
+
+/// Parses a redacted room event, falling back to a custom event if the redacted content is no
+/// longer valid for its type.
+fn parse_redacted<T>(mut json: Value) -> T
+where
+    T: FromStr + From<CustomRoomEvent> + From<CustomStateEvent>,
+{
+    let is_state = json.get("state_key").is_some();
+
+    if let Ok(event) = json.to_string().parse() {
+        return event;
+    }
+
+    // The custom event types read their type from an `event_type` field rather than `type`.
+    if let Some(object) = json.as_object_mut() {
+        if let Some(event_type) = object.remove("type") {
+            object.insert("event_type".to_string(), event_type);
+        }
+    }
+
+    let json = json.to_string();
+
+    if is_state {
+        T::from(
+            json.parse::<CustomStateEvent>()
+                .expect("redacted state events are valid custom state events"),
+        )
+    } else {
+        T::from(
+            json.parse::<CustomRoomEvent>()
+                .expect("redacted room events are valid custom room events"),
+        )
+    }
+}
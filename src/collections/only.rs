@@ -22,6 +22,7 @@ use crate::{
     },
     presence::PresenceEvent,
     push_rules::PushRulesEvent,
+    reaction::ReactionEvent,
     receipt::ReceiptEvent,
     room::{
         encrypted::EncryptedEvent,
@@ -114,6 +115,9 @@ pub enum RoomEvent {
     /// m.call.invite
     CallInvite(InviteEvent),
 
+    /// m.reaction
+    Reaction(ReactionEvent),
+
     /// m.room.encrypted
     RoomEncrypted(EncryptedEvent),
 
@@ -327,6 +331,7 @@ impl FromStr for Event {
             | EventType::CallCandidates
             | EventType::CallHangup
             | EventType::CallInvite
+            | EventType::Reaction
             | EventType::RoomAliases
             | EventType::RoomAvatar
             | EventType::RoomCanonicalAlias
@@ -368,6 +373,7 @@ impl Serialize for RoomEvent {
             RoomEvent::CallCandidates(ref event) => event.serialize(serializer),
             RoomEvent::CallHangup(ref event) => event.serialize(serializer),
             RoomEvent::CallInvite(ref event) => event.serialize(serializer),
+            RoomEvent::Reaction(ref event) => event.serialize(serializer),
             RoomEvent::RoomEncrypted(ref event) => event.serialize(serializer),
             RoomEvent::RoomMessage(ref event) => event.serialize(serializer),
             RoomEvent::RoomMessageFeedback(ref event) => event.serialize(serializer),
@@ -434,6 +440,13 @@ impl FromStr for RoomEvent {
                     message: error.to_string(),
                 })),
             },
+            EventType::Reaction => match json.parse() {
+                Ok(event) => Ok(RoomEvent::Reaction(event)),
+                Err(error) => Err(InvalidEvent(InnerInvalidEvent::Validation {
+                    json: value,
+                    message: error.to_string(),
+                })),
+            },
             EventType::RoomEncrypted => match json.parse() {
                 Ok(event) => Ok(RoomEvent::RoomEncrypted(event)),
                 Err(error) => Err(InvalidEvent(InnerInvalidEvent::Validation {
@@ -561,6 +574,7 @@ impl_from_t_for_room_event!(AnswerEvent, CallAnswer);
 impl_from_t_for_room_event!(CandidatesEvent, CallCandidates);
 impl_from_t_for_room_event!(HangupEvent, CallHangup);
 impl_from_t_for_room_event!(InviteEvent, CallInvite);
+impl_from_t_for_room_event!(ReactionEvent, Reaction);
 impl_from_t_for_room_event!(EncryptedEvent, RoomEncrypted);
 impl_from_t_for_room_event!(MessageEvent, RoomMessage);
 impl_from_t_for_room_event!(FeedbackEvent, RoomMessageFeedback);
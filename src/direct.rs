@@ -43,8 +43,7 @@ mod tests {
             to_string(&event).unwrap(),
             format!(
                 r#"{{"content":{{"{}":["{}"]}},"type":"m.direct"}}"#,
-                alice.to_string(),
-                room[0].to_string()
+                alice, room[0]
             )
         );
     }
@@ -52,7 +51,7 @@ mod tests {
     #[test]
     fn deserialization() {
         let alice = UserId::new("ruma.io").unwrap();
-        let rooms = vec![
+        let rooms = [
             RoomId::new("ruma.io").unwrap(),
             RoomId::new("ruma.io").unwrap(),
         ];
@@ -62,9 +61,7 @@ mod tests {
             "content": {{ "{}": ["{}", "{}"] }},
             "type": "m.direct"
         }}"#,
-            alice.to_string(),
-            rooms[0].to_string(),
-            rooms[1].to_string()
+            alice, rooms[0], rooms[1]
         );
 
         let event: DirectEvent = json_data.parse().unwrap();
@@ -0,0 +1,242 @@
+//! Computation of the [content hash](https://matrix.org/docs/spec/server_server/r0.1.3#calculating-the-content-hash-for-an-event)
+//! and the [reference hash](https://matrix.org/docs/spec/server_server/r0.1.3#calculating-the-reference-hash-for-an-event)
+//! of events, and of the event IDs derived from reference hashes in room versions 3 and later.
+//!
+//! The functions here accept any serializable event, but are meant for PDUs such as those in the
+//! `pdu` module, since the hashes cover the fields that only PDUs have. Since events in room
+//! versions 3 and later don't carry their event ID, any `event_id` is ignored when hashing them.
+//!
+//! This module is only available with the `hashes` feature.
+
+use std::convert::TryFrom;
+
+use ruma_identifiers::{EventId, RoomVersionId};
+use serde::Serialize;
+use serde_json::{to_value, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    canonical_json::to_canonical_json, pdu::EventHash, room::redaction::redact, InvalidInput,
+};
+
+/// Computes the content hash of an event.
+///
+/// This is the SHA-256 hash of the canonical JSON of the event without its `unsigned`,
+/// `signatures` and `hashes` keys, and is what goes into the `hashes` of a PDU.
+pub fn content_hash<T>(event: &T, version: &RoomVersionId) -> Result<EventHash, InvalidInput>
+where
+    T: Serialize,
+{
+    let mut json = to_json(event, version)?;
+
+    if let Value::Object(ref mut object) = json {
+        object.remove("unsigned");
+        object.remove("signatures");
+        object.remove("hashes");
+    }
+
+    Ok(EventHash {
+        sha256: base64::encode_config(sha256(&json)?, base64::STANDARD_NO_PAD),
+    })
+}
+
+/// Computes the reference hash of an event.
+///
+/// This is the SHA-256 hash of the canonical JSON of the redacted event without its `unsigned`
+/// and `signatures` keys. Room versions 1 and 2 use it to reference other events in
+/// `prev_events` and `auth_events`.
+pub fn reference_hash<T>(event: &T, version: &RoomVersionId) -> Result<EventHash, InvalidInput>
+where
+    T: Serialize,
+{
+    Ok(EventHash {
+        sha256: base64::encode_config(reference_sha256(event, version)?, base64::STANDARD_NO_PAD),
+    })
+}
+
+/// Computes the ID of an event in room versions 3 and later, which is derived from its reference
+/// hash.
+///
+/// Room version 3 encodes the hash with standard base64, and later room versions with URL-safe
+/// base64. Returns an error for room versions 1 and 2, where event IDs are chosen by the origin
+/// server, and for room versions this crate doesn't know about.
+pub fn reference_event_id<T>(event: &T, version: &RoomVersionId) -> Result<EventId, InvalidInput>
+where
+    T: Serialize,
+{
+    let config = match version_number(version) {
+        Some(3) => base64::STANDARD_NO_PAD,
+        Some(number) if number >= 4 => base64::URL_SAFE_NO_PAD,
+        _ => {
+            return Err(InvalidInput(format!(
+                "room version {} does not derive event IDs from reference hashes",
+                version
+            )));
+        }
+    };
+
+    let event_id = format!(
+        "${}",
+        base64::encode_config(reference_sha256(event, version)?, config)
+    );
+
+    EventId::try_from(event_id.as_str()).map_err(|error| InvalidInput(error.to_string()))
+}
+
+/// The SHA-256 hash the reference hash and event ID are made of.
+fn reference_sha256<T>(event: &T, version: &RoomVersionId) -> Result<Vec<u8>, InvalidInput>
+where
+    T: Serialize,
+{
+    let mut json = redact(&to_json(event, version)?, version);
+
+    if let Value::Object(ref mut object) = json {
+        object.remove("unsigned");
+        object.remove("signatures");
+    }
+
+    sha256(&json)
+}
+
+/// Serializes an event, removing its `event_id` in room versions that derive it from the
+/// reference hash.
+fn to_json<T>(event: &T, version: &RoomVersionId) -> Result<Value, InvalidInput>
+where
+    T: Serialize,
+{
+    let mut json = to_value(event).map_err(|error| InvalidInput(error.to_string()))?;
+
+    if version_number(version).map_or(false, |number| number >= 3) {
+        if let Value::Object(ref mut object) = json {
+            object.remove("event_id");
+        }
+    }
+
+    Ok(json)
+}
+
+/// The SHA-256 hash of the canonical JSON of a value.
+fn sha256(json: &Value) -> Result<Vec<u8>, InvalidInput> {
+    Ok(Sha256::digest(to_canonical_json(json)?.as_bytes()).to_vec())
+}
+
+/// The number of an official room version.
+fn version_number(version: &RoomVersionId) -> Option<u32> {
+    version.to_string().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ruma_identifiers::RoomVersionId;
+    use serde_json::json;
+
+    use super::{content_hash, reference_event_id, reference_hash};
+
+    #[test]
+    fn content_hashes() {
+        let version = RoomVersionId::version_1();
+
+        let minimal = json!({
+            "event_id": "$0:domain",
+            "origin": "domain",
+            "origin_server_ts": 1_000_000,
+            "signatures": {},
+            "type": "X",
+            "unsigned": {"age_ts": 1_000_000}
+        });
+
+        assert_eq!(
+            content_hash(&minimal, &version).unwrap().sha256,
+            "6tJjLpXtggfke8UxFhAKg82QVkJzvKOVOOSjUDK4ZSI"
+        );
+
+        let message = json!({
+            "content": {"body": "Here is the message content"},
+            "event_id": "$0:domain",
+            "origin": "domain",
+            "origin_server_ts": 1_000_000,
+            "type": "m.room.message",
+            "room_id": "!r:domain",
+            "sender": "@u:domain",
+            "signatures": {},
+            "unsigned": {"age_ts": 1_000_000}
+        });
+
+        assert_eq!(
+            content_hash(&message, &version).unwrap().sha256,
+            "onLKD1bGljeBWQhWZ1kaP9SorVmRQNdN5aM2JYU2n/g"
+        );
+    }
+
+    #[test]
+    fn reference_hashes_ignore_redacted_keys() {
+        let version = RoomVersionId::version_4();
+        let mut event = json!({
+            "auth_events": [],
+            "content": {"body": "hello", "msgtype": "m.text"},
+            "depth": 1,
+            "event_id": "$ignored",
+            "hashes": {"sha256": "abc"},
+            "origin": "example.com",
+            "origin_server_ts": 1,
+            "prev_events": [],
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "signatures": {"example.com": {"ed25519:0": "sig"}},
+            "type": "m.room.message",
+            "unsigned": {"age": 5}
+        });
+
+        let content = content_hash(&event, &version).unwrap();
+        let reference = reference_hash(&event, &version).unwrap();
+        let event_id = reference_event_id(&event, &version).unwrap();
+
+        assert_eq!(
+            event_id.to_string(),
+            format!("${}", reference.sha256.replace('+', "-").replace('/', "_"))
+        );
+
+        event["content"]["body"] = json!("changed");
+        event["event_id"] = json!("$other");
+        event["signatures"] = json!({});
+
+        assert_ne!(content_hash(&event, &version).unwrap(), content);
+        assert_eq!(reference_hash(&event, &version).unwrap(), reference);
+        assert_eq!(reference_event_id(&event, &version).unwrap(), event_id);
+
+        event["origin_server_ts"] = json!(2);
+
+        assert_ne!(reference_event_id(&event, &version).unwrap(), event_id);
+    }
+
+    #[test]
+    fn event_id_encoding() {
+        let event = json!({
+            "content": {},
+            "origin_server_ts": 3,
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "type": "m.room.message"
+        });
+
+        let v3 = reference_event_id(&event, &RoomVersionId::version_3()).unwrap();
+        let v4 = reference_event_id(&event, &RoomVersionId::version_4()).unwrap();
+        let v6 = reference_event_id(&event, &RoomVersionId::try_from("6").unwrap()).unwrap();
+
+        assert_eq!(
+            v3.to_string().replace('+', "-").replace('/', "_"),
+            v4.to_string()
+        );
+        assert_eq!(v4, v6);
+        assert!(!v4.to_string().contains(&['+', '/', '='][..]));
+
+        assert!(reference_event_id(&event, &RoomVersionId::version_2()).is_err());
+        assert!(reference_event_id(
+            &event,
+            &RoomVersionId::try_from("io.example.custom").unwrap()
+        )
+        .is_err());
+    }
+}
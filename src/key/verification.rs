@@ -4,11 +4,16 @@
 
 use serde::{Deserialize, Serialize};
 
+use self::emoji::{Emoji, EMOJI};
+
 pub mod accept;
 pub mod cancel;
+pub mod emoji;
 pub mod key;
 pub mod mac;
 pub mod request;
+#[cfg(feature = "crypto")]
+pub mod sas;
 pub mod start;
 
 /// A hash algorithm.
@@ -96,6 +101,50 @@ impl_enum! {
     }
 }
 
+impl ShortAuthenticationString {
+    /// Represents the six bytes of a short authentication string with this method.
+    pub fn represent(self, bytes: &[u8; 6]) -> SasRepresentation {
+        match self {
+            ShortAuthenticationString::Decimal => {
+                let bytes: Vec<u16> = bytes.iter().map(|byte| u16::from(*byte)).collect();
+
+                // The first 39 bits, split into three 13-bit numbers.
+                SasRepresentation::Decimal([
+                    (bytes[0] << 5 | bytes[1] >> 3) + 1000,
+                    ((bytes[1] & 0x7) << 10 | bytes[2] << 2 | bytes[3] >> 6) + 1000,
+                    ((bytes[3] & 0x3f) << 7 | bytes[4] >> 1) + 1000,
+                ])
+            }
+            ShortAuthenticationString::Emoji => {
+                let number = bytes
+                    .iter()
+                    .fold(0u64, |number, byte| number << 8 | u64::from(*byte));
+                let mut emoji = [&EMOJI[0]; 7];
+
+                // The first 42 bits, split into seven 6-bit numbers.
+                for (i, emoji) in emoji.iter_mut().enumerate() {
+                    *emoji = &EMOJI[(number >> (42 - 6 * i) & 0x3f) as usize];
+                }
+
+                SasRepresentation::Emoji(emoji)
+            }
+            ShortAuthenticationString::__Nonexhaustive => {
+                panic!("__Nonexhaustive enum variant is not intended for use.")
+            }
+        }
+    }
+}
+
+/// A short authentication string as shown to the user.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SasRepresentation {
+    /// Three numbers between 1000 and 9191, shown with the *decimal* method.
+    Decimal([u16; 3]),
+
+    /// Seven emoji, shown with the *emoji* method.
+    Emoji([&'static Emoji; 7]),
+}
+
 /// A Short Authentication String (SAS) verification method.
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum VerificationMethod {
@@ -115,3 +164,60 @@ impl_enum! {
         MSasV1 => "m.sas.v1",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{emoji::EMOJI, SasRepresentation, ShortAuthenticationString};
+
+    #[test]
+    fn decimal() {
+        let represent = |bytes| ShortAuthenticationString::Decimal.represent(bytes);
+
+        assert_eq!(
+            represent(&[0; 6]),
+            SasRepresentation::Decimal([1000, 1000, 1000])
+        );
+        assert_eq!(
+            represent(&[0xff; 6]),
+            SasRepresentation::Decimal([9191, 9191, 9191])
+        );
+        assert_eq!(
+            represent(&[0b1000_0000, 0b0000_0100, 0, 0b0100_0000, 0, 0]),
+            SasRepresentation::Decimal([5096, 5097, 1000])
+        );
+    }
+
+    #[test]
+    fn emoji() {
+        let represent = |bytes| ShortAuthenticationString::Emoji.represent(bytes);
+
+        assert_eq!(represent(&[0; 6]), SasRepresentation::Emoji([&EMOJI[0]; 7]));
+        assert_eq!(
+            represent(&[0xff; 6]),
+            SasRepresentation::Emoji([&EMOJI[63]; 7])
+        );
+
+        match represent(&[
+            0b0000_0100,
+            0b0010_0000,
+            0b1100_0100,
+            0b0001_0100,
+            0b0110_0001,
+            0b1100_0000,
+        ]) {
+            SasRepresentation::Emoji(emoji) => {
+                let descriptions: Vec<&str> = emoji.iter().map(|emoji| emoji.description).collect();
+
+                assert_eq!(
+                    descriptions,
+                    ["Cat", "Lion", "Horse", "Unicorn", "Pig", "Elephant", "Rabbit"]
+                );
+            }
+            SasRepresentation::Decimal(_) => panic!("expected emoji"),
+        }
+
+        assert_eq!(EMOJI[36].symbol, "\u{1F44D}");
+        assert_eq!(EMOJI[36].translation_key, "thumbs_up");
+        assert_eq!(EMOJI[63].description, "Pin");
+    }
+}
@@ -0,0 +1,340 @@
+//! The [emoji table](https://matrix.org/docs/spec/client_server/r0.6.0#sas-method-emoji) of
+//! the *emoji* short authentication string method.
+
+/// An emoji of a short authentication string.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Emoji {
+    /// The emoji itself.
+    pub symbol: &'static str,
+
+    /// The English description of the emoji, which clients should show next to it.
+    pub description: &'static str,
+
+    /// A key for looking up a translation of the description, which is the description in lower
+    /// snake case, e.g. `thumbs_up`.
+    pub translation_key: &'static str,
+}
+
+/// The 64 emoji a short authentication string is made of, indexed by their 6-bit number.
+pub const EMOJI: [Emoji; 64] = [
+    Emoji {
+        symbol: "\u{1F436}",
+        description: "Dog",
+        translation_key: "dog",
+    },
+    Emoji {
+        symbol: "\u{1F431}",
+        description: "Cat",
+        translation_key: "cat",
+    },
+    Emoji {
+        symbol: "\u{1F981}",
+        description: "Lion",
+        translation_key: "lion",
+    },
+    Emoji {
+        symbol: "\u{1F40E}",
+        description: "Horse",
+        translation_key: "horse",
+    },
+    Emoji {
+        symbol: "\u{1F984}",
+        description: "Unicorn",
+        translation_key: "unicorn",
+    },
+    Emoji {
+        symbol: "\u{1F437}",
+        description: "Pig",
+        translation_key: "pig",
+    },
+    Emoji {
+        symbol: "\u{1F418}",
+        description: "Elephant",
+        translation_key: "elephant",
+    },
+    Emoji {
+        symbol: "\u{1F430}",
+        description: "Rabbit",
+        translation_key: "rabbit",
+    },
+    Emoji {
+        symbol: "\u{1F43C}",
+        description: "Panda",
+        translation_key: "panda",
+    },
+    Emoji {
+        symbol: "\u{1F413}",
+        description: "Rooster",
+        translation_key: "rooster",
+    },
+    Emoji {
+        symbol: "\u{1F427}",
+        description: "Penguin",
+        translation_key: "penguin",
+    },
+    Emoji {
+        symbol: "\u{1F422}",
+        description: "Turtle",
+        translation_key: "turtle",
+    },
+    Emoji {
+        symbol: "\u{1F41F}",
+        description: "Fish",
+        translation_key: "fish",
+    },
+    Emoji {
+        symbol: "\u{1F419}",
+        description: "Octopus",
+        translation_key: "octopus",
+    },
+    Emoji {
+        symbol: "\u{1F98B}",
+        description: "Butterfly",
+        translation_key: "butterfly",
+    },
+    Emoji {
+        symbol: "\u{1F337}",
+        description: "Flower",
+        translation_key: "flower",
+    },
+    Emoji {
+        symbol: "\u{1F333}",
+        description: "Tree",
+        translation_key: "tree",
+    },
+    Emoji {
+        symbol: "\u{1F335}",
+        description: "Cactus",
+        translation_key: "cactus",
+    },
+    Emoji {
+        symbol: "\u{1F344}",
+        description: "Mushroom",
+        translation_key: "mushroom",
+    },
+    Emoji {
+        symbol: "\u{1F30F}",
+        description: "Globe",
+        translation_key: "globe",
+    },
+    Emoji {
+        symbol: "\u{1F319}",
+        description: "Moon",
+        translation_key: "moon",
+    },
+    Emoji {
+        symbol: "\u{2601}\u{FE0F}",
+        description: "Cloud",
+        translation_key: "cloud",
+    },
+    Emoji {
+        symbol: "\u{1F525}",
+        description: "Fire",
+        translation_key: "fire",
+    },
+    Emoji {
+        symbol: "\u{1F34C}",
+        description: "Banana",
+        translation_key: "banana",
+    },
+    Emoji {
+        symbol: "\u{1F34E}",
+        description: "Apple",
+        translation_key: "apple",
+    },
+    Emoji {
+        symbol: "\u{1F353}",
+        description: "Strawberry",
+        translation_key: "strawberry",
+    },
+    Emoji {
+        symbol: "\u{1F33D}",
+        description: "Corn",
+        translation_key: "corn",
+    },
+    Emoji {
+        symbol: "\u{1F355}",
+        description: "Pizza",
+        translation_key: "pizza",
+    },
+    Emoji {
+        symbol: "\u{1F382}",
+        description: "Cake",
+        translation_key: "cake",
+    },
+    Emoji {
+        symbol: "\u{2764}\u{FE0F}",
+        description: "Heart",
+        translation_key: "heart",
+    },
+    Emoji {
+        symbol: "\u{1F600}",
+        description: "Smiley",
+        translation_key: "smiley",
+    },
+    Emoji {
+        symbol: "\u{1F916}",
+        description: "Robot",
+        translation_key: "robot",
+    },
+    Emoji {
+        symbol: "\u{1F3A9}",
+        description: "Hat",
+        translation_key: "hat",
+    },
+    Emoji {
+        symbol: "\u{1F453}",
+        description: "Glasses",
+        translation_key: "glasses",
+    },
+    Emoji {
+        symbol: "\u{1F527}",
+        description: "Spanner",
+        translation_key: "spanner",
+    },
+    Emoji {
+        symbol: "\u{1F385}",
+        description: "Santa",
+        translation_key: "santa",
+    },
+    Emoji {
+        symbol: "\u{1F44D}",
+        description: "Thumbs Up",
+        translation_key: "thumbs_up",
+    },
+    Emoji {
+        symbol: "\u{2602}\u{FE0F}",
+        description: "Umbrella",
+        translation_key: "umbrella",
+    },
+    Emoji {
+        symbol: "\u{231B}",
+        description: "Hourglass",
+        translation_key: "hourglass",
+    },
+    Emoji {
+        symbol: "\u{23F0}",
+        description: "Clock",
+        translation_key: "clock",
+    },
+    Emoji {
+        symbol: "\u{1F381}",
+        description: "Gift",
+        translation_key: "gift",
+    },
+    Emoji {
+        symbol: "\u{1F4A1}",
+        description: "Light Bulb",
+        translation_key: "light_bulb",
+    },
+    Emoji {
+        symbol: "\u{1F4D5}",
+        description: "Book",
+        translation_key: "book",
+    },
+    Emoji {
+        symbol: "\u{270F}\u{FE0F}",
+        description: "Pencil",
+        translation_key: "pencil",
+    },
+    Emoji {
+        symbol: "\u{1F4CE}",
+        description: "Paperclip",
+        translation_key: "paperclip",
+    },
+    Emoji {
+        symbol: "\u{2702}\u{FE0F}",
+        description: "Scissors",
+        translation_key: "scissors",
+    },
+    Emoji {
+        symbol: "\u{1F512}",
+        description: "Lock",
+        translation_key: "lock",
+    },
+    Emoji {
+        symbol: "\u{1F511}",
+        description: "Key",
+        translation_key: "key",
+    },
+    Emoji {
+        symbol: "\u{1F528}",
+        description: "Hammer",
+        translation_key: "hammer",
+    },
+    Emoji {
+        symbol: "\u{260E}\u{FE0F}",
+        description: "Telephone",
+        translation_key: "telephone",
+    },
+    Emoji {
+        symbol: "\u{1F3C1}",
+        description: "Flag",
+        translation_key: "flag",
+    },
+    Emoji {
+        symbol: "\u{1F682}",
+        description: "Train",
+        translation_key: "train",
+    },
+    Emoji {
+        symbol: "\u{1F6B2}",
+        description: "Bicycle",
+        translation_key: "bicycle",
+    },
+    Emoji {
+        symbol: "\u{2708}\u{FE0F}",
+        description: "Aeroplane",
+        translation_key: "aeroplane",
+    },
+    Emoji {
+        symbol: "\u{1F680}",
+        description: "Rocket",
+        translation_key: "rocket",
+    },
+    Emoji {
+        symbol: "\u{1F3C6}",
+        description: "Trophy",
+        translation_key: "trophy",
+    },
+    Emoji {
+        symbol: "\u{26BD}",
+        description: "Ball",
+        translation_key: "ball",
+    },
+    Emoji {
+        symbol: "\u{1F3B8}",
+        description: "Guitar",
+        translation_key: "guitar",
+    },
+    Emoji {
+        symbol: "\u{1F3BA}",
+        description: "Trumpet",
+        translation_key: "trumpet",
+    },
+    Emoji {
+        symbol: "\u{1F514}",
+        description: "Bell",
+        translation_key: "bell",
+    },
+    Emoji {
+        symbol: "\u{2693}",
+        description: "Anchor",
+        translation_key: "anchor",
+    },
+    Emoji {
+        symbol: "\u{1F3A7}",
+        description: "Headphones",
+        translation_key: "headphones",
+    },
+    Emoji {
+        symbol: "\u{1F4C1}",
+        description: "Folder",
+        translation_key: "folder",
+    },
+    Emoji {
+        symbol: "\u{1F4CC}",
+        description: "Pin",
+        translation_key: "pin",
+    },
+];
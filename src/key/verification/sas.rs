@@ -0,0 +1,1049 @@
+//! A state machine for [SAS key verification](https://matrix.org/docs/spec/client_server/r0.6.0#short-authentication-string-sas-verification)
+//! with the *m.sas.v1* method.
+//!
+//! A `Sas` tracks one verification between the own device and another device. It consumes the
+//! *m.key.verification* events received from the other device and produces the ones to send back:
+//!
+//! 1. One device optionally sends an *m.key.verification.request* (`Sas::request`), which the other
+//!    device answers with an *m.key.verification.start* (`Sas::from_request`). Otherwise one device
+//!    sends the start event right away (`Sas::start`).
+//! 2. The device receiving the start event answers with an *m.key.verification.accept*
+//!    (`Sas::receive_start` or `Sas::from_start`), committing to its ephemeral key.
+//! 3. Both devices exchange their ephemeral keys in *m.key.verification.key* events
+//!    (`Sas::receive_accept` and `Sas::receive_key`), after which both can show the short
+//!    authentication string.
+//! 4. If the users confirm that the strings match (`Sas::confirm`), both devices send an
+//!    *m.key.verification.mac* with the MACs of their keys (`Sas::receive_mac`).
+//!
+//! Every step returns the *m.key.verification.cancel* content to send if the event violates the
+//! protocol, after which the verification is cancelled.
+//!
+//! This module is only available with the `crypto` feature.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use js_int::UInt;
+use rand::rngs::OsRng;
+use ruma_identifiers::{DeviceId, UserId};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use super::{
+    accept::AcceptEventContent,
+    cancel::{CancelCode, CancelEventContent},
+    key::KeyEventContent,
+    mac::MacEventContent,
+    request::RequestEventContent,
+    start::{MSasV1Content, MSasV1ContentOptions, StartEventContent},
+    HashAlgorithm, KeyAgreementProtocol, MessageAuthenticationCode, SasRepresentation,
+    ShortAuthenticationString, VerificationMethod,
+};
+use crate::canonical_json::to_canonical_json;
+
+/// How long a verification may go without receiving an event before it times out.
+pub const TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How far in the future the timestamp of an *m.key.verification.request* may be.
+const MAX_REQUEST_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// The short authentication string methods this module supports.
+const SAS_METHODS: [ShortAuthenticationString; 2] = [
+    ShortAuthenticationString::Decimal,
+    ShortAuthenticationString::Emoji,
+];
+
+/// A device taking part in a verification.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+    /// The ID of the user the device belongs to.
+    pub user_id: UserId,
+
+    /// The ID of the device.
+    pub device_id: DeviceId,
+
+    /// The keys to verify, as a map from key ID (e.g. `ed25519:DEVICEID`) to the key encoded as
+    /// unpadded base64.
+    ///
+    /// The keys of the own device are sent in the *m.key.verification.mac* event, and the keys of
+    /// the other device are the ones its MACs are checked against.
+    pub keys: HashMap<String, String>,
+}
+
+/// An SAS verification with another device.
+pub struct Sas {
+    /// The own device.
+    own: Device,
+
+    /// The device being verified.
+    other: Device,
+
+    /// The ID of the verification, which all of its events carry.
+    transaction_id: String,
+
+    /// The own ephemeral key, until it is used for the key agreement.
+    secret: Option<EphemeralSecret>,
+
+    /// The own ephemeral public key, encoded as unpadded base64.
+    public_key: String,
+
+    /// Whether the own device sent the *m.key.verification.start* event.
+    started: bool,
+
+    /// The *m.key.verification.start* event content, once sent or received.
+    start: Option<StartEventContent>,
+
+    /// The agreed upon short authentication string methods.
+    methods: Vec<ShortAuthenticationString>,
+
+    /// The result of the key agreement.
+    shared_secret: Option<[u8; 32]>,
+
+    /// The step the verification is at.
+    state: State,
+
+    /// When the verification was created or last received an event.
+    last_activity: SystemTime,
+}
+
+/// The steps of a verification.
+#[derive(Clone, Debug, PartialEq)]
+enum State {
+    /// An *m.key.verification.start* event is expected.
+    Requested,
+
+    /// The own device sent an *m.key.verification.start* and expects an
+    /// *m.key.verification.accept* event.
+    Started,
+
+    /// An *m.key.verification.key* event is expected. The commitment is only known to the device
+    /// that sent the start event.
+    Accepted { commitment: Option<String> },
+
+    /// The short authentication string is known, and the user's confirmation and an
+    /// *m.key.verification.mac* event are expected. `verified_keys` are the keys of the other
+    /// device whose MACs were verified, once the MAC event was received.
+    KeysExchanged {
+        confirmed: bool,
+        verified_keys: Option<Vec<String>>,
+    },
+
+    /// The other device was verified.
+    Done { verified_keys: Vec<String> },
+
+    /// The verification was cancelled by either device.
+    Cancelled(CancelCode),
+}
+
+impl Sas {
+    /// Requests a verification from another device.
+    ///
+    /// `now` is the time the request is sent at. The other device is expected to answer with an
+    /// *m.key.verification.start* event, which is handled by `receive_start`.
+    pub fn request(
+        own: Device,
+        other: Device,
+        transaction_id: String,
+        now: SystemTime,
+    ) -> (Self, RequestEventContent) {
+        let timestamp = now
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or(0);
+        let content = RequestEventContent {
+            from_device: own.device_id.clone(),
+            transaction_id: transaction_id.clone(),
+            methods: vec![VerificationMethod::MSasV1],
+            timestamp: u64::try_from(timestamp)
+                .ok()
+                .and_then(UInt::new)
+                .unwrap_or(UInt::MAX),
+        };
+
+        (
+            Self::new(own, other, transaction_id, State::Requested),
+            content,
+        )
+    }
+
+    /// Answers an *m.key.verification.request* event sent by `sender`.
+    ///
+    /// Fails if the request is older than `TIMEOUT` or more than five minutes in the future as
+    /// seen from `now`, doesn't come from the other device or doesn't offer the *m.sas.v1*
+    /// method.
+    pub fn from_request(
+        own: Device,
+        other: Device,
+        sender: &UserId,
+        request: &RequestEventContent,
+        now: SystemTime,
+    ) -> Result<(Self, StartEventContent), CancelEventContent> {
+        let transaction_id = request.transaction_id.clone();
+        let timestamp = UNIX_EPOCH + Duration::from_millis(u64::from(request.timestamp));
+
+        if *sender != other.user_id || request.from_device != other.device_id {
+            return Err(cancel_content(
+                transaction_id,
+                CancelCode::UserMismatch,
+                "the request does not come from the expected device",
+            ));
+        }
+
+        if timestamp + TIMEOUT < now || timestamp > now + MAX_REQUEST_SKEW {
+            return Err(cancel_content(
+                transaction_id,
+                CancelCode::Timeout,
+                "the request timestamp is too far from the current time",
+            ));
+        }
+
+        if !request.methods.contains(&VerificationMethod::MSasV1) {
+            return Err(cancel_content(
+                transaction_id,
+                CancelCode::UnknownMethod,
+                "the request does not offer the m.sas.v1 method",
+            ));
+        }
+
+        Ok(Self::start(own, other, transaction_id))
+    }
+
+    /// Starts a verification with another device.
+    ///
+    /// The other device is expected to answer with an *m.key.verification.accept* event, which is
+    /// handled by `receive_accept`.
+    pub fn start(own: Device, other: Device, transaction_id: String) -> (Self, StartEventContent) {
+        let content = StartEventContent::MSasV1(
+            MSasV1Content::new(MSasV1ContentOptions {
+                from_device: own.device_id.clone(),
+                transaction_id: transaction_id.clone(),
+                key_agreement_protocols: vec![KeyAgreementProtocol::Curve25519],
+                hashes: vec![HashAlgorithm::Sha256],
+                message_authentication_codes: vec![MessageAuthenticationCode::HkdfHmacSha256],
+                short_authentication_string: SAS_METHODS.to_vec(),
+            })
+            .expect("the supported methods are valid"),
+        );
+
+        let mut sas = Self::new(own, other, transaction_id, State::Started);
+        sas.started = true;
+        sas.start = Some(content.clone());
+
+        (sas, content)
+    }
+
+    /// Accepts an *m.key.verification.start* event sent by `sender` without a prior request.
+    pub fn from_start(
+        own: Device,
+        other: Device,
+        sender: &UserId,
+        start: &StartEventContent,
+    ) -> Result<(Self, AcceptEventContent), CancelEventContent> {
+        let transaction_id = match *start {
+            StartEventContent::MSasV1(ref content) => content.transaction_id.clone(),
+            StartEventContent::__Nonexhaustive => {
+                panic!("__Nonexhaustive enum variant is not intended for use.")
+            }
+        };
+
+        let mut sas = Self::new(own, other, transaction_id, State::Requested);
+        let accept = sas.receive_start(sender, start)?;
+
+        Ok((sas, accept))
+    }
+
+    /// Handles an *m.key.verification.start* event sent by `sender` in answer to a request,
+    /// returning the *m.key.verification.accept* content to send.
+    pub fn receive_start(
+        &mut self,
+        sender: &UserId,
+        start: &StartEventContent,
+    ) -> Result<AcceptEventContent, CancelEventContent> {
+        let content = match *start {
+            StartEventContent::MSasV1(ref content) => content,
+            StartEventContent::__Nonexhaustive => {
+                panic!("__Nonexhaustive enum variant is not intended for use.")
+            }
+        };
+
+        self.check(sender, &content.transaction_id)?;
+
+        if self.state != State::Requested {
+            return Err(self.unexpected("m.key.verification.start"));
+        }
+
+        if content.from_device != self.other.device_id {
+            return Err(self.fail(
+                CancelCode::UserMismatch,
+                "the start event does not come from the expected device",
+            ));
+        }
+
+        // The start event is validated on creation and parsing, so the other methods always
+        // include the ones this module supports.
+        self.methods = SAS_METHODS
+            .iter()
+            .filter(|method| content.short_authentication_string.contains(method))
+            .cloned()
+            .collect();
+
+        let commitment = match commitment(&self.public_key, start) {
+            Some(commitment) => commitment,
+            None => {
+                return Err(self.fail(
+                    CancelCode::InvalidMessage,
+                    "the start event can't be encoded as canonical JSON",
+                ))
+            }
+        };
+
+        self.start = Some(start.clone());
+        self.state = State::Accepted { commitment: None };
+        self.last_activity = SystemTime::now();
+
+        Ok(AcceptEventContent {
+            transaction_id: self.transaction_id.clone(),
+            method: VerificationMethod::MSasV1,
+            key_agreement_protocol: KeyAgreementProtocol::Curve25519,
+            hash: HashAlgorithm::Sha256,
+            message_authentication_code: MessageAuthenticationCode::HkdfHmacSha256,
+            short_authentication_string: self.methods.clone(),
+            commitment,
+        })
+    }
+
+    /// Handles an *m.key.verification.accept* event sent by `sender`, returning the
+    /// *m.key.verification.key* content to send.
+    pub fn receive_accept(
+        &mut self,
+        sender: &UserId,
+        accept: &AcceptEventContent,
+    ) -> Result<KeyEventContent, CancelEventContent> {
+        self.check(sender, &accept.transaction_id)?;
+
+        if self.state != State::Started {
+            return Err(self.unexpected("m.key.verification.accept"));
+        }
+
+        let offered = accept.method == VerificationMethod::MSasV1
+            && accept.key_agreement_protocol == KeyAgreementProtocol::Curve25519
+            && accept.hash == HashAlgorithm::Sha256
+            && accept.message_authentication_code == MessageAuthenticationCode::HkdfHmacSha256
+            && !accept.short_authentication_string.is_empty()
+            && accept
+                .short_authentication_string
+                .iter()
+                .all(|method| SAS_METHODS.contains(method));
+
+        if !offered {
+            return Err(self.fail(
+                CancelCode::UnknownMethod,
+                "the accept event chose a method that was not offered",
+            ));
+        }
+
+        self.methods = accept.short_authentication_string.clone();
+        self.state = State::Accepted {
+            commitment: Some(accept.commitment.clone()),
+        };
+        self.last_activity = SystemTime::now();
+
+        Ok(self.key_content())
+    }
+
+    /// Handles an *m.key.verification.key* event sent by `sender`.
+    ///
+    /// Returns the *m.key.verification.key* content to send in return if the own device accepted
+    /// the verification, and `None` if it started it. Afterwards, the short authentication string
+    /// can be shown to the user.
+    pub fn receive_key(
+        &mut self,
+        sender: &UserId,
+        key: &KeyEventContent,
+    ) -> Result<Option<KeyEventContent>, CancelEventContent> {
+        self.check(sender, &key.transaction_id)?;
+
+        let expected_commitment = match self.state {
+            State::Accepted { ref commitment } => commitment.clone(),
+            _ => return Err(self.unexpected("m.key.verification.key")),
+        };
+
+        if let Some(expected_commitment) = expected_commitment {
+            let start = self
+                .start
+                .as_ref()
+                .expect("started verifications have a start event");
+
+            if commitment(&key.key, start) != Some(expected_commitment) {
+                return Err(self.fail(
+                    CancelCode::KeyMismatch,
+                    "the key does not match the commitment",
+                ));
+            }
+        }
+
+        let public_key = match decode(&key.key).map(<[u8; 32]>::try_from) {
+            Some(Ok(public_key)) => PublicKey::from(public_key),
+            _ => return Err(self.fail(CancelCode::InvalidMessage, "the key is not valid")),
+        };
+
+        let secret = self
+            .secret
+            .take()
+            .expect("the key is only agreed upon once");
+
+        self.shared_secret = Some(secret.diffie_hellman(&public_key).to_bytes());
+        self.state = State::KeysExchanged {
+            confirmed: false,
+            verified_keys: None,
+        };
+        self.last_activity = SystemTime::now();
+
+        if self.started {
+            Ok(None)
+        } else {
+            Ok(Some(self.key_content()))
+        }
+    }
+
+    /// The six bytes of the short authentication string, once the keys have been exchanged.
+    pub fn sas_bytes(&self) -> Option<[u8; 6]> {
+        let shared_secret = self.shared_secret.as_ref()?;
+        let (starter, accepter) = if self.started {
+            (&self.own, &self.other)
+        } else {
+            (&self.other, &self.own)
+        };
+        let info = format!(
+            "MATRIX_KEY_VERIFICATION_SAS{}{}{}{}{}",
+            starter.user_id,
+            starter.device_id,
+            accepter.user_id,
+            accepter.device_id,
+            self.transaction_id
+        );
+
+        let mut bytes = [0; 6];
+        Hkdf::<Sha256>::new(None, shared_secret)
+            .expand(info.as_bytes(), &mut bytes)
+            .expect("6 bytes is a valid HKDF-SHA-256 output length");
+
+        Some(bytes)
+    }
+
+    /// The short authentication string methods both devices support, once the verification was
+    /// accepted.
+    pub fn methods(&self) -> &[ShortAuthenticationString] {
+        &self.methods
+    }
+
+    /// The short authentication string as shown with `method`, once the keys have been exchanged
+    /// and if both devices support the method.
+    pub fn short_authentication_string(
+        &self,
+        method: ShortAuthenticationString,
+    ) -> Option<SasRepresentation> {
+        if self.methods.contains(&method) {
+            self.sas_bytes().map(|bytes| method.represent(&bytes))
+        } else {
+            None
+        }
+    }
+
+    /// Confirms that the user saw the same short authentication string on both devices, returning
+    /// the *m.key.verification.mac* content to send.
+    pub fn confirm(&mut self) -> Result<MacEventContent, CancelEventContent> {
+        let verified_keys = match self.state {
+            State::KeysExchanged {
+                confirmed: false,
+                ref mut verified_keys,
+            } => verified_keys.take(),
+            _ => return Err(self.unexpected("confirmation")),
+        };
+
+        let mut mac = HashMap::new();
+
+        for (key_id, key) in &self.own.keys {
+            mac.insert(
+                key_id.clone(),
+                self.mac(&self.own, &self.other, key_id, key),
+            );
+        }
+
+        let mut key_ids: Vec<&str> = self.own.keys.keys().map(String::as_str).collect();
+        key_ids.sort_unstable();
+
+        let keys = self.mac(&self.own, &self.other, "KEY_IDS", &key_ids.join(","));
+
+        self.state = match verified_keys {
+            Some(verified_keys) => State::Done { verified_keys },
+            None => State::KeysExchanged {
+                confirmed: true,
+                verified_keys: None,
+            },
+        };
+
+        Ok(MacEventContent {
+            transaction_id: self.transaction_id.clone(),
+            mac,
+            keys,
+        })
+    }
+
+    /// Cancels the verification because the user saw different short authentication strings,
+    /// returning the *m.key.verification.cancel* content to send.
+    pub fn mismatch(&mut self) -> CancelEventContent {
+        self.fail(
+            CancelCode::KeyMismatch,
+            "the short authentication strings did not match",
+        )
+    }
+
+    /// Handles an *m.key.verification.mac* event sent by `sender`.
+    ///
+    /// MACs of keys that are not among the other device's keys are ignored, but at least one of
+    /// the other device's keys has to be verified.
+    pub fn receive_mac(
+        &mut self,
+        sender: &UserId,
+        mac: &MacEventContent,
+    ) -> Result<(), CancelEventContent> {
+        self.check(sender, &mac.transaction_id)?;
+
+        let confirmed = match self.state {
+            State::KeysExchanged {
+                confirmed,
+                verified_keys: None,
+            } => confirmed,
+            _ => return Err(self.unexpected("m.key.verification.mac")),
+        };
+
+        let mut key_ids: Vec<&str> = mac.mac.keys().map(String::as_str).collect();
+        key_ids.sort_unstable();
+
+        if self.mac(&self.other, &self.own, "KEY_IDS", &key_ids.join(",")) != mac.keys {
+            return Err(self.fail(CancelCode::KeyMismatch, "the MAC of the key IDs is invalid"));
+        }
+
+        let mut verified_keys = Vec::new();
+
+        for (key_id, key_mac) in &mac.mac {
+            if let Some(key) = self.other.keys.get(key_id) {
+                if self.mac(&self.other, &self.own, key_id, key) != *key_mac {
+                    return Err(self.fail(
+                        CancelCode::KeyMismatch,
+                        &format!("the MAC of the key {} is invalid", key_id),
+                    ));
+                }
+
+                verified_keys.push(key_id.clone());
+            }
+        }
+
+        if verified_keys.is_empty() {
+            return Err(self.fail(CancelCode::KeyMismatch, "none of the known keys were sent"));
+        }
+
+        verified_keys.sort_unstable();
+
+        self.state = if confirmed {
+            State::Done { verified_keys }
+        } else {
+            State::KeysExchanged {
+                confirmed,
+                verified_keys: Some(verified_keys),
+            }
+        };
+        self.last_activity = SystemTime::now();
+
+        Ok(())
+    }
+
+    /// Handles an *m.key.verification.cancel* event sent by `sender`.
+    ///
+    /// Cancel events of other verifications or from other users are ignored.
+    pub fn receive_cancel(&mut self, sender: &UserId, cancel: &CancelEventContent) {
+        if cancel.transaction_id == self.transaction_id
+            && *sender == self.other.user_id
+            && !self.is_finished()
+        {
+            self.state = State::Cancelled(cancel.code.clone());
+        }
+    }
+
+    /// Cancels the verification on behalf of the user, returning the
+    /// *m.key.verification.cancel* content to send.
+    pub fn cancel(&mut self) -> CancelEventContent {
+        self.fail(CancelCode::User, "the user cancelled the verification")
+    }
+
+    /// Cancels the verification if it didn't receive an event for `TIMEOUT` as seen from `now`,
+    /// returning the *m.key.verification.cancel* content to send.
+    pub fn check_timeout(&mut self, now: SystemTime) -> Option<CancelEventContent> {
+        if !self.is_finished() && self.last_activity + TIMEOUT <= now {
+            Some(self.fail(CancelCode::Timeout, "the verification timed out"))
+        } else {
+            None
+        }
+    }
+
+    /// The ID of the verification.
+    pub fn transaction_id(&self) -> &str {
+        &self.transaction_id
+    }
+
+    /// Whether the other device was verified.
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done { .. })
+    }
+
+    /// The reason the verification was cancelled, if it was.
+    pub fn cancel_code(&self) -> Option<&CancelCode> {
+        match self.state {
+            State::Cancelled(ref code) => Some(code),
+            _ => None,
+        }
+    }
+
+    /// The IDs of the other device's keys that were verified, once the verification is done.
+    pub fn verified_keys(&self) -> Option<&[String]> {
+        match self.state {
+            State::Done { ref verified_keys } => Some(verified_keys),
+            _ => None,
+        }
+    }
+
+    /// Creates a verification with a new ephemeral key.
+    fn new(own: Device, other: Device, transaction_id: String, state: State) -> Self {
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let public_key = encode(PublicKey::from(&secret).as_bytes());
+
+        Self {
+            own,
+            other,
+            transaction_id,
+            secret: Some(secret),
+            public_key,
+            started: false,
+            start: None,
+            methods: Vec::new(),
+            shared_secret: None,
+            state,
+            last_activity: SystemTime::now(),
+        }
+    }
+
+    /// Whether the verification is done or cancelled.
+    fn is_finished(&self) -> bool {
+        matches!(self.state, State::Done { .. } | State::Cancelled(_))
+    }
+
+    /// Fails if an event doesn't belong to the verification.
+    ///
+    /// Events of other verifications are answered with `CancelCode::UnknownTransaction` without
+    /// cancelling this one, as are events after the verification finished.
+    fn check(&mut self, sender: &UserId, transaction_id: &str) -> Result<(), CancelEventContent> {
+        if transaction_id != self.transaction_id {
+            return Err(cancel_content(
+                transaction_id.to_string(),
+                CancelCode::UnknownTransaction,
+                "the transaction is not known",
+            ));
+        }
+
+        if self.is_finished() {
+            return Err(cancel_content(
+                transaction_id.to_string(),
+                CancelCode::UnexpectedMessage,
+                "the verification is already finished",
+            ));
+        }
+
+        if *sender != self.other.user_id {
+            return Err(self.fail(
+                CancelCode::UserMismatch,
+                "the event does not come from the expected user",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Cancels the verification because of an unexpected event.
+    fn unexpected(&mut self, event: &str) -> CancelEventContent {
+        self.fail(
+            CancelCode::UnexpectedMessage,
+            &format!("unexpected {}", event),
+        )
+    }
+
+    /// Cancels the verification, returning the *m.key.verification.cancel* content to send.
+    fn fail(&mut self, code: CancelCode, reason: &str) -> CancelEventContent {
+        self.secret = None;
+        self.shared_secret = None;
+
+        if !self.is_finished() {
+            self.state = State::Cancelled(code.clone());
+        }
+
+        cancel_content(self.transaction_id.clone(), code, reason)
+    }
+
+    /// The *m.key.verification.key* content with the own ephemeral public key.
+    fn key_content(&self) -> KeyEventContent {
+        KeyEventContent {
+            transaction_id: self.transaction_id.clone(),
+            key: self.public_key.clone(),
+        }
+    }
+
+    /// Computes the *hkdf-hmac-sha256* MAC of a key sent by `sender` to `receiver`.
+    fn mac(&self, sender: &Device, receiver: &Device, key_id: &str, key: &str) -> String {
+        let shared_secret = self
+            .shared_secret
+            .as_ref()
+            .expect("MACs are only computed after the key agreement");
+        let info = format!(
+            "MATRIX_KEY_VERIFICATION_MAC{}{}{}{}{}{}",
+            sender.user_id,
+            sender.device_id,
+            receiver.user_id,
+            receiver.device_id,
+            self.transaction_id,
+            key_id
+        );
+
+        let mut mac_key = [0; 32];
+        Hkdf::<Sha256>::new(None, shared_secret)
+            .expand(info.as_bytes(), &mut mac_key)
+            .expect("32 bytes is a valid HKDF-SHA-256 output length");
+
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&mac_key).expect("HMAC accepts any key length");
+        mac.update(key.as_bytes());
+
+        encode(&mac.finalize().into_bytes())
+    }
+}
+
+impl Debug for Sas {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("Sas")
+            .field("own", &self.own)
+            .field("other", &self.other)
+            .field("transaction_id", &self.transaction_id)
+            .field("started", &self.started)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/// The commitment to an ephemeral public key, which is the SHA-256 hash of the key followed by
+/// the canonical JSON of the *m.key.verification.start* content.
+fn commitment(public_key: &str, start: &StartEventContent) -> Option<String> {
+    let start = to_canonical_json(start).ok()?;
+
+    Some(encode(&Sha256::digest(
+        format!("{}{}", public_key, start).as_bytes(),
+    )))
+}
+
+/// The content of an *m.key.verification.cancel* event.
+fn cancel_content(transaction_id: String, code: CancelCode, reason: &str) -> CancelEventContent {
+    CancelEventContent {
+        transaction_id,
+        reason: reason.to_string(),
+        code,
+    }
+}
+
+/// Encodes bytes as unpadded base64.
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::STANDARD_NO_PAD)
+}
+
+/// Decodes padded or unpadded base64.
+fn decode(encoded: &str) -> Option<Vec<u8>> {
+    base64::decode_config(encoded.trim_end_matches('='), base64::STANDARD_NO_PAD).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        convert::TryFrom,
+        time::{Duration, SystemTime},
+    };
+
+    use ruma_identifiers::UserId;
+
+    use super::{Device, Sas, TIMEOUT};
+    use crate::key::verification::{
+        accept::AcceptEventContent, cancel::CancelCode, ShortAuthenticationString,
+        VerificationMethod,
+    };
+
+    fn device(user_id: &str, device_id: &str, key: &str) -> Device {
+        let mut keys = HashMap::new();
+        keys.insert(format!("ed25519:{}", device_id), key.to_string());
+
+        Device {
+            user_id: UserId::try_from(user_id).unwrap(),
+            device_id: device_id.to_string(),
+            keys,
+        }
+    }
+
+    fn alice_device() -> Device {
+        device("@alice:example.com", "ALICEDEVICE", "alice+ed25519+key")
+    }
+
+    fn bob_device() -> Device {
+        device("@bob:example.com", "BOBDEVICE", "bob+ed25519+key")
+    }
+
+    /// Runs a verification started by Alice up to the key exchange.
+    fn exchange_keys() -> (Sas, Sas) {
+        let (alice_id, bob_id) = (alice_device().user_id, bob_device().user_id);
+
+        let (mut alice, start) = Sas::start(alice_device(), bob_device(), "txn".to_string());
+        let (mut bob, accept) =
+            Sas::from_start(bob_device(), alice_device(), &alice_id, &start).unwrap();
+        let key = alice.receive_accept(&bob_id, &accept).unwrap();
+        let key = bob.receive_key(&alice_id, &key).unwrap().unwrap();
+
+        assert_eq!(alice.receive_key(&bob_id, &key).unwrap(), None);
+
+        (alice, bob)
+    }
+
+    #[test]
+    fn requested_verification() {
+        let (alice_id, bob_id) = (alice_device().user_id, bob_device().user_id);
+        let now = SystemTime::now();
+
+        let (mut alice, request) =
+            Sas::request(alice_device(), bob_device(), "txn".to_string(), now);
+        let (mut bob, start) =
+            Sas::from_request(bob_device(), alice_device(), &alice_id, &request, now).unwrap();
+        let accept = alice.receive_start(&bob_id, &start).unwrap();
+
+        assert_eq!(
+            accept.short_authentication_string,
+            vec![
+                ShortAuthenticationString::Decimal,
+                ShortAuthenticationString::Emoji
+            ]
+        );
+
+        let key = bob.receive_accept(&alice_id, &accept).unwrap();
+        let key = alice.receive_key(&bob_id, &key).unwrap().unwrap();
+
+        assert_eq!(bob.receive_key(&alice_id, &key).unwrap(), None);
+        for &method in &[
+            ShortAuthenticationString::Decimal,
+            ShortAuthenticationString::Emoji,
+        ] {
+            assert!(alice.short_authentication_string(method).is_some());
+            assert_eq!(
+                alice.short_authentication_string(method),
+                bob.short_authentication_string(method)
+            );
+        }
+
+        let bob_mac = bob.confirm().unwrap();
+        alice.receive_mac(&bob_id, &bob_mac).unwrap();
+
+        assert!(!alice.is_done());
+
+        let alice_mac = alice.confirm().unwrap();
+
+        assert!(alice.is_done());
+        assert_eq!(
+            alice.verified_keys().unwrap(),
+            &["ed25519:BOBDEVICE".to_string()]
+        );
+
+        bob.receive_mac(&alice_id, &alice_mac).unwrap();
+
+        assert!(bob.is_done());
+        assert_eq!(
+            bob.verified_keys().unwrap(),
+            &["ed25519:ALICEDEVICE".to_string()]
+        );
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let alice_id = alice_device().user_id;
+        let now = SystemTime::now();
+
+        let (_, request) = Sas::request(
+            alice_device(),
+            bob_device(),
+            "txn".to_string(),
+            now - TIMEOUT * 2,
+        );
+        let cancel =
+            Sas::from_request(bob_device(), alice_device(), &alice_id, &request, now).unwrap_err();
+
+        assert_eq!(cancel.code, CancelCode::Timeout);
+        assert_eq!(cancel.transaction_id, "txn");
+
+        let (_, mut request) = Sas::request(alice_device(), bob_device(), "txn".to_string(), now);
+        request.methods = vec![VerificationMethod::__Nonexhaustive];
+
+        assert_eq!(
+            Sas::from_request(bob_device(), alice_device(), &alice_id, &request, now)
+                .unwrap_err()
+                .code,
+            CancelCode::UnknownMethod
+        );
+
+        request.methods = vec![VerificationMethod::MSasV1];
+
+        assert_eq!(
+            Sas::from_request(
+                bob_device(),
+                alice_device(),
+                &bob_device().user_id,
+                &request,
+                now
+            )
+            .unwrap_err()
+            .code,
+            CancelCode::UserMismatch
+        );
+    }
+
+    #[test]
+    fn unexpected_events() {
+        let (alice_id, bob_id) = (alice_device().user_id, bob_device().user_id);
+
+        let (mut alice, start) = Sas::start(alice_device(), bob_device(), "txn".to_string());
+        let (mut bob, accept) =
+            Sas::from_start(bob_device(), alice_device(), &alice_id, &start).unwrap();
+
+        let mut other_accept = accept.clone();
+        other_accept.transaction_id = "other".to_string();
+
+        let cancel = alice.receive_accept(&bob_id, &other_accept).unwrap_err();
+
+        assert_eq!(cancel.code, CancelCode::UnknownTransaction);
+        assert_eq!(cancel.transaction_id, "other");
+        assert_eq!(alice.cancel_code(), None);
+
+        let cancel = alice.receive_start(&bob_id, &start).unwrap_err();
+
+        assert_eq!(cancel.code, CancelCode::UnexpectedMessage);
+        assert_eq!(alice.cancel_code(), Some(&CancelCode::UnexpectedMessage));
+
+        bob.receive_cancel(&alice_id, &cancel);
+
+        assert_eq!(bob.cancel_code(), Some(&CancelCode::UnexpectedMessage));
+        assert_eq!(
+            bob.receive_accept(&alice_id, &accept).unwrap_err().code,
+            CancelCode::UnexpectedMessage
+        );
+
+        let (mut alice, start) = Sas::start(alice_device(), bob_device(), "txn".to_string());
+        let (_, accept) = Sas::from_start(bob_device(), alice_device(), &alice_id, &start).unwrap();
+
+        assert_eq!(
+            alice.receive_accept(&alice_id, &accept).unwrap_err().code,
+            CancelCode::UserMismatch
+        );
+    }
+
+    #[test]
+    fn unknown_methods() {
+        let (alice_id, bob_id) = (alice_device().user_id, bob_device().user_id);
+
+        let (mut alice, start) = Sas::start(alice_device(), bob_device(), "txn".to_string());
+        let (_, accept) = Sas::from_start(bob_device(), alice_device(), &alice_id, &start).unwrap();
+        let accept = AcceptEventContent {
+            short_authentication_string: Vec::new(),
+            ..accept
+        };
+
+        assert_eq!(
+            alice.receive_accept(&bob_id, &accept).unwrap_err().code,
+            CancelCode::UnknownMethod
+        );
+    }
+
+    #[test]
+    fn commitment_mismatch() {
+        let (alice_id, bob_id) = (alice_device().user_id, bob_device().user_id);
+
+        let (mut alice, start) = Sas::start(alice_device(), bob_device(), "txn".to_string());
+        let (_, accept) = Sas::from_start(bob_device(), alice_device(), &alice_id, &start).unwrap();
+        let (mut mallory, _) =
+            Sas::from_start(bob_device(), alice_device(), &alice_id, &start).unwrap();
+
+        let key = alice.receive_accept(&bob_id, &accept).unwrap();
+        let key = mallory.receive_key(&alice_id, &key).unwrap().unwrap();
+
+        assert_eq!(
+            alice.receive_key(&bob_id, &key).unwrap_err().code,
+            CancelCode::KeyMismatch
+        );
+    }
+
+    #[test]
+    fn mac_mismatch() {
+        let (alice_id, bob_id) = (alice_device().user_id, bob_device().user_id);
+
+        let (mut alice, mut bob) = exchange_keys();
+        let mut mac = bob.confirm().unwrap();
+        mac.mac
+            .insert("ed25519:BOBDEVICE".to_string(), "invalid".to_string());
+
+        assert_eq!(
+            alice.receive_mac(&bob_id, &mac).unwrap_err().code,
+            CancelCode::KeyMismatch
+        );
+
+        let (mut alice, mut bob) = exchange_keys();
+        let mut mac = bob.confirm().unwrap();
+        mac.keys = alice.confirm().unwrap().keys;
+
+        assert_eq!(
+            alice.receive_mac(&bob_id, &mac).unwrap_err().code,
+            CancelCode::KeyMismatch
+        );
+
+        let (mut alice, mut bob) = exchange_keys();
+
+        assert_eq!(alice.mismatch().code, CancelCode::KeyMismatch);
+        assert!(alice.confirm().is_err());
+        assert!(bob.confirm().is_ok());
+        assert!(!bob.is_done());
+
+        bob.receive_cancel(&alice_id, &alice.mismatch());
+
+        assert_eq!(bob.cancel_code(), Some(&CancelCode::KeyMismatch));
+    }
+
+    #[test]
+    fn timeout() {
+        let (mut alice, _) = Sas::start(alice_device(), bob_device(), "txn".to_string());
+        let now = SystemTime::now();
+
+        assert_eq!(alice.check_timeout(now), None);
+        assert_eq!(
+            alice
+                .check_timeout(now + TIMEOUT + Duration::from_secs(1))
+                .unwrap()
+                .code,
+            CancelCode::Timeout
+        );
+        assert_eq!(alice.cancel_code(), Some(&CancelCode::Timeout));
+    }
+}
@@ -121,7 +121,7 @@ use std::{
 };
 
 use js_int::UInt;
-use ruma_identifiers::{EventId, RoomId, UserId};
+use ruma_identifiers::{EventId, RoomId, RoomVersionId, UserId};
 use serde::{
     de::{Error as SerdeError, IntoDeserializer, MapAccess, Visitor},
     ser::SerializeMap,
@@ -132,11 +132,14 @@ use serde_json::Value;
 pub use custom::CustomEvent;
 pub use custom_room::CustomRoomEvent;
 pub use custom_state::CustomStateEvent;
+pub use unsigned::UnsignedData;
 
 #[macro_use]
 mod macros;
 
+pub mod auth;
 pub mod call;
+pub mod canonical_json;
 /// Enums for heterogeneous collections of events.
 pub mod collections {
     pub mod all;
@@ -146,19 +149,31 @@ pub mod direct;
 pub mod dummy;
 pub mod forwarded_room_key;
 pub mod fully_read;
+#[cfg(feature = "hashes")]
+pub mod hashes;
 pub mod ignored_user_list;
 pub mod key;
+#[cfg(feature = "crypto")]
+pub mod megolm;
+pub mod pdu;
 pub mod presence;
 pub mod push_rules;
+pub mod reaction;
 pub mod receipt;
 pub mod room;
 pub mod room_key;
 pub mod room_key_request;
+#[cfg(feature = "signatures")]
+pub mod signatures;
+pub mod state;
+pub mod state_res;
 pub mod sticker;
 pub mod stripped;
 pub mod tag;
 pub mod typing;
 
+mod unsigned;
+
 /// An event that is malformed or otherwise invalid.
 ///
 /// When attempting to create an event from a string of JSON data, an error in the input data may
@@ -347,6 +362,9 @@ pub enum EventType {
     /// m.push_rules
     PushRules,
 
+    /// m.reaction
+    Reaction,
+
     /// m.receipt
     Receipt,
 
@@ -469,6 +487,19 @@ pub trait RoomEvent: Event {
 
     /// Additional key-value pairs not signed by the homeserver.
     fn unsigned(&self) -> Option<&Value>;
+
+    /// Redacts the event in response to the given redaction event.
+    ///
+    /// See `collections::all::Event::redact` for details.
+    fn redact(
+        &self,
+        redaction: &room::redaction::RedactionEvent,
+        version: &RoomVersionId,
+    ) -> collections::all::RoomEvent {
+        let json = serde_json::to_value(self).expect("events always serialize to JSON");
+
+        collections::all::RoomEvent::from_redacted(&json, redaction, version)
+    }
 }
 
 /// An event that describes persistent state about a room.
@@ -551,6 +582,7 @@ impl Display for EventType {
             EventType::IgnoredUserList => "m.ignored_user_list",
             EventType::Presence => "m.presence",
             EventType::PushRules => "m.push_rules",
+            EventType::Reaction => "m.reaction",
             EventType::Receipt => "m.receipt",
             EventType::RoomAliases => "m.room.aliases",
             EventType::RoomAvatar => "m.room.avatar",
@@ -607,6 +639,7 @@ impl<'a> From<&'a str> for EventType {
             "m.ignored_user_list" => EventType::IgnoredUserList,
             "m.presence" => EventType::Presence,
             "m.push_rules" => EventType::PushRules,
+            "m.reaction" => EventType::Reaction,
             "m.receipt" => EventType::Receipt,
             "m.room.aliases" => EventType::RoomAliases,
             "m.room.avatar" => EventType::RoomAvatar,
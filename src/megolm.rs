@@ -0,0 +1,790 @@
+//! [Megolm](https://gitlab.matrix.org/matrix-org/olm/-/blob/master/docs/megolm.md) group sessions,
+//! which encrypt *m.room.encrypted* events with the *m.megolm.v1.aes-sha2* algorithm.
+//!
+//! A sender encrypts with an `OutboundGroupSession` and shares its key with the other devices in
+//! the room in an *m.room_key* event. Each receiving device creates an `InboundGroupSession` from
+//! that key to decrypt the messages. Inbound sessions can be forwarded to other devices in
+//! *m.forwarded_room_key* events.
+//!
+//! This module is only available with the `crypto` feature.
+
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+};
+
+use aes::{
+    cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit},
+    Aes256,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use ruma_identifiers::{DeviceId, RoomId};
+use sha2::Sha256;
+
+use crate::{
+    forwarded_room_key::ForwardedRoomKeyEventContent,
+    room::encrypted::{MegolmV1AesSha2Content, MegolmV1AesSha2Plaintext},
+    room_key::RoomKeyEventContent,
+    Algorithm,
+};
+
+/// The version byte of Megolm messages.
+const MESSAGE_VERSION: u8 = 3;
+
+/// The version byte of session keys shared in *m.room_key* events, which are signed.
+const SESSION_KEY_VERSION: u8 = 2;
+
+/// The version byte of exported session keys, which are not signed.
+const EXPORT_VERSION: u8 = 1;
+
+/// The length of the truncated MAC of a message.
+const MAC_LENGTH: usize = 8;
+
+/// The length of an Ed25519 signature.
+const SIGNATURE_LENGTH: usize = 64;
+
+/// The length of the ratchet, which consists of four 32-byte parts.
+const RATCHET_LENGTH: usize = 128;
+
+/// The reason a Megolm operation failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MegolmError(String);
+
+impl MegolmError {
+    /// A message describing the failure.
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for MegolmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for MegolmError {}
+
+/// A session for encrypting messages to a room.
+pub struct OutboundGroupSession {
+    /// The ratchet at the index of the next message.
+    ratchet: Ratchet,
+
+    /// The key messages are signed with.
+    signing_key: SigningKey,
+}
+
+impl OutboundGroupSession {
+    /// Creates a session with a random ratchet and signing key.
+    pub fn new() -> Self {
+        let mut ratchet = [0; RATCHET_LENGTH];
+        let mut seed = [0; 32];
+
+        OsRng.fill_bytes(&mut ratchet);
+        OsRng.fill_bytes(&mut seed);
+
+        Self {
+            ratchet: Ratchet::from_bytes(&ratchet, 0),
+            signing_key: SigningKey::from_bytes(&seed),
+        }
+    }
+
+    /// The ID of the session, which is its public signing key encoded as unpadded base64.
+    pub fn session_id(&self) -> String {
+        encode(self.signing_key.verifying_key().as_bytes())
+    }
+
+    /// The index of the next message.
+    pub fn message_index(&self) -> u32 {
+        self.ratchet.counter
+    }
+
+    /// The signed session key at the current message index, encoded as unpadded base64.
+    ///
+    /// Messages encrypted from now on can be decrypted with an `InboundGroupSession` created from
+    /// this key.
+    pub fn session_key(&self) -> String {
+        let mut key = Vec::with_capacity(1 + 4 + RATCHET_LENGTH + 32 + SIGNATURE_LENGTH);
+
+        key.push(SESSION_KEY_VERSION);
+        key.extend_from_slice(&self.ratchet.counter.to_be_bytes());
+        key.extend_from_slice(&self.ratchet.to_bytes());
+        key.extend_from_slice(self.signing_key.verifying_key().as_bytes());
+
+        let signature = self.signing_key.sign(&key);
+        key.extend_from_slice(&signature.to_bytes());
+
+        encode(&key)
+    }
+
+    /// The content of an *m.room_key* event sharing the session key.
+    pub fn room_key(&self, room_id: RoomId) -> RoomKeyEventContent {
+        RoomKeyEventContent {
+            algorithm: Algorithm::MegolmV1AesSha2,
+            room_id,
+            session_id: self.session_id(),
+            session_key: self.session_key(),
+        }
+    }
+
+    /// Encrypts a message, returning the Megolm message encoded as unpadded base64.
+    ///
+    /// Each message advances the ratchet, so earlier message keys can't be recovered from the
+    /// session.
+    pub fn encrypt_bytes(&mut self, plaintext: &[u8]) -> String {
+        let keys = self.ratchet.message_keys();
+        let ciphertext = cbc::Encryptor::<Aes256>::new(&keys.aes_key.into(), &keys.iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(plaintext);
+
+        let mut message = vec![MESSAGE_VERSION];
+
+        message.push(0x08);
+        push_varint(&mut message, u64::from(self.ratchet.counter));
+        message.push(0x12);
+        push_varint(&mut message, ciphertext.len() as u64);
+        message.extend_from_slice(&ciphertext);
+
+        let mac = keys.mac(&message);
+        message.extend_from_slice(&mac);
+
+        let signature = self.signing_key.sign(&message);
+        message.extend_from_slice(&signature.to_bytes());
+
+        self.ratchet.advance();
+
+        encode(&message)
+    }
+
+    /// Encrypts the payload of an event.
+    ///
+    /// `sender_key` and `device_id` are the Curve25519 identity key and ID of the sending device.
+    pub fn encrypt(
+        &mut self,
+        plaintext: &MegolmV1AesSha2Plaintext,
+        sender_key: String,
+        device_id: DeviceId,
+    ) -> MegolmV1AesSha2Content {
+        let plaintext = serde_json::to_vec(plaintext).expect("payloads always serialize to JSON");
+
+        MegolmV1AesSha2Content {
+            algorithm: Algorithm::MegolmV1AesSha2,
+            ciphertext: self.encrypt_bytes(&plaintext),
+            sender_key,
+            device_id,
+            session_id: self.session_id(),
+        }
+    }
+}
+
+impl Default for OutboundGroupSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for OutboundGroupSession {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("OutboundGroupSession")
+            .field("session_id", &self.session_id())
+            .field("message_index", &self.message_index())
+            .finish()
+    }
+}
+
+/// A session for decrypting the messages of an `OutboundGroupSession`.
+#[derive(Clone)]
+pub struct InboundGroupSession {
+    /// The ratchet at the first known message index.
+    ratchet: Ratchet,
+
+    /// The key messages are signed with.
+    signing_key: VerifyingKey,
+}
+
+impl InboundGroupSession {
+    /// Creates a session from a signed session key, as shared in *m.room_key* events.
+    pub fn new(session_key: &str) -> Result<Self, MegolmError> {
+        let key = decode(session_key)?;
+
+        if key.len() != 1 + 4 + RATCHET_LENGTH + 32 + SIGNATURE_LENGTH
+            || key[0] != SESSION_KEY_VERSION
+        {
+            return Err(MegolmError("invalid session key".to_string()));
+        }
+
+        let (signed, signature) = key.split_at(key.len() - SIGNATURE_LENGTH);
+        let session = Self::from_export_bytes(signed)?;
+        let signature =
+            Signature::from_slice(signature).map_err(|error| MegolmError(error.to_string()))?;
+
+        session
+            .signing_key
+            .verify(signed, &signature)
+            .map_err(|_| MegolmError("invalid session key signature".to_string()))?;
+
+        Ok(session)
+    }
+
+    /// Creates a session from an exported session key, as shared in *m.forwarded_room_key*
+    /// events.
+    pub fn import(exported_key: &str) -> Result<Self, MegolmError> {
+        let key = decode(exported_key)?;
+
+        if key.len() != 1 + 4 + RATCHET_LENGTH + 32 || key[0] != EXPORT_VERSION {
+            return Err(MegolmError("invalid exported session key".to_string()));
+        }
+
+        Self::from_export_bytes(&key)
+    }
+
+    /// Creates a session from the content of an *m.room_key* event.
+    pub fn from_room_key(content: &RoomKeyEventContent) -> Result<Self, MegolmError> {
+        check_algorithm(&content.algorithm)?;
+
+        let session = Self::new(&content.session_key)?;
+        session.check_session_id(&content.session_id)?;
+
+        Ok(session)
+    }
+
+    /// Creates a session from the content of an *m.forwarded_room_key* event.
+    pub fn from_forwarded_room_key(
+        content: &ForwardedRoomKeyEventContent,
+    ) -> Result<Self, MegolmError> {
+        check_algorithm(&content.algorithm)?;
+
+        let session = Self::import(&content.session_key)?;
+        session.check_session_id(&content.session_id)?;
+
+        Ok(session)
+    }
+
+    /// The ID of the session, which is its public signing key encoded as unpadded base64.
+    pub fn session_id(&self) -> String {
+        encode(self.signing_key.as_bytes())
+    }
+
+    /// The index of the first message the session can decrypt.
+    pub fn first_known_index(&self) -> u32 {
+        self.ratchet.counter
+    }
+
+    /// Exports the session at the given message index, encoded as unpadded base64.
+    ///
+    /// Returns `None` if the index is before the first known index.
+    pub fn export_at(&self, index: u32) -> Option<String> {
+        if index < self.ratchet.counter {
+            return None;
+        }
+
+        let mut ratchet = self.ratchet.clone();
+        ratchet.advance_to(index);
+
+        let mut key = Vec::with_capacity(1 + 4 + RATCHET_LENGTH + 32);
+
+        key.push(EXPORT_VERSION);
+        key.extend_from_slice(&ratchet.counter.to_be_bytes());
+        key.extend_from_slice(&ratchet.to_bytes());
+        key.extend_from_slice(self.signing_key.as_bytes());
+
+        Some(encode(&key))
+    }
+
+    /// The content of an *m.forwarded_room_key* event sharing the session from its first known
+    /// index.
+    ///
+    /// `sender_key` and `sender_claimed_ed25519_key` are the keys of the device that created the
+    /// session, and `forwarding_curve25519_key_chain` the keys of the devices the session was
+    /// forwarded through so far.
+    pub fn forwarded_room_key(
+        &self,
+        room_id: RoomId,
+        sender_key: String,
+        sender_claimed_ed25519_key: String,
+        forwarding_curve25519_key_chain: Vec<String>,
+    ) -> ForwardedRoomKeyEventContent {
+        ForwardedRoomKeyEventContent {
+            algorithm: Algorithm::MegolmV1AesSha2,
+            room_id,
+            sender_key,
+            session_id: self.session_id(),
+            session_key: self
+                .export_at(self.first_known_index())
+                .expect("the first known index can be exported"),
+            sender_claimed_ed25519_key,
+            forwarding_curve25519_key_chain,
+        }
+    }
+
+    /// Decrypts a Megolm message encoded as base64, returning the plaintext and the message
+    /// index.
+    pub fn decrypt_bytes(&self, message: &str) -> Result<(Vec<u8>, u32), MegolmError> {
+        let message = decode(message)?;
+
+        if message.len() < 1 + MAC_LENGTH + SIGNATURE_LENGTH {
+            return Err(MegolmError("the message is too short".to_string()));
+        }
+
+        let (signed, signature) = message.split_at(message.len() - SIGNATURE_LENGTH);
+        let (payload, mac) = signed.split_at(signed.len() - MAC_LENGTH);
+
+        if payload[0] != MESSAGE_VERSION {
+            return Err(MegolmError(format!(
+                "unsupported message version {}",
+                payload[0]
+            )));
+        }
+
+        let signature =
+            Signature::from_slice(signature).map_err(|error| MegolmError(error.to_string()))?;
+
+        self.signing_key
+            .verify(signed, &signature)
+            .map_err(|_| MegolmError("invalid message signature".to_string()))?;
+
+        let (index, ciphertext) = parse_payload(&payload[1..])?;
+
+        if index < self.ratchet.counter {
+            return Err(MegolmError(format!(
+                "the message index {} is before the first known index {}",
+                index, self.ratchet.counter
+            )));
+        }
+
+        let mut ratchet = self.ratchet.clone();
+        ratchet.advance_to(index);
+
+        let keys = ratchet.message_keys();
+
+        if keys.mac(payload)[..] != *mac {
+            return Err(MegolmError("invalid message MAC".to_string()));
+        }
+
+        let plaintext = cbc::Decryptor::<Aes256>::new(&keys.aes_key.into(), &keys.iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+            .map_err(|_| MegolmError("invalid message padding".to_string()))?;
+
+        Ok((plaintext, index))
+    }
+
+    /// Decrypts the content of an *m.room.encrypted* event, returning the payload and the message
+    /// index.
+    ///
+    /// Clients should remember the message indices they have seen, since a repeated index means a
+    /// message was replayed.
+    pub fn decrypt(
+        &self,
+        content: &MegolmV1AesSha2Content,
+    ) -> Result<(MegolmV1AesSha2Plaintext, u32), MegolmError> {
+        self.check_session_id(&content.session_id)?;
+
+        let (plaintext, index) = self.decrypt_bytes(&content.ciphertext)?;
+        let plaintext = serde_json::from_slice(&plaintext)
+            .map_err(|error| MegolmError(format!("invalid payload: {}", error)))?;
+
+        Ok((plaintext, index))
+    }
+
+    /// Creates a session from an exported session key without its version byte check.
+    fn from_export_bytes(key: &[u8]) -> Result<Self, MegolmError> {
+        let counter = u32::from_be_bytes(<[u8; 4]>::try_from(&key[1..5]).expect("4 bytes"));
+        let ratchet = Ratchet::from_bytes(&key[5..5 + RATCHET_LENGTH], counter);
+        let signing_key = <[u8; 32]>::try_from(&key[5 + RATCHET_LENGTH..5 + RATCHET_LENGTH + 32])
+            .expect("32 bytes");
+        let signing_key = VerifyingKey::from_bytes(&signing_key)
+            .map_err(|error| MegolmError(error.to_string()))?;
+
+        Ok(Self {
+            ratchet,
+            signing_key,
+        })
+    }
+
+    /// Fails unless the session has the given ID.
+    fn check_session_id(&self, session_id: &str) -> Result<(), MegolmError> {
+        if self.session_id() == session_id {
+            Ok(())
+        } else {
+            Err(MegolmError(format!(
+                "the session ID {} does not match the session key",
+                session_id
+            )))
+        }
+    }
+}
+
+impl Debug for InboundGroupSession {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("InboundGroupSession")
+            .field("session_id", &self.session_id())
+            .field("first_known_index", &self.first_known_index())
+            .finish()
+    }
+}
+
+/// The Megolm ratchet, which derives a new key for every message index.
+#[derive(Clone)]
+struct Ratchet {
+    /// The four parts of the ratchet.
+    parts: [[u8; 32]; 4],
+
+    /// The message index the ratchet is at.
+    counter: u32,
+}
+
+/// The keys a message is encrypted and authenticated with.
+struct MessageKeys {
+    aes_key: [u8; 32],
+    mac_key: [u8; 32],
+    iv: [u8; 16],
+}
+
+impl Ratchet {
+    fn from_bytes(bytes: &[u8], counter: u32) -> Self {
+        let mut parts = [[0; 32]; 4];
+
+        for (i, part) in parts.iter_mut().enumerate() {
+            part.copy_from_slice(&bytes[i * 32..(i + 1) * 32]);
+        }
+
+        Self { parts, counter }
+    }
+
+    fn to_bytes(&self) -> [u8; RATCHET_LENGTH] {
+        let mut bytes = [0; RATCHET_LENGTH];
+
+        for (i, part) in self.parts.iter().enumerate() {
+            bytes[i * 32..(i + 1) * 32].copy_from_slice(part);
+        }
+
+        bytes
+    }
+
+    /// Advances the ratchet by one message.
+    ///
+    /// Part `j` is rehashed whenever the lowest `8 * (3 - j)` bits of the counter wrap around,
+    /// and the parts after it are then derived from it.
+    fn advance(&mut self) {
+        self.counter = self.counter.wrapping_add(1);
+
+        let mut mask = 0x00ff_ffff;
+        let mut from = 0;
+
+        while from < 4 && self.counter & mask != 0 {
+            from += 1;
+            mask >>= 8;
+        }
+
+        for to in (from..4).rev() {
+            self.rehash(from, to);
+        }
+    }
+
+    /// Advances the ratchet to the given message index, which must not be before the current one
+    /// unless the counter wrapped around.
+    fn advance_to(&mut self, index: u32) {
+        for j in 0..4 {
+            let shift = (3 - j) * 8;
+            let mask = !0u32 << shift;
+            let mut steps = (index >> shift).wrapping_sub(self.counter >> shift) & 0xff;
+
+            if steps == 0 {
+                if index < self.counter {
+                    steps = 0x100;
+                } else {
+                    continue;
+                }
+            }
+
+            while steps > 1 {
+                self.rehash(j, j);
+                steps -= 1;
+            }
+
+            for to in (j..4).rev() {
+                self.rehash(j, to);
+            }
+
+            self.counter = index & mask;
+        }
+    }
+
+    /// Replaces part `to` with HMAC-SHA-256 of the byte `to`, keyed with part `from`.
+    fn rehash(&mut self, from: usize, to: usize) {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.parts[from]).expect("HMAC accepts any key length");
+        mac.update(&[to as u8]);
+
+        self.parts[to].copy_from_slice(&mac.finalize().into_bytes());
+    }
+
+    /// The keys of the message at the current index.
+    fn message_keys(&self) -> MessageKeys {
+        let mut okm = [0; 80];
+
+        Hkdf::<Sha256>::new(None, &self.to_bytes())
+            .expand(b"MEGOLM_KEYS", &mut okm)
+            .expect("80 bytes is a valid HKDF-SHA-256 output length");
+
+        let mut keys = MessageKeys {
+            aes_key: [0; 32],
+            mac_key: [0; 32],
+            iv: [0; 16],
+        };
+
+        keys.aes_key.copy_from_slice(&okm[..32]);
+        keys.mac_key.copy_from_slice(&okm[32..64]);
+        keys.iv.copy_from_slice(&okm[64..]);
+
+        keys
+    }
+}
+
+impl MessageKeys {
+    /// The truncated HMAC-SHA-256 of a message payload.
+    fn mac(&self, payload: &[u8]) -> [u8; MAC_LENGTH] {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.mac_key).expect("HMAC accepts any key length");
+        mac.update(payload);
+
+        let mut truncated = [0; MAC_LENGTH];
+        truncated.copy_from_slice(&mac.finalize().into_bytes()[..MAC_LENGTH]);
+        truncated
+    }
+}
+
+/// Parses the protobuf-encoded fields of a message payload into the message index and the
+/// ciphertext.
+fn parse_payload(mut payload: &[u8]) -> Result<(u32, &[u8]), MegolmError> {
+    let mut index = None;
+    let mut ciphertext = None;
+
+    while !payload.is_empty() {
+        let key = read_varint(&mut payload)?;
+
+        match key & 0x7 {
+            0 => {
+                let value = read_varint(&mut payload)?;
+
+                if key >> 3 == 1 {
+                    index = Some(u32::try_from(value).map_err(|_| {
+                        MegolmError("the message index is out of range".to_string())
+                    })?);
+                }
+            }
+            2 => {
+                let length = usize::try_from(read_varint(&mut payload)?)
+                    .ok()
+                    .filter(|length| *length <= payload.len())
+                    .ok_or_else(|| MegolmError("truncated message".to_string()))?;
+                let (value, rest) = payload.split_at(length);
+
+                if key >> 3 == 2 {
+                    ciphertext = Some(value);
+                }
+
+                payload = rest;
+            }
+            _ => return Err(MegolmError("invalid message encoding".to_string())),
+        }
+    }
+
+    match (index, ciphertext) {
+        (Some(index), Some(ciphertext)) => Ok((index, ciphertext)),
+        _ => Err(MegolmError(
+            "the message has no index or ciphertext".to_string(),
+        )),
+    }
+}
+
+/// Appends a protobuf varint.
+fn push_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+
+    bytes.push(value as u8);
+}
+
+/// Reads a protobuf varint from the front of a slice.
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, MegolmError> {
+    let mut value = 0;
+
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Ok(value);
+        }
+    }
+
+    Err(MegolmError("invalid varint".to_string()))
+}
+
+/// Fails unless the algorithm is *m.megolm.v1.aes-sha2*.
+fn check_algorithm(algorithm: &Algorithm) -> Result<(), MegolmError> {
+    if *algorithm == Algorithm::MegolmV1AesSha2 {
+        Ok(())
+    } else {
+        Err(MegolmError(format!("unsupported algorithm {}", algorithm)))
+    }
+}
+
+/// Encodes bytes as unpadded base64.
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::STANDARD_NO_PAD)
+}
+
+/// Decodes padded or unpadded base64.
+fn decode(encoded: &str) -> Result<Vec<u8>, MegolmError> {
+    base64::decode_config(encoded.trim_end_matches('='), base64::STANDARD_NO_PAD)
+        .map_err(|error| MegolmError(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ruma_identifiers::RoomId;
+    use serde_json::{from_value, json};
+
+    use super::{InboundGroupSession, OutboundGroupSession, Ratchet};
+    use crate::room::encrypted::MegolmV1AesSha2Plaintext;
+
+    fn ratchet() -> Ratchet {
+        let bytes: Vec<u8> = (0..128).collect();
+
+        Ratchet::from_bytes(&bytes, 0)
+    }
+
+    #[test]
+    fn ratchet_advance_to_matches_advance() {
+        for &(start, end) in &[
+            (0, 1),
+            (0, 255),
+            (0, 256),
+            (0, 300),
+            (250, 70_000),
+            (1, 65_537),
+        ] {
+            let mut stepped = ratchet();
+            stepped.advance_to(start);
+
+            let mut jumped = stepped.clone();
+
+            while stepped.counter < end {
+                stepped.advance();
+            }
+
+            jumped.advance_to(end);
+
+            assert_eq!(jumped.counter, end);
+            assert_eq!(jumped.to_bytes()[..], stepped.to_bytes()[..]);
+        }
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let room_id = RoomId::try_from("!room:example.com").unwrap();
+        let mut outbound = OutboundGroupSession::new();
+
+        let early = outbound.encrypt_bytes(b"before sharing");
+        let room_key = outbound.room_key(room_id.clone());
+        let inbound = InboundGroupSession::from_room_key(&room_key).unwrap();
+
+        assert_eq!(inbound.session_id(), outbound.session_id());
+        assert_eq!(inbound.first_known_index(), 1);
+        assert!(inbound.decrypt_bytes(&early).is_err());
+
+        let plaintext: MegolmV1AesSha2Plaintext = from_value(json!({
+            "content": {"body": "hello", "msgtype": "m.text"},
+            "room_id": "!room:example.com",
+            "type": "m.room.message"
+        }))
+        .unwrap();
+
+        for index in 1..4 {
+            let content =
+                outbound.encrypt(&plaintext, "sender_key".to_string(), "DEVICE".to_string());
+
+            assert_eq!(content.session_id, outbound.session_id());
+            assert_eq!(
+                inbound.decrypt(&content).unwrap(),
+                (plaintext.clone(), index)
+            );
+        }
+
+        assert_eq!(
+            inbound.decrypt_bytes(&outbound.encrypt_bytes(b"")).unwrap(),
+            (Vec::new(), 4)
+        );
+    }
+
+    #[test]
+    fn tampering() {
+        let mut outbound = OutboundGroupSession::new();
+        let inbound = InboundGroupSession::new(&outbound.session_key()).unwrap();
+        let message =
+            base64::decode_config(outbound.encrypt_bytes(b"hello"), base64::STANDARD_NO_PAD)
+                .unwrap();
+
+        for i in 0..message.len() {
+            let mut tampered = message.clone();
+            tampered[i] ^= 1;
+
+            assert!(inbound
+                .decrypt_bytes(&base64::encode_config(&tampered, base64::STANDARD_NO_PAD))
+                .is_err());
+        }
+
+        let mut session_key =
+            base64::decode_config(outbound.session_key(), base64::STANDARD_NO_PAD).unwrap();
+        session_key[10] ^= 1;
+
+        assert!(InboundGroupSession::new(&base64::encode_config(
+            &session_key,
+            base64::STANDARD_NO_PAD
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn export_and_forward() {
+        let room_id = RoomId::try_from("!room:example.com").unwrap();
+        let mut outbound = OutboundGroupSession::new();
+        let inbound = InboundGroupSession::new(&outbound.session_key()).unwrap();
+
+        let first = outbound.encrypt_bytes(b"first");
+        let second = outbound.encrypt_bytes(b"second");
+
+        let forwarded = inbound.forwarded_room_key(
+            room_id,
+            "sender_key".to_string(),
+            "sender_ed25519".to_string(),
+            Vec::new(),
+        );
+        let imported = InboundGroupSession::from_forwarded_room_key(&forwarded).unwrap();
+
+        assert_eq!(imported.decrypt_bytes(&first).unwrap().0, b"first");
+
+        let later = InboundGroupSession::import(&inbound.export_at(1).unwrap()).unwrap();
+
+        assert!(later.decrypt_bytes(&first).is_err());
+        assert_eq!(
+            later.decrypt_bytes(&second).unwrap(),
+            (b"second".to_vec(), 1)
+        );
+        assert!(later.export_at(0).is_none());
+
+        let mut wrong_id = forwarded;
+        wrong_id.session_id = "other".to_string();
+
+        assert!(InboundGroupSession::from_forwarded_room_key(&wrong_id).is_err());
+    }
+}
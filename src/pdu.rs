@@ -0,0 +1,518 @@
+//! Types for persistent data units (PDUs), the form in which homeservers exchange room events.
+//!
+//! A PDU is a room event with the additional fields servers use to build and verify the event
+//! graph. The types here wrap any room event type in this crate, such as
+//! `room::message::MessageEvent` or `collections::all::RoomEvent`, so the existing content types
+//! can be used on the server side as well.
+//!
+//! The shape of a PDU depends on the version of the room it belongs to. In room versions 1 and 2,
+//! PDUs carry their event ID and reference other events by ID and hash (`RoomV1Pdu`). From room
+//! version 3 on, event IDs are derived from the reference hash of the event, so PDUs don't carry
+//! them and reference other events by ID only (`RoomV3Pdu`).
+
+use std::{collections::HashMap, convert::TryFrom, str::FromStr};
+
+use js_int::UInt;
+use ruma_identifiers::EventId;
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
+use serde_json::{json, to_value, Value};
+
+use crate::{
+    collections::all::StateEvent, state_res::ResolvableEvent, InnerInvalidEvent, InvalidEvent,
+};
+
+/// The keys of a PDU that are not part of the wrapped event.
+///
+/// `redacts` is not included since it is part of *m.room.redaction* events, which are the only
+/// events that have it.
+const PDU_KEYS: &[&str] = &[
+    "origin",
+    "depth",
+    "prev_events",
+    "auth_events",
+    "hashes",
+    "signatures",
+];
+
+/// The signatures of an event, as a map from server names to key IDs to signatures.
+pub type Signatures = HashMap<String, HashMap<String, String>>;
+
+/// The content hashes of an event.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EventHash {
+    /// The SHA-256 hash, encoded as unpadded base64.
+    pub sha256: String,
+}
+
+/// A PDU in the format used by room versions 1 and 2.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomV1Pdu<E> {
+    /// The event, including its ID.
+    pub event: E,
+
+    /// The server name of the homeserver that created the event.
+    pub origin: String,
+
+    /// The maximum depth of the `prev_events`, plus one.
+    pub depth: UInt,
+
+    /// The IDs and hashes of the most recent events in the room that the homeserver was aware of
+    /// when it created the event.
+    pub prev_events: Vec<(EventId, EventHash)>,
+
+    /// The IDs and hashes of the events that authorize the event.
+    pub auth_events: Vec<(EventId, EventHash)>,
+
+    /// The content hashes of the event.
+    pub hashes: EventHash,
+
+    /// The signatures of the event.
+    pub signatures: Signatures,
+}
+
+/// A PDU in the format used by room versions 3 and later.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomV3Pdu<E> {
+    /// The event.
+    ///
+    /// The event ID is derived from the event's reference hash and is not part of the PDU when
+    /// it is serialized.
+    pub event: E,
+
+    /// The server name of the homeserver that created the event.
+    pub origin: String,
+
+    /// The maximum depth of the `prev_events`, plus one.
+    pub depth: UInt,
+
+    /// The IDs of the most recent events in the room that the homeserver was aware of when it
+    /// created the event.
+    pub prev_events: Vec<EventId>,
+
+    /// The IDs of the events that authorize the event.
+    pub auth_events: Vec<EventId>,
+
+    /// The content hashes of the event.
+    pub hashes: EventHash,
+
+    /// The signatures of the event.
+    pub signatures: Signatures,
+}
+
+/// A PDU in the format of any room version.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pdu<E> {
+    /// A PDU in the format used by room versions 1 and 2.
+    RoomV1(RoomV1Pdu<E>),
+
+    /// A PDU in the format used by room versions 3 and later.
+    RoomV3(RoomV3Pdu<E>),
+}
+
+impl<E> Pdu<E> {
+    /// The event.
+    pub fn event(&self) -> &E {
+        match *self {
+            Pdu::RoomV1(ref pdu) => &pdu.event,
+            Pdu::RoomV3(ref pdu) => &pdu.event,
+        }
+    }
+
+    /// Converts the PDU into its event.
+    pub fn into_event(self) -> E {
+        match self {
+            Pdu::RoomV1(pdu) => pdu.event,
+            Pdu::RoomV3(pdu) => pdu.event,
+        }
+    }
+
+    /// The server name of the homeserver that created the event.
+    pub fn origin(&self) -> &str {
+        match *self {
+            Pdu::RoomV1(ref pdu) => &pdu.origin,
+            Pdu::RoomV3(ref pdu) => &pdu.origin,
+        }
+    }
+
+    /// The maximum depth of the `prev_events`, plus one.
+    pub fn depth(&self) -> UInt {
+        match *self {
+            Pdu::RoomV1(ref pdu) => pdu.depth,
+            Pdu::RoomV3(ref pdu) => pdu.depth,
+        }
+    }
+
+    /// The IDs of the `prev_events`.
+    pub fn prev_event_ids(&self) -> Vec<&EventId> {
+        match *self {
+            Pdu::RoomV1(ref pdu) => pdu.prev_events.iter().map(|(id, _)| id).collect(),
+            Pdu::RoomV3(ref pdu) => pdu.prev_events.iter().collect(),
+        }
+    }
+
+    /// The IDs of the `auth_events`.
+    pub fn auth_event_ids(&self) -> Vec<&EventId> {
+        match *self {
+            Pdu::RoomV1(ref pdu) => pdu.auth_events.iter().map(|(id, _)| id).collect(),
+            Pdu::RoomV3(ref pdu) => pdu.auth_events.iter().collect(),
+        }
+    }
+
+    /// The content hashes of the event.
+    pub fn hashes(&self) -> &EventHash {
+        match *self {
+            Pdu::RoomV1(ref pdu) => &pdu.hashes,
+            Pdu::RoomV3(ref pdu) => &pdu.hashes,
+        }
+    }
+
+    /// The signatures of the event.
+    pub fn signatures(&self) -> &Signatures {
+        match *self {
+            Pdu::RoomV1(ref pdu) => &pdu.signatures,
+            Pdu::RoomV3(ref pdu) => &pdu.signatures,
+        }
+    }
+}
+
+impl<E> From<RoomV1Pdu<E>> for Pdu<E> {
+    fn from(pdu: RoomV1Pdu<E>) -> Self {
+        Pdu::RoomV1(pdu)
+    }
+}
+
+impl<E> From<RoomV3Pdu<E>> for Pdu<E> {
+    fn from(pdu: RoomV3Pdu<E>) -> Self {
+        Pdu::RoomV3(pdu)
+    }
+}
+
+impl From<Pdu<StateEvent>> for ResolvableEvent {
+    fn from(pdu: Pdu<StateEvent>) -> Self {
+        let auth_events = pdu.auth_event_ids().into_iter().cloned().collect();
+
+        Self {
+            event: pdu.into_event(),
+            auth_events,
+        }
+    }
+}
+
+impl<E> FromStr for RoomV1Pdu<E>
+where
+    E: FromStr<Err = InvalidEvent>,
+{
+    type Err = InvalidEvent;
+
+    /// Attempt to create `Self` from parsing a string of JSON data.
+    fn from_str(json: &str) -> Result<Self, Self::Err> {
+        let (fields, event) = split(serde_json::from_str(json)?)?;
+
+        Ok(Self {
+            event,
+            origin: fields.origin,
+            depth: fields.depth,
+            prev_events: fields.prev_events,
+            auth_events: fields.auth_events,
+            hashes: fields.hashes,
+            signatures: fields.signatures,
+        })
+    }
+}
+
+impl<'a, E> TryFrom<&'a str> for RoomV1Pdu<E>
+where
+    E: FromStr<Err = InvalidEvent>,
+{
+    type Error = InvalidEvent;
+
+    /// Attempt to create `Self` from parsing a string of JSON data.
+    fn try_from(json: &'a str) -> Result<Self, Self::Error> {
+        FromStr::from_str(json)
+    }
+}
+
+impl<E> RoomV3Pdu<E>
+where
+    E: FromStr<Err = InvalidEvent>,
+{
+    /// Attempt to create `Self` from parsing a string of JSON data.
+    ///
+    /// Since PDUs in this format don't carry their event ID, it has to be supplied separately.
+    pub fn parse(json: &str, event_id: &EventId) -> Result<Self, InvalidEvent> {
+        let mut value: Value = serde_json::from_str(json)?;
+
+        if let Value::Object(ref mut object) = value {
+            object.insert("event_id".to_string(), json!(event_id));
+        }
+
+        let (fields, event) = split(value)?;
+
+        Ok(Self {
+            event,
+            origin: fields.origin,
+            depth: fields.depth,
+            prev_events: fields.prev_events,
+            auth_events: fields.auth_events,
+            hashes: fields.hashes,
+            signatures: fields.signatures,
+        })
+    }
+}
+
+impl<E> Serialize for RoomV1Pdu<E>
+where
+    E: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let fields = json!({
+            "origin": self.origin,
+            "depth": self.depth,
+            "prev_events": self.prev_events,
+            "auth_events": self.auth_events,
+            "hashes": self.hashes,
+            "signatures": self.signatures,
+        });
+
+        merge(&self.event, fields, true)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<E> Serialize for RoomV3Pdu<E>
+where
+    E: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let fields = json!({
+            "origin": self.origin,
+            "depth": self.depth,
+            "prev_events": self.prev_events,
+            "auth_events": self.auth_events,
+            "hashes": self.hashes,
+            "signatures": self.signatures,
+        });
+
+        merge(&self.event, fields, false)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<E> Serialize for Pdu<E>
+where
+    E: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            Pdu::RoomV1(ref pdu) => pdu.serialize(serializer),
+            Pdu::RoomV3(ref pdu) => pdu.serialize(serializer),
+        }
+    }
+}
+
+/// The fields of a PDU that are not part of the wrapped event, with `R` being the type of the
+/// references to other events.
+#[derive(Deserialize)]
+struct PduFields<R> {
+    origin: String,
+    depth: UInt,
+    prev_events: Vec<R>,
+    auth_events: Vec<R>,
+    hashes: EventHash,
+    #[serde(default)]
+    signatures: Signatures,
+}
+
+/// Splits the JSON representation of a PDU into the PDU fields and the wrapped event.
+fn split<R, E>(mut value: Value) -> Result<(PduFields<R>, E), InvalidEvent>
+where
+    R: DeserializeOwned,
+    E: FromStr<Err = InvalidEvent>,
+{
+    let fields = match serde_json::from_value::<PduFields<R>>(value.clone()) {
+        Ok(fields) => fields,
+        Err(error) => {
+            return Err(InvalidEvent(InnerInvalidEvent::Validation {
+                json: value,
+                message: error.to_string(),
+            }));
+        }
+    };
+
+    if value.get("room_id").map_or(true, Value::is_null) {
+        return Err(InvalidEvent(InnerInvalidEvent::Validation {
+            json: value,
+            message: "PDUs must have a room_id".to_string(),
+        }));
+    }
+
+    if let Value::Object(ref mut object) = value {
+        for key in PDU_KEYS {
+            object.remove(*key);
+        }
+    }
+
+    Ok((fields, value.to_string().parse()?))
+}
+
+/// Adds the PDU fields to the JSON representation of an event.
+fn merge<E>(event: &E, fields: Value, keep_event_id: bool) -> Result<Value, serde_json::Error>
+where
+    E: Serialize,
+{
+    let mut value = to_value(event)?;
+
+    if let (Value::Object(object), Value::Object(fields)) = (&mut value, fields) {
+        object.extend(fields);
+
+        if !keep_event_id {
+            object.remove("event_id");
+        }
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ruma_identifiers::EventId;
+    use serde_json::{json, to_value};
+
+    use super::{Pdu, RoomV1Pdu, RoomV3Pdu};
+    use crate::{
+        collections::all::{RoomEvent, StateEvent},
+        room::{message::MessageEvent, redaction::RedactionEvent},
+        state_res::ResolvableEvent,
+        RoomEvent as _,
+    };
+
+    #[test]
+    fn room_v1_pdu() {
+        let json = json!({
+            "auth_events": [["$create:example.com", {"sha256": "abc"}]],
+            "content": {"body": "hello", "msgtype": "m.text"},
+            "depth": 3,
+            "event_id": "$message:example.com",
+            "hashes": {"sha256": "def"},
+            "origin": "example.com",
+            "origin_server_ts": 1,
+            "prev_events": [["$previous:example.com", {"sha256": "ghi"}]],
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "signatures": {"example.com": {"ed25519:0": "sig"}},
+            "type": "m.room.message"
+        });
+
+        let pdu: RoomV1Pdu<MessageEvent> = json.to_string().parse().unwrap();
+
+        assert_eq!(pdu.event.event_id().to_string(), "$message:example.com");
+        assert_eq!(pdu.origin, "example.com");
+        assert_eq!(pdu.prev_events[0].0.to_string(), "$previous:example.com");
+        assert_eq!(pdu.auth_events[0].1.sha256, "abc");
+        assert_eq!(pdu.signatures["example.com"]["ed25519:0"], "sig");
+        assert_eq!(to_value(&pdu).unwrap(), json);
+
+        let pdu = Pdu::from(pdu);
+
+        assert_eq!(pdu.auth_event_ids()[0].to_string(), "$create:example.com");
+        assert_eq!(u64::from(pdu.depth()), 3);
+    }
+
+    #[test]
+    fn room_v3_pdu() {
+        let json = json!({
+            "auth_events": ["$create", "$power_levels"],
+            "content": {"reason": "spam"},
+            "depth": 4,
+            "hashes": {"sha256": "def"},
+            "origin": "example.com",
+            "origin_server_ts": 1,
+            "prev_events": ["$previous"],
+            "redacts": "$message",
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "signatures": {},
+            "type": "m.room.redaction"
+        });
+        let event_id = EventId::try_from("$redaction").unwrap();
+
+        let pdu = RoomV3Pdu::<RedactionEvent>::parse(&json.to_string(), &event_id).unwrap();
+
+        assert_eq!(pdu.event.event_id(), &event_id);
+        assert_eq!(pdu.event.redacts.to_string(), "$message");
+        assert_eq!(pdu.auth_events.len(), 2);
+        assert_eq!(to_value(&pdu).unwrap(), json);
+
+        let pdu = RoomV3Pdu::<RoomEvent>::parse(&json.to_string(), &event_id).unwrap();
+
+        match pdu.event {
+            RoomEvent::RoomRedaction(_) => {}
+            _ => panic!("expected a redaction event"),
+        }
+    }
+
+    #[test]
+    fn state_pdu_for_state_resolution() {
+        let json = json!({
+            "auth_events": ["$create"],
+            "content": {"topic": "Topic"},
+            "depth": 2,
+            "hashes": {"sha256": "def"},
+            "origin": "example.com",
+            "origin_server_ts": 1,
+            "prev_events": ["$create"],
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "state_key": "",
+            "type": "m.room.topic"
+        });
+        let event_id = EventId::try_from("$topic").unwrap();
+
+        let pdu = RoomV3Pdu::<StateEvent>::parse(&json.to_string(), &event_id).unwrap();
+        let event = ResolvableEvent::from(Pdu::from(pdu));
+
+        assert_eq!(event.event.event_id(), &event_id);
+        assert_eq!(event.auth_events[0].to_string(), "$create");
+    }
+
+    #[test]
+    fn missing_fields() {
+        let json = json!({
+            "auth_events": [],
+            "content": {"topic": "Topic"},
+            "depth": 2,
+            "event_id": "$topic:example.com",
+            "hashes": {"sha256": "def"},
+            "origin": "example.com",
+            "origin_server_ts": 1,
+            "prev_events": [],
+            "sender": "@alice:example.com",
+            "state_key": "",
+            "type": "m.room.topic"
+        });
+
+        assert!(json.to_string().parse::<RoomV1Pdu<StateEvent>>().is_err());
+
+        let mut json = json;
+        json["room_id"] = json!("!room:example.com");
+
+        assert!(json.to_string().parse::<RoomV1Pdu<StateEvent>>().is_ok());
+
+        json.as_object_mut().unwrap().remove("depth");
+
+        assert!(json.to_string().parse::<RoomV1Pdu<StateEvent>>().is_err());
+    }
+}
@@ -14,6 +14,15 @@ use serde_json::{from_value, Value};
 
 use super::{default_true, FromStrError};
 
+pub use self::{
+    edit::{AnyPushRule, RulesetError},
+    eval::{MatchedPushRule, PushContext, RuleKind},
+};
+
+mod edit;
+mod eval;
+mod predefined;
+
 ruma_event! {
     /// Describes all push rules for a user.
     PushRulesEvent {
@@ -0,0 +1,553 @@
+//! Modification of a `Ruleset`, following the semantics of the client-server push rules API.
+
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+use ruma_identifiers::{RoomId, UserId};
+
+use super::{Action, ConditionalPushRule, PatternedPushRule, PushRule, RuleKind, Ruleset};
+
+/// A push rule of any kind, together with its kind.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnyPushRule {
+    /// An override rule.
+    Override(ConditionalPushRule),
+
+    /// A content rule.
+    Content(PatternedPushRule),
+
+    /// A room rule. The rule ID is the ID of the room.
+    Room(PushRule),
+
+    /// A sender rule. The rule ID is the ID of the user.
+    Sender(PushRule),
+
+    /// An underride rule.
+    Underride(ConditionalPushRule),
+}
+
+impl AnyPushRule {
+    /// The kind of the rule.
+    pub fn kind(&self) -> RuleKind {
+        match *self {
+            AnyPushRule::Override(_) => RuleKind::Override,
+            AnyPushRule::Content(_) => RuleKind::Content,
+            AnyPushRule::Room(_) => RuleKind::Room,
+            AnyPushRule::Sender(_) => RuleKind::Sender,
+            AnyPushRule::Underride(_) => RuleKind::Underride,
+        }
+    }
+
+    /// The ID of the rule.
+    pub fn rule_id(&self) -> &str {
+        match *self {
+            AnyPushRule::Override(ref rule) | AnyPushRule::Underride(ref rule) => &rule.rule_id,
+            AnyPushRule::Content(ref rule) => &rule.rule_id,
+            AnyPushRule::Room(ref rule) | AnyPushRule::Sender(ref rule) => &rule.rule_id,
+        }
+    }
+}
+
+/// An error returned when a `Ruleset` can't be modified as requested.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RulesetError {
+    /// There is no rule of the given kind with the given ID.
+    NotFound(String),
+
+    /// The rule is a server-default rule, which can't be removed or replaced.
+    ServerDefaultRule(String),
+
+    /// The rule given as `before` or `after` is a server-default rule, and user-defined rules
+    /// can't be positioned relative to those.
+    RelativeToServerDefaultRule(String),
+
+    /// The rule ID is not valid for a user-defined rule.
+    ///
+    /// IDs can't contain `/` or `\`, and IDs starting with `.` are reserved for server-default
+    /// rules. The IDs of room and sender rules must also be a valid room ID and user ID
+    /// respectively.
+    InvalidRuleId(String),
+}
+
+impl Display for RulesetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match *self {
+            RulesetError::NotFound(ref rule_id) => write!(f, "push rule `{}` not found", rule_id),
+            RulesetError::ServerDefaultRule(ref rule_id) => {
+                write!(f, "push rule `{}` is a server-default rule", rule_id)
+            }
+            RulesetError::RelativeToServerDefaultRule(ref rule_id) => write!(
+                f,
+                "cannot position a rule relative to server-default rule `{}`",
+                rule_id
+            ),
+            RulesetError::InvalidRuleId(ref rule_id) => {
+                write!(f, "`{}` is not a valid push rule ID", rule_id)
+            }
+        }
+    }
+}
+
+impl Error for RulesetError {}
+
+impl Ruleset {
+    /// Adds a user-defined rule, or replaces the existing user-defined rule with the same kind and
+    /// ID.
+    ///
+    /// If `before` is given, the rule becomes the next-most important rule with respect to that
+    /// rule, otherwise if `after` is given, it becomes the next-less important rule with respect
+    /// to that rule. Both must refer to user-defined rules of the same kind. If neither is given,
+    /// the rule becomes the most important user-defined rule of its kind.
+    ///
+    /// The rule's `default` flag is cleared, since only the server can add server-default rules.
+    pub fn insert(
+        &mut self,
+        rule: AnyPushRule,
+        after: Option<&str>,
+        before: Option<&str>,
+    ) -> Result<(), RulesetError> {
+        match rule {
+            AnyPushRule::Override(rule) => insert(&mut self.override_rules, rule, after, before),
+            AnyPushRule::Content(rule) => insert(&mut self.content, rule, after, before),
+            AnyPushRule::Room(rule) => {
+                if RoomId::try_from(rule.rule_id.as_str()).is_err() {
+                    return Err(RulesetError::InvalidRuleId(rule.rule_id));
+                }
+
+                insert(&mut self.room, rule, after, before)
+            }
+            AnyPushRule::Sender(rule) => {
+                if UserId::try_from(rule.rule_id.as_str()).is_err() {
+                    return Err(RulesetError::InvalidRuleId(rule.rule_id));
+                }
+
+                insert(&mut self.sender, rule, after, before)
+            }
+            AnyPushRule::Underride(rule) => insert(&mut self.underride, rule, after, before),
+        }
+    }
+
+    /// Removes the user-defined rule with the given kind and ID.
+    pub fn remove(&mut self, kind: RuleKind, rule_id: &str) -> Result<AnyPushRule, RulesetError> {
+        match kind {
+            RuleKind::Override => {
+                remove(&mut self.override_rules, rule_id).map(AnyPushRule::Override)
+            }
+            RuleKind::Content => remove(&mut self.content, rule_id).map(AnyPushRule::Content),
+            RuleKind::Room => remove(&mut self.room, rule_id).map(AnyPushRule::Room),
+            RuleKind::Sender => remove(&mut self.sender, rule_id).map(AnyPushRule::Sender),
+            RuleKind::Underride => remove(&mut self.underride, rule_id).map(AnyPushRule::Underride),
+            RuleKind::__Nonexhaustive => Err(RulesetError::NotFound(rule_id.to_string())),
+        }
+    }
+
+    /// Enables or disables the rule with the given kind and ID.
+    ///
+    /// This works for server-default rules as well as user-defined ones.
+    pub fn set_enabled(
+        &mut self,
+        kind: RuleKind,
+        rule_id: &str,
+        enabled: bool,
+    ) -> Result<(), RulesetError> {
+        self.with_rule(kind, rule_id, |rule| *rule.enabled_mut() = enabled)
+    }
+
+    /// Replaces the actions of the rule with the given kind and ID.
+    ///
+    /// This works for server-default rules as well as user-defined ones.
+    pub fn set_actions(
+        &mut self,
+        kind: RuleKind,
+        rule_id: &str,
+        actions: Vec<Action>,
+    ) -> Result<(), RulesetError> {
+        self.with_rule(kind, rule_id, |rule| *rule.actions_mut() = actions)
+    }
+
+    /// Calls `f` on the rule with the given kind and ID.
+    fn with_rule<F>(&mut self, kind: RuleKind, rule_id: &str, f: F) -> Result<(), RulesetError>
+    where
+        F: FnOnce(&mut dyn Rule),
+    {
+        let rule: Option<&mut dyn Rule> = match kind {
+            RuleKind::Override => find_mut(&mut self.override_rules, rule_id),
+            RuleKind::Content => find_mut(&mut self.content, rule_id),
+            RuleKind::Room => find_mut(&mut self.room, rule_id),
+            RuleKind::Sender => find_mut(&mut self.sender, rule_id),
+            RuleKind::Underride => find_mut(&mut self.underride, rule_id),
+            RuleKind::__Nonexhaustive => None,
+        };
+
+        match rule {
+            Some(rule) => {
+                f(rule);
+                Ok(())
+            }
+            None => Err(RulesetError::NotFound(rule_id.to_string())),
+        }
+    }
+}
+
+/// The fields shared by every kind of push rule.
+trait Rule {
+    fn rule_id(&self) -> &str;
+
+    fn is_default(&self) -> bool;
+
+    fn default_mut(&mut self) -> &mut bool;
+
+    fn enabled_mut(&mut self) -> &mut bool;
+
+    fn actions_mut(&mut self) -> &mut Vec<Action>;
+}
+
+macro_rules! impl_rule {
+    ($name:ident) => {
+        impl Rule for $name {
+            fn rule_id(&self) -> &str {
+                &self.rule_id
+            }
+
+            fn is_default(&self) -> bool {
+                self.default
+            }
+
+            fn default_mut(&mut self) -> &mut bool {
+                &mut self.default
+            }
+
+            fn enabled_mut(&mut self) -> &mut bool {
+                &mut self.enabled
+            }
+
+            fn actions_mut(&mut self) -> &mut Vec<Action> {
+                &mut self.actions
+            }
+        }
+    };
+}
+
+impl_rule!(PushRule);
+impl_rule!(ConditionalPushRule);
+impl_rule!(PatternedPushRule);
+
+fn find_mut<'a, R: Rule>(rules: &'a mut [R], rule_id: &str) -> Option<&'a mut dyn Rule> {
+    rules
+        .iter_mut()
+        .find(|rule| rule.rule_id() == rule_id)
+        .map(|rule| rule as &mut dyn Rule)
+}
+
+fn insert<R: Rule>(
+    rules: &mut Vec<R>,
+    mut rule: R,
+    after: Option<&str>,
+    before: Option<&str>,
+) -> Result<(), RulesetError> {
+    let rule_id = rule.rule_id().to_string();
+
+    if let Some(existing) = rules.iter().find(|existing| existing.rule_id() == rule_id) {
+        if existing.is_default() {
+            return Err(RulesetError::ServerDefaultRule(rule_id));
+        }
+    }
+
+    if rule_id.is_empty() || rule_id.starts_with('.') || rule_id.contains(&['/', '\\'][..]) {
+        return Err(RulesetError::InvalidRuleId(rule_id));
+    }
+
+    *rule.default_mut() = false;
+
+    // Position relative to the other rules, ignoring the one being replaced.
+    let position_of = |rules: &[R], relative_id: &str| match rules
+        .iter()
+        .position(|rule| rule.rule_id() == relative_id)
+    {
+        Some(index) if rules[index].is_default() => Err(RulesetError::RelativeToServerDefaultRule(
+            relative_id.to_string(),
+        )),
+        Some(index) => Ok(index),
+        None => Err(RulesetError::NotFound(relative_id.to_string())),
+    };
+
+    let mut remaining: Vec<R> = Vec::with_capacity(rules.len() + 1);
+    let mut replaced = None;
+
+    for (index, existing) in rules.drain(..).enumerate() {
+        if existing.rule_id() == rule_id {
+            replaced = Some((index, existing));
+        } else {
+            remaining.push(existing);
+        }
+    }
+
+    let index = match (before, after) {
+        (Some(before), _) => position_of(&remaining, before),
+        (None, Some(after)) => position_of(&remaining, after).map(|index| index + 1),
+        (None, None) => Ok(highest_user_priority(&remaining)),
+    };
+
+    let index = match index {
+        Ok(index) => index,
+        Err(error) => {
+            // Restore the ruleset before bailing out.
+            *rules = remaining;
+
+            if let Some((replaced_index, replaced_rule)) = replaced {
+                rules.insert(replaced_index, replaced_rule);
+            }
+
+            return Err(error);
+        }
+    };
+
+    remaining.insert(index, rule);
+    *rules = remaining;
+
+    Ok(())
+}
+
+/// The index at which a new rule becomes the most important user-defined rule.
+///
+/// This is directly before the first user-defined rule, or if there are none, before the
+/// server-default rules. The `.m.rule.master` rule always stays first.
+fn highest_user_priority<R: Rule>(rules: &[R]) -> usize {
+    rules
+        .iter()
+        .position(|rule| !rule.is_default())
+        .or_else(|| {
+            rules
+                .iter()
+                .position(|rule| rule.rule_id() != ".m.rule.master")
+        })
+        .unwrap_or(rules.len())
+}
+
+fn remove<R: Rule>(rules: &mut Vec<R>, rule_id: &str) -> Result<R, RulesetError> {
+    match rules.iter().position(|rule| rule.rule_id() == rule_id) {
+        Some(index) if rules[index].is_default() => {
+            Err(RulesetError::ServerDefaultRule(rule_id.to_string()))
+        }
+        Some(index) => Ok(rules.remove(index)),
+        None => Err(RulesetError::NotFound(rule_id.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ruma_identifiers::UserId;
+
+    use super::{AnyPushRule, RulesetError};
+    use crate::push_rules::{
+        Action, ConditionalPushRule, PatternedPushRule, PushRule, RuleKind, Ruleset,
+    };
+
+    fn ruleset() -> Ruleset {
+        Ruleset::server_default(&UserId::try_from("@alice:example.com").unwrap())
+    }
+
+    fn content_rule(rule_id: &str) -> AnyPushRule {
+        AnyPushRule::Content(PatternedPushRule {
+            actions: vec![Action::Notify],
+            default: false,
+            enabled: true,
+            rule_id: rule_id.to_string(),
+            pattern: rule_id.to_string(),
+        })
+    }
+
+    fn content_rule_ids(ruleset: &Ruleset) -> Vec<&str> {
+        ruleset
+            .content
+            .iter()
+            .map(|rule| rule.rule_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn insert_positions() {
+        let mut ruleset = ruleset();
+
+        ruleset.insert(content_rule("a"), None, None).unwrap();
+        ruleset.insert(content_rule("b"), None, None).unwrap();
+        ruleset.insert(content_rule("c"), Some("a"), None).unwrap();
+        ruleset.insert(content_rule("d"), None, Some("a")).unwrap();
+
+        assert_eq!(
+            content_rule_ids(&ruleset),
+            vec!["b", "d", "a", "c", ".m.rule.contains_user_name"]
+        );
+
+        // Re-inserting moves the existing rule.
+        ruleset.insert(content_rule("c"), None, Some("b")).unwrap();
+
+        assert_eq!(
+            content_rule_ids(&ruleset),
+            vec!["c", "b", "d", "a", ".m.rule.contains_user_name"]
+        );
+    }
+
+    #[test]
+    fn insert_override_keeps_master_first() {
+        let mut ruleset = ruleset();
+        let rule = ConditionalPushRule {
+            actions: vec![Action::DontNotify],
+            default: false,
+            enabled: true,
+            rule_id: "mine".to_string(),
+            conditions: Vec::new(),
+        };
+
+        ruleset
+            .insert(AnyPushRule::Override(rule), None, None)
+            .unwrap();
+
+        assert_eq!(ruleset.override_rules[0].rule_id, ".m.rule.master");
+        assert_eq!(ruleset.override_rules[1].rule_id, "mine");
+    }
+
+    #[test]
+    fn insert_errors() {
+        let mut ruleset = ruleset();
+        ruleset.insert(content_rule("a"), None, None).unwrap();
+
+        assert_eq!(
+            ruleset.insert(content_rule(".m.rule.contains_user_name"), None, None),
+            Err(RulesetError::ServerDefaultRule(
+                ".m.rule.contains_user_name".to_string()
+            ))
+        );
+        assert_eq!(
+            ruleset.insert(content_rule(".mine"), None, None),
+            Err(RulesetError::InvalidRuleId(".mine".to_string()))
+        );
+        assert_eq!(
+            ruleset.insert(content_rule("b"), None, Some(".m.rule.contains_user_name")),
+            Err(RulesetError::RelativeToServerDefaultRule(
+                ".m.rule.contains_user_name".to_string()
+            ))
+        );
+        assert_eq!(
+            ruleset.insert(content_rule("a"), Some("missing"), None),
+            Err(RulesetError::NotFound("missing".to_string()))
+        );
+
+        // Failed replacements leave the ruleset untouched.
+        assert_eq!(
+            content_rule_ids(&ruleset),
+            vec!["a", ".m.rule.contains_user_name"]
+        );
+    }
+
+    #[test]
+    fn insert_clears_default() {
+        let mut ruleset = ruleset();
+        let rule = AnyPushRule::Content(PatternedPushRule {
+            actions: vec![Action::Notify],
+            default: true,
+            enabled: true,
+            rule_id: "a".to_string(),
+            pattern: "a".to_string(),
+        });
+
+        ruleset.insert(rule, None, None).unwrap();
+
+        assert!(!ruleset.content[0].default);
+        assert!(ruleset.remove(RuleKind::Content, "a").is_ok());
+    }
+
+    #[test]
+    fn insert_room_and_sender_ids() {
+        let mut ruleset = ruleset();
+        let rule = |rule_id: &str| PushRule {
+            actions: vec![Action::DontNotify],
+            default: false,
+            enabled: true,
+            rule_id: rule_id.to_string(),
+        };
+
+        assert_eq!(
+            ruleset.insert(AnyPushRule::Room(rule("@bob:example.com")), None, None),
+            Err(RulesetError::InvalidRuleId("@bob:example.com".to_string()))
+        );
+        assert_eq!(
+            ruleset.insert(AnyPushRule::Sender(rule("!room:example.com")), None, None),
+            Err(RulesetError::InvalidRuleId("!room:example.com".to_string()))
+        );
+        assert!(ruleset
+            .insert(AnyPushRule::Room(rule("!room:example.com")), None, None)
+            .is_ok());
+        assert!(ruleset
+            .insert(AnyPushRule::Sender(rule("@bob:example.com")), None, None)
+            .is_ok());
+    }
+
+    #[test]
+    fn remove() {
+        let mut ruleset = ruleset();
+        ruleset
+            .insert(
+                AnyPushRule::Room(PushRule {
+                    actions: vec![Action::DontNotify],
+                    default: false,
+                    enabled: true,
+                    rule_id: "!n8f893n9:example.com".to_string(),
+                }),
+                None,
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(
+            ruleset.remove(RuleKind::Override, ".m.rule.master"),
+            Err(RulesetError::ServerDefaultRule(
+                ".m.rule.master".to_string()
+            ))
+        );
+        assert_eq!(
+            ruleset.remove(RuleKind::Sender, "!n8f893n9:example.com"),
+            Err(RulesetError::NotFound("!n8f893n9:example.com".to_string()))
+        );
+        assert!(ruleset
+            .remove(RuleKind::Room, "!n8f893n9:example.com")
+            .is_ok());
+        assert!(ruleset.room.is_empty());
+    }
+
+    #[test]
+    fn set_enabled_and_actions() {
+        let mut ruleset = ruleset();
+
+        ruleset
+            .set_enabled(RuleKind::Override, ".m.rule.master", true)
+            .unwrap();
+        ruleset
+            .set_actions(
+                RuleKind::Underride,
+                ".m.rule.message",
+                vec![Action::DontNotify],
+            )
+            .unwrap();
+
+        assert!(ruleset.override_rules[0].enabled);
+        assert_eq!(
+            ruleset
+                .underride
+                .iter()
+                .find(|rule| rule.rule_id == ".m.rule.message")
+                .unwrap()
+                .actions,
+            vec![Action::DontNotify]
+        );
+        assert_eq!(
+            ruleset.set_enabled(RuleKind::Content, "missing", false),
+            Err(RulesetError::NotFound("missing".to_string()))
+        );
+    }
+}
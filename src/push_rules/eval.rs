@@ -0,0 +1,707 @@
+//! Evaluation of push rules against room events.
+
+use js_int::{Int, UInt};
+use ruma_identifiers::UserId;
+use serde_json::{to_value, Value};
+
+use super::{
+    Action, ConditionalPushRule, EventMatchCondition, PatternedPushRule, PushCondition, PushRule,
+    RoomMemberCountCondition, Ruleset, SenderNotificationPermissionCondition,
+};
+use crate::{collections::all::RoomEvent, room::power_levels::PowerLevelsEventContent};
+
+/// The kind of a push rule, which determines its priority during evaluation.
+///
+/// Variants are listed from highest to lowest priority.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RuleKind {
+    /// User-configured rules that override all other kinds.
+    Override,
+
+    /// Rules that match against the body of a message.
+    Content,
+
+    /// Rules that apply to all messages in a given room.
+    Room,
+
+    /// Rules that apply to all messages from a given user.
+    Sender,
+
+    /// Identical to override rules, but with a lower priority than content, room and sender rules.
+    Underride,
+
+    /// Additional variants may be added in the future and will not be considered breaking changes
+    /// to ruma-events.
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl_enum! {
+    RuleKind {
+        Override => "override",
+        Content => "content",
+        Room => "room",
+        Sender => "sender",
+        Underride => "underride",
+    }
+}
+
+/// Information about the user and room an event is being evaluated for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PushContext {
+    /// The ID of the user whose push rules are being evaluated.
+    pub user_id: UserId,
+
+    /// The display name of the user in the room, if any.
+    pub user_display_name: Option<String>,
+
+    /// The number of members in the room.
+    pub member_count: UInt,
+
+    /// The current power levels of the room, if the room has any.
+    pub power_levels: Option<PowerLevelsEventContent>,
+}
+
+/// A push rule that matched an event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatchedPushRule<'a> {
+    /// The kind of the rule.
+    pub kind: RuleKind,
+
+    /// The ID of the rule.
+    pub rule_id: &'a str,
+
+    /// The actions to take for the event.
+    pub actions: &'a [Action],
+}
+
+impl Ruleset {
+    /// Finds the highest priority enabled rule that matches the given event.
+    ///
+    /// Rules are tried in the order override, content, room, sender and underride, and in the
+    /// order they appear within each kind. Returns `None` if no rule matches or the event was sent
+    /// by the user themselves, in which case the event should not generate a notification.
+    pub fn evaluate<'a>(
+        &'a self,
+        event: &RoomEvent,
+        context: &PushContext,
+    ) -> Option<MatchedPushRule<'a>> {
+        let event = to_value(event).ok()?;
+        let room_id = event.get("room_id").and_then(Value::as_str);
+        let sender = event.get("sender").and_then(Value::as_str);
+
+        if sender == Some(context.user_id.to_string().as_str()) {
+            return None;
+        }
+
+        let conditional = |kind, rules: &'a [ConditionalPushRule]| {
+            rules
+                .iter()
+                .find(|rule| {
+                    rule.enabled
+                        && rule
+                            .conditions
+                            .iter()
+                            .all(|condition| condition.applies(&event, context))
+                })
+                .map(|rule| MatchedPushRule {
+                    kind,
+                    rule_id: &rule.rule_id,
+                    actions: &rule.actions,
+                })
+        };
+
+        let content = |rules: &'a [PatternedPushRule]| {
+            rules
+                .iter()
+                .find(|rule| rule.enabled && rule.applies(&event))
+                .map(|rule| MatchedPushRule {
+                    kind: RuleKind::Content,
+                    rule_id: &rule.rule_id,
+                    actions: &rule.actions,
+                })
+        };
+
+        let simple = |kind, rules: &'a [PushRule], id: Option<&str>| {
+            rules
+                .iter()
+                .find(|rule| rule.enabled && Some(rule.rule_id.as_str()) == id)
+                .map(|rule| MatchedPushRule {
+                    kind,
+                    rule_id: &rule.rule_id,
+                    actions: &rule.actions,
+                })
+        };
+
+        conditional(RuleKind::Override, &self.override_rules)
+            .or_else(|| content(&self.content))
+            .or_else(|| simple(RuleKind::Room, &self.room, room_id))
+            .or_else(|| simple(RuleKind::Sender, &self.sender, sender))
+            .or_else(|| conditional(RuleKind::Underride, &self.underride))
+    }
+
+    /// Returns the actions to take for the given event.
+    ///
+    /// This is empty if no rule matches the event.
+    pub fn get_actions(&self, event: &RoomEvent, context: &PushContext) -> &[Action] {
+        self.evaluate(event, context)
+            .map(|matched| matched.actions)
+            .unwrap_or(&[])
+    }
+}
+
+impl PatternedPushRule {
+    /// Whether the rule's pattern matches the `content.body` of the event.
+    fn applies(&self, event: &Value) -> bool {
+        match lookup_str(event, "content.body") {
+            Some(body) => matches_words(&self.pattern, body),
+            None => false,
+        }
+    }
+}
+
+impl PushCondition {
+    /// Whether the condition holds for the given event, serialized as JSON.
+    fn applies(&self, event: &Value, context: &PushContext) -> bool {
+        match *self {
+            PushCondition::EventMatch(ref condition) => condition.applies(event),
+            PushCondition::ContainsDisplayName => {
+                let display_name = match context.user_display_name {
+                    Some(ref display_name) if !display_name.is_empty() => display_name,
+                    _ => return false,
+                };
+
+                match lookup_str(event, "content.body") {
+                    Some(body) => contains_words(body, display_name),
+                    None => false,
+                }
+            }
+            PushCondition::RoomMemberCount(ref condition) => {
+                condition.applies(context.member_count)
+            }
+            PushCondition::SenderNotificationPermission(ref condition) => {
+                match event.get("sender").and_then(Value::as_str) {
+                    Some(sender) => condition.applies(sender, context.power_levels.as_ref()),
+                    None => false,
+                }
+            }
+            PushCondition::__Nonexhaustive => false,
+        }
+    }
+}
+
+impl EventMatchCondition {
+    /// Whether the pattern matches the event's value at `key`.
+    ///
+    /// `content.body` is matched on word boundaries, every other key must match as a whole. The
+    /// match is case-insensitive.
+    fn applies(&self, event: &Value) -> bool {
+        let value = match lookup_str(event, &self.key) {
+            Some(value) => value,
+            None => return false,
+        };
+
+        if self.key == "content.body" {
+            matches_words(&self.pattern, value)
+        } else {
+            glob_matches(&lowercase_chars(&self.pattern), &lowercase_chars(value))
+        }
+    }
+}
+
+impl RoomMemberCountCondition {
+    /// Whether the given member count satisfies the condition.
+    ///
+    /// An `is` value that can't be parsed never matches.
+    fn applies(&self, member_count: UInt) -> bool {
+        let (operator, count) = ["==", "<=", ">=", "<", ">"]
+            .iter()
+            .find(|operator| self.is.starts_with(*operator))
+            .map_or(("==", self.is.as_str()), |operator| {
+                (*operator, &self.is[operator.len()..])
+            });
+
+        let count = match count.parse::<UInt>() {
+            Ok(count) => count,
+            Err(_) => return false,
+        };
+
+        match operator {
+            "<=" => member_count <= count,
+            ">=" => member_count >= count,
+            "<" => member_count < count,
+            ">" => member_count > count,
+            _ => member_count == count,
+        }
+    }
+}
+
+impl SenderNotificationPermissionCondition {
+    /// Whether the sender's power level is high enough for the notification type `key`.
+    fn applies(&self, sender: &str, power_levels: Option<&PowerLevelsEventContent>) -> bool {
+        let (sender_level, required_level) = match power_levels {
+            Some(power_levels) => {
+                let sender_level = power_levels
+                    .users
+                    .iter()
+                    .find(|(user_id, _)| user_id.to_string() == sender)
+                    .map_or(power_levels.users_default, |(_, level)| *level);
+
+                let required_level = match self.key.as_str() {
+                    "room" => power_levels.notifications.room,
+                    _ => Int::from(50),
+                };
+
+                (sender_level, required_level)
+            }
+            None => (Int::from(0), Int::from(50)),
+        };
+
+        sender_level >= required_level
+    }
+}
+
+/// Looks up the string at the dot-separated `path` in the given JSON object.
+fn lookup_str<'a>(value: &'a Value, path: &str) -> Option<&'a str> {
+    path.split('.')
+        .try_fold(value, |value, key| value.get(key))
+        .and_then(Value::as_str)
+}
+
+/// Whether `c` is part of a word, for the purposes of word boundary matching.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn lowercase_chars(s: &str) -> Vec<char> {
+    s.chars().flat_map(char::to_lowercase).collect()
+}
+
+/// Whether the glob `pattern` matches a run of whole words within `value`, ignoring case.
+fn matches_words(pattern: &str, value: &str) -> bool {
+    let pattern = lowercase_chars(pattern);
+    let value = lowercase_chars(value);
+
+    glob_search(
+        &pattern,
+        &value,
+        |start| start == 0 || !is_word_char(value[start - 1]),
+        |end| end == value.len() || !is_word_char(value[end]),
+    )
+}
+
+/// Whether `needle` appears as a run of whole words within `haystack`, ignoring case.
+fn contains_words(haystack: &str, needle: &str) -> bool {
+    let haystack = lowercase_chars(haystack);
+    let needle = lowercase_chars(needle);
+
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .any(|(start, window)| {
+            let end = start + needle.len();
+
+            window == needle.as_slice()
+                && (start == 0 || !is_word_char(haystack[start - 1]))
+                && (end == haystack.len() || !is_word_char(haystack[end]))
+        })
+}
+
+/// Whether the glob `pattern` matches all of `value`.
+///
+/// Supports `*` (any sequence), `?` (any single character) and character classes such as
+/// `[abc]`, `[a-z]` and `[!a]`.
+fn glob_matches(pattern: &[char], value: &[char]) -> bool {
+    glob_search(pattern, value, |start| start == 0, |end| end == value.len())
+}
+
+/// A part of a glob pattern.
+enum GlobToken<'a> {
+    /// `*`, matching any sequence of characters.
+    Star,
+
+    /// `?`, matching any character.
+    Any,
+
+    /// A character class, matching the characters the predicate accepts.
+    Class(Box<dyn Fn(char) -> bool + 'a>),
+
+    /// A character matching itself.
+    Char(char),
+}
+
+/// Whether the glob `pattern` matches a part of `value` that starts at a position accepted by
+/// `is_start` and ends at a position accepted by `is_end`.
+///
+/// The pattern is run as a nondeterministic automaton over `value`, whose states are the number
+/// of tokens matched so far, so this takes time proportional to the length of `value` times the
+/// length of `pattern` regardless of the wildcards in the pattern.
+fn glob_search(
+    pattern: &[char],
+    value: &[char],
+    is_start: impl Fn(usize) -> bool,
+    is_end: impl Fn(usize) -> bool,
+) -> bool {
+    let tokens = glob_tokens(pattern);
+
+    // `states[i]` is whether a match so far has matched the first `i` tokens.
+    let mut states = vec![false; tokens.len() + 1];
+    let mut next = vec![false; tokens.len() + 1];
+
+    for position in 0..=value.len() {
+        if is_start(position) {
+            states[0] = true;
+        }
+
+        // A `*` can match an empty sequence.
+        for i in 0..tokens.len() {
+            if states[i] {
+                if let GlobToken::Star = tokens[i] {
+                    states[i + 1] = true;
+                }
+            }
+        }
+
+        if states[tokens.len()] && is_end(position) {
+            return true;
+        }
+
+        let c = match value.get(position) {
+            Some(&c) => c,
+            None => break,
+        };
+
+        for state in next.iter_mut() {
+            *state = false;
+        }
+
+        for (i, token) in tokens.iter().enumerate() {
+            if !states[i] {
+                continue;
+            }
+
+            match *token {
+                GlobToken::Star => next[i] = true,
+                GlobToken::Any => next[i + 1] = true,
+                GlobToken::Class(ref matches) => next[i + 1] |= matches(c),
+                GlobToken::Char(p) => next[i + 1] |= p == c,
+            }
+        }
+
+        std::mem::swap(&mut states, &mut next);
+    }
+
+    false
+}
+
+/// Splits a glob pattern into its tokens.
+fn glob_tokens(mut pattern: &[char]) -> Vec<GlobToken<'_>> {
+    let mut tokens = Vec::new();
+
+    while let Some(&p) = pattern.first() {
+        let token = match p {
+            '*' => GlobToken::Star,
+            '?' => GlobToken::Any,
+            '[' => {
+                if let Some((matches, rest)) = parse_class(&pattern[1..]) {
+                    tokens.push(GlobToken::Class(matches));
+                    pattern = rest;
+                    continue;
+                }
+
+                GlobToken::Char('[')
+            }
+            p => GlobToken::Char(p),
+        };
+
+        tokens.push(token);
+        pattern = &pattern[1..];
+    }
+
+    tokens
+}
+
+/// Parses a character class following an opening `[`.
+///
+/// Returns a predicate for the class and the remainder of the pattern, or `None` if the class is
+/// not terminated, in which case the `[` is matched literally.
+#[allow(clippy::type_complexity)]
+fn parse_class(pattern: &[char]) -> Option<(Box<dyn Fn(char) -> bool + '_>, &[char])> {
+    let (negated, pattern) = match pattern.first() {
+        Some('!') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+
+    // A `]` directly after the opening bracket is part of the class.
+    let end = pattern.iter().skip(1).position(|&c| c == ']')? + 1;
+    let class = &pattern[..end];
+
+    let predicate = move |c: char| {
+        let mut i = 0;
+        let mut found = false;
+
+        while i < class.len() {
+            if i + 2 < class.len() && class[i + 1] == '-' {
+                found |= class[i] <= c && c <= class[i + 2];
+                i += 3;
+            } else {
+                found |= class[i] == c;
+                i += 1;
+            }
+        }
+
+        found != negated
+    };
+
+    Some((Box::new(predicate), &pattern[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, convert::TryFrom};
+
+    use js_int::{Int, UInt};
+    use ruma_identifiers::UserId;
+
+    use super::{glob_matches, lowercase_chars, matches_words, PushContext, RuleKind};
+    use crate::{
+        collections::all::RoomEvent,
+        push_rules::{
+            Action, ConditionalPushRule, EventMatchCondition, PatternedPushRule, PushCondition,
+            PushRule, RoomMemberCountCondition, Ruleset, SenderNotificationPermissionCondition,
+            Tweak,
+        },
+        room::power_levels::{NotificationPowerLevels, PowerLevelsEventContent},
+    };
+
+    fn message(body: &str) -> RoomEvent {
+        format!(
+            r#"{{
+                "content": {{"body": "{}", "msgtype": "m.text"}},
+                "event_id": "$h29iv0s8:example.com",
+                "origin_server_ts": 1,
+                "room_id": "!n8f893n9:example.com",
+                "sender": "@bob:example.com",
+                "type": "m.room.message"
+            }}"#,
+            body
+        )
+        .parse()
+        .unwrap()
+    }
+
+    fn context() -> PushContext {
+        PushContext {
+            user_id: UserId::try_from("@alice:example.com").unwrap(),
+            user_display_name: Some("Alice".to_string()),
+            member_count: UInt::from(3u32),
+            power_levels: None,
+        }
+    }
+
+    fn empty_ruleset() -> Ruleset {
+        Ruleset {
+            content: Vec::new(),
+            override_rules: Vec::new(),
+            room: Vec::new(),
+            sender: Vec::new(),
+            underride: Vec::new(),
+        }
+    }
+
+    fn conditional(rule_id: &str, conditions: Vec<PushCondition>) -> ConditionalPushRule {
+        ConditionalPushRule {
+            actions: vec![Action::Notify],
+            default: false,
+            enabled: true,
+            rule_id: rule_id.to_string(),
+            conditions,
+        }
+    }
+
+    fn matches(pattern: &str, value: &str) -> bool {
+        glob_matches(&lowercase_chars(pattern), &lowercase_chars(value))
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(matches("m.room.message", "m.room.message"));
+        assert!(!matches("m.room.message", "m.room.message.feedback"));
+        assert!(matches("m.room.*", "m.room.member"));
+        assert!(matches("m.?oom.member", "m.room.member"));
+        assert!(matches("[a-c]at", "bat"));
+        assert!(!matches("[!a-c]at", "bat"));
+        assert!(matches("[]]x", "]x"));
+        assert!(matches("[x", "[x"));
+        assert!(matches("M.ROOM.*", "m.room.member"));
+    }
+
+    #[test]
+    fn word_patterns_on_large_bodies() {
+        let body = "a ".repeat(32_000);
+
+        assert!(!matches_words("b", &body));
+        assert!(!matches_words("*a*a*b", &body));
+        assert!(!matches_words("a?b", &body));
+        assert!(matches_words("a a", &body));
+        assert!(matches_words("*a*a*b", &format!("{}b", body)));
+        assert!(!matches("*a*a*b", &body));
+    }
+
+    #[test]
+    fn own_events_never_match() {
+        let mut ruleset = empty_ruleset();
+        ruleset.underride.push(conditional("all", Vec::new()));
+
+        let mut context = context();
+        assert!(ruleset.evaluate(&message("hi"), &context).is_some());
+
+        context.user_id = UserId::try_from("@bob:example.com").unwrap();
+        assert!(ruleset.evaluate(&message("hi"), &context).is_none());
+        assert!(ruleset.get_actions(&message("hi"), &context).is_empty());
+    }
+
+    #[test]
+    fn override_rules_take_priority() {
+        let mut ruleset = empty_ruleset();
+        ruleset.content.push(PatternedPushRule {
+            actions: vec![Action::SetTweak(Tweak::Highlight { value: true })],
+            default: false,
+            enabled: true,
+            rule_id: "hello".to_string(),
+            pattern: "hello".to_string(),
+        });
+        ruleset.override_rules.push(conditional(
+            "override",
+            vec![PushCondition::EventMatch(EventMatchCondition {
+                key: "type".to_string(),
+                pattern: "m.room.message".to_string(),
+            })],
+        ));
+
+        let matched = ruleset.evaluate(&message("hello"), &context()).unwrap();
+        assert_eq!(matched.kind, RuleKind::Override);
+        assert_eq!(matched.rule_id, "override");
+        assert_eq!(matched.actions, &[Action::Notify]);
+
+        ruleset.override_rules[0].enabled = false;
+
+        let matched = ruleset.evaluate(&message("hello"), &context()).unwrap();
+        assert_eq!(matched.kind, RuleKind::Content);
+    }
+
+    #[test]
+    fn content_rules_match_whole_words() {
+        let mut ruleset = empty_ruleset();
+        ruleset.content.push(PatternedPushRule {
+            actions: vec![Action::Notify],
+            default: false,
+            enabled: true,
+            rule_id: "cake".to_string(),
+            pattern: "cake*lie".to_string(),
+        });
+
+        assert!(ruleset
+            .evaluate(&message("the Cake is a lie!"), &context())
+            .is_some());
+        assert!(ruleset
+            .evaluate(&message("pancake is a lie"), &context())
+            .is_none());
+        assert!(ruleset
+            .evaluate(&message("the cake is a lier"), &context())
+            .is_none());
+    }
+
+    #[test]
+    fn room_and_sender_rules_match_ids() {
+        let rule = |rule_id: &str| PushRule {
+            actions: vec![Action::DontNotify],
+            default: false,
+            enabled: true,
+            rule_id: rule_id.to_string(),
+        };
+
+        let mut ruleset = empty_ruleset();
+        ruleset.sender.push(rule("@bob:example.com"));
+
+        let matched = ruleset.evaluate(&message("hi"), &context()).unwrap();
+        assert_eq!(matched.kind, RuleKind::Sender);
+
+        ruleset.room.push(rule("!n8f893n9:example.com"));
+
+        let matched = ruleset.evaluate(&message("hi"), &context()).unwrap();
+        assert_eq!(matched.kind, RuleKind::Room);
+        assert_eq!(
+            ruleset.get_actions(&message("hi"), &context()),
+            &[Action::DontNotify]
+        );
+    }
+
+    #[test]
+    fn contains_display_name_condition() {
+        let mut ruleset = empty_ruleset();
+        ruleset.underride.push(conditional(
+            "name",
+            vec![PushCondition::ContainsDisplayName],
+        ));
+
+        assert!(ruleset
+            .evaluate(&message("hey alice"), &context())
+            .is_some());
+        assert!(ruleset
+            .evaluate(&message("hey malice"), &context())
+            .is_none());
+
+        let mut context = context();
+        context.user_display_name = None;
+
+        assert!(ruleset.evaluate(&message("hey alice"), &context).is_none());
+    }
+
+    #[test]
+    fn room_member_count_condition() {
+        let applies = |is: &str, count: u32| {
+            RoomMemberCountCondition { is: is.to_string() }.applies(UInt::from(count))
+        };
+
+        assert!(applies("2", 2));
+        assert!(applies("==2", 2));
+        assert!(!applies("2", 3));
+        assert!(applies("<3", 2));
+        assert!(!applies("<3", 3));
+        assert!(applies("<=3", 3));
+        assert!(applies(">=3", 3));
+        assert!(applies(">2", 3));
+        assert!(!applies(">foo", 3));
+    }
+
+    #[test]
+    fn sender_notification_permission_condition() {
+        let condition = SenderNotificationPermissionCondition {
+            key: "room".to_string(),
+        };
+        let mut power_levels = PowerLevelsEventContent {
+            ban: Int::from(50),
+            events: HashMap::new(),
+            events_default: Int::from(0),
+            invite: Int::from(50),
+            kick: Int::from(50),
+            redact: Int::from(50),
+            state_default: Int::from(50),
+            users: HashMap::new(),
+            users_default: Int::from(0),
+            notifications: NotificationPowerLevels {
+                room: Int::from(50),
+            },
+        };
+
+        assert!(!condition.applies("@bob:example.com", Some(&power_levels)));
+        assert!(!condition.applies("@bob:example.com", None));
+
+        power_levels
+            .users
+            .insert(UserId::try_from("@bob:example.com").unwrap(), Int::from(50));
+
+        assert!(condition.applies("@bob:example.com", Some(&power_levels)));
+    }
+}
@@ -0,0 +1,255 @@
+//! The predefined push rules that homeservers must provide for every user.
+
+use ruma_identifiers::UserId;
+
+use super::{
+    Action, ConditionalPushRule, EventMatchCondition, PatternedPushRule, PushCondition,
+    RoomMemberCountCondition, Ruleset, SenderNotificationPermissionCondition, Tweak,
+};
+
+impl Ruleset {
+    /// Creates the default ruleset a homeserver provides for the given user.
+    ///
+    /// This contains all of the predefined rules from the specification, in the order the
+    /// specification lists them, with `default` set to `true`.
+    pub fn server_default(user_id: &UserId) -> Self {
+        Self {
+            content: vec![PatternedPushRule {
+                actions: vec![Action::Notify, sound("default"), highlight(true)],
+                default: true,
+                enabled: true,
+                rule_id: ".m.rule.contains_user_name".to_string(),
+                pattern: user_id.localpart().to_string(),
+            }],
+            override_rules: vec![
+                ConditionalPushRule {
+                    actions: vec![Action::DontNotify],
+                    default: true,
+                    enabled: false,
+                    rule_id: ".m.rule.master".to_string(),
+                    conditions: Vec::new(),
+                },
+                predefined(
+                    ".m.rule.suppress_notices",
+                    vec![Action::DontNotify],
+                    vec![event_match("content.msgtype", "m.notice")],
+                ),
+                predefined(
+                    ".m.rule.invite_for_me",
+                    vec![Action::Notify, sound("default"), highlight(false)],
+                    vec![
+                        event_match("type", "m.room.member"),
+                        event_match("content.membership", "invite"),
+                        event_match("state_key", &user_id.to_string()),
+                    ],
+                ),
+                predefined(
+                    ".m.rule.member_event",
+                    vec![Action::DontNotify],
+                    vec![event_match("type", "m.room.member")],
+                ),
+                predefined(
+                    ".m.rule.contains_display_name",
+                    vec![Action::Notify, sound("default"), highlight(true)],
+                    vec![PushCondition::ContainsDisplayName],
+                ),
+                predefined(
+                    ".m.rule.tombstone",
+                    vec![Action::Notify, highlight(true)],
+                    vec![
+                        event_match("type", "m.room.tombstone"),
+                        event_match("state_key", ""),
+                    ],
+                ),
+                predefined(
+                    ".m.rule.roomnotif",
+                    vec![Action::Notify, highlight(true)],
+                    vec![
+                        event_match("content.body", "@room"),
+                        PushCondition::SenderNotificationPermission(
+                            SenderNotificationPermissionCondition {
+                                key: "room".to_string(),
+                            },
+                        ),
+                    ],
+                ),
+            ],
+            room: Vec::new(),
+            sender: Vec::new(),
+            underride: vec![
+                predefined(
+                    ".m.rule.call",
+                    vec![Action::Notify, sound("ring"), highlight(false)],
+                    vec![event_match("type", "m.call.invite")],
+                ),
+                predefined(
+                    ".m.rule.encrypted_room_one_to_one",
+                    vec![Action::Notify, sound("default"), highlight(false)],
+                    vec![member_count("2"), event_match("type", "m.room.encrypted")],
+                ),
+                predefined(
+                    ".m.rule.room_one_to_one",
+                    vec![Action::Notify, sound("default"), highlight(false)],
+                    vec![member_count("2"), event_match("type", "m.room.message")],
+                ),
+                predefined(
+                    ".m.rule.message",
+                    vec![Action::Notify, highlight(false)],
+                    vec![event_match("type", "m.room.message")],
+                ),
+                predefined(
+                    ".m.rule.encrypted",
+                    vec![Action::Notify, highlight(false)],
+                    vec![event_match("type", "m.room.encrypted")],
+                ),
+            ],
+        }
+    }
+}
+
+/// Creates an enabled, predefined rule with the given conditions.
+fn predefined(
+    rule_id: &str,
+    actions: Vec<Action>,
+    conditions: Vec<PushCondition>,
+) -> ConditionalPushRule {
+    ConditionalPushRule {
+        actions,
+        default: true,
+        enabled: true,
+        rule_id: rule_id.to_string(),
+        conditions,
+    }
+}
+
+fn event_match(key: &str, pattern: &str) -> PushCondition {
+    PushCondition::EventMatch(EventMatchCondition {
+        key: key.to_string(),
+        pattern: pattern.to_string(),
+    })
+}
+
+fn member_count(is: &str) -> PushCondition {
+    PushCondition::RoomMemberCount(RoomMemberCountCondition { is: is.to_string() })
+}
+
+fn sound(value: &str) -> Action {
+    Action::SetTweak(Tweak::Sound {
+        value: value.to_string(),
+    })
+}
+
+fn highlight(value: bool) -> Action {
+    Action::SetTweak(Tweak::Highlight { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use js_int::UInt;
+    use ruma_identifiers::UserId;
+
+    use crate::{
+        collections::all::RoomEvent,
+        push_rules::{PushContext, Ruleset},
+    };
+
+    fn context(member_count: u32) -> PushContext {
+        PushContext {
+            user_id: UserId::try_from("@alice:example.com").unwrap(),
+            user_display_name: Some("Alice Liddell".to_string()),
+            member_count: UInt::from(member_count),
+            power_levels: None,
+        }
+    }
+
+    fn rule_id_for(json: &str, member_count: u32) -> Option<String> {
+        let ruleset = Ruleset::server_default(&UserId::try_from("@alice:example.com").unwrap());
+        let event = json.parse::<RoomEvent>().unwrap();
+
+        ruleset
+            .evaluate(&event, &context(member_count))
+            .map(|matched| matched.rule_id.to_string())
+    }
+
+    fn message(msgtype: &str, body: &str) -> String {
+        format!(
+            r#"{{
+                "content": {{"body": "{}", "msgtype": "{}"}},
+                "event_id": "$h29iv0s8:example.com",
+                "origin_server_ts": 1,
+                "room_id": "!n8f893n9:example.com",
+                "sender": "@bob:example.com",
+                "type": "m.room.message"
+            }}"#,
+            body, msgtype
+        )
+    }
+
+    #[test]
+    fn all_rules_are_default() {
+        let ruleset = Ruleset::server_default(&UserId::try_from("@alice:example.com").unwrap());
+
+        assert!(ruleset.override_rules.iter().all(|rule| rule.default));
+        assert!(ruleset.content.iter().all(|rule| rule.default));
+        assert!(ruleset.underride.iter().all(|rule| rule.default));
+        assert_eq!(ruleset.content[0].pattern, "alice");
+        assert!(!ruleset.override_rules[0].enabled);
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(
+            rule_id_for(&message("m.text", "hello"), 2),
+            Some(".m.rule.room_one_to_one".to_string())
+        );
+        assert_eq!(
+            rule_id_for(&message("m.text", "hello"), 5),
+            Some(".m.rule.message".to_string())
+        );
+        assert_eq!(
+            rule_id_for(&message("m.notice", "hello alice"), 5),
+            Some(".m.rule.suppress_notices".to_string())
+        );
+        assert_eq!(
+            rule_id_for(&message("m.text", "hello Alice Liddell"), 5),
+            Some(".m.rule.contains_display_name".to_string())
+        );
+        assert_eq!(
+            rule_id_for(&message("m.text", "ping alice"), 5),
+            Some(".m.rule.contains_user_name".to_string())
+        );
+        assert_eq!(
+            rule_id_for(&message("m.text", "@room hi"), 5),
+            Some(".m.rule.message".to_string())
+        );
+    }
+
+    #[test]
+    fn invite_for_me() {
+        let invite = |state_key: &str| {
+            format!(
+                r#"{{
+                    "content": {{"membership": "invite"}},
+                    "event_id": "$h29iv0s8:example.com",
+                    "origin_server_ts": 1,
+                    "room_id": "!n8f893n9:example.com",
+                    "sender": "@bob:example.com",
+                    "state_key": "{}",
+                    "type": "m.room.member"
+                }}"#,
+                state_key
+            )
+        };
+
+        assert_eq!(
+            rule_id_for(&invite("@alice:example.com"), 5),
+            Some(".m.rule.invite_for_me".to_string())
+        );
+        assert_eq!(
+            rule_id_for(&invite("@carl:example.com"), 5),
+            Some(".m.rule.member_event".to_string())
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! Types for the *m.reaction* event.
+
+use std::collections::{HashMap, HashSet};
+
+use ruma_events_macros::ruma_event;
+use ruma_identifiers::{EventId, UserId};
+use serde::{
+    de::Error as _, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{from_value, Map, Value};
+
+use crate::{collections::all::RoomEvent, room::redaction::RedactionEvent};
+
+ruma_event! {
+    /// A reaction to another event, usually an emoji.
+    ReactionEvent {
+        kind: RoomEvent,
+        event_type: Reaction,
+        content: {
+            /// Information about the event being reacted to.
+            ///
+            /// This is `None` if the reaction has been redacted.
+            #[serde(rename = "m.relates_to")]
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub relates_to: Option<Annotation>,
+        },
+    }
+}
+
+/// An annotation of another event, sent as a relation with `rel_type` set to `m.annotation`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotation {
+    /// The event being annotated.
+    pub event_id: EventId,
+
+    /// The annotation itself, e.g. the emoji used to react to the event.
+    pub key: String,
+}
+
+impl Serialize for Annotation {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Annotation", 3)?;
+
+        state.serialize_field("event_id", &self.event_id)?;
+        state.serialize_field("key", &self.key)?;
+        state.serialize_field("rel_type", "m.annotation")?;
+
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Annotation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value: Map<String, Value> = Deserialize::deserialize(deserializer)?;
+
+        match value.get("rel_type").and_then(Value::as_str) {
+            Some("m.annotation") => {}
+            Some(rel_type) => {
+                return Err(D::Error::custom(format!(
+                    "expected rel_type `m.annotation`, found `{}`",
+                    rel_type
+                )))
+            }
+            None => return Err(D::Error::missing_field("rel_type")),
+        }
+
+        let event_id = match value.get("event_id") {
+            Some(event_id) => from_value(event_id.clone()).map_err(D::Error::custom)?,
+            None => return Err(D::Error::missing_field("event_id")),
+        };
+
+        let key = match value.get("key") {
+            Some(key) => from_value(key.clone()).map_err(D::Error::custom)?,
+            None => return Err(D::Error::missing_field("key")),
+        };
+
+        Ok(Annotation { event_id, key })
+    }
+}
+
+/// Counts the reactions to events, taking redactions of reactions into account.
+///
+/// Reactions and redactions can be added in any order. Each user is counted at most once per
+/// event and key, so duplicate reactions don't inflate the counts.
+#[derive(Clone, Debug, Default)]
+pub struct ReactionAggregator {
+    /// The reactions that have been added, by event ID.
+    reactions: HashMap<EventId, (UserId, Annotation)>,
+
+    /// The event IDs of all events that have been redacted.
+    redacted: HashSet<EventId>,
+}
+
+impl ReactionAggregator {
+    /// Creates an aggregator without any reactions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an event to the aggregation.
+    ///
+    /// *m.reaction* events are counted, and *m.room.redaction* events remove the reaction they
+    /// redact. All other events are ignored.
+    pub fn add_event(&mut self, event: &RoomEvent) {
+        match *event {
+            RoomEvent::Reaction(ref reaction) => self.add_reaction(reaction),
+            RoomEvent::RoomRedaction(ref redaction) => self.add_redaction(redaction),
+            _ => {}
+        }
+    }
+
+    /// Adds a reaction to the aggregation.
+    ///
+    /// Redacted reactions, which no longer say what they react to, are ignored.
+    pub fn add_reaction(&mut self, reaction: &ReactionEvent) {
+        if let Some(ref annotation) = reaction.content.relates_to {
+            self.reactions.insert(
+                reaction.event_id.clone(),
+                (reaction.sender.clone(), annotation.clone()),
+            );
+        }
+    }
+
+    /// Adds a redaction to the aggregation, removing the reaction it redacts, if any.
+    pub fn add_redaction(&mut self, redaction: &RedactionEvent) {
+        self.redacted.insert(redaction.redacts.clone());
+    }
+
+    /// The number of reactions to the given event, by key.
+    pub fn counts_for(&self, event_id: &EventId) -> HashMap<String, usize> {
+        self.counts().remove(event_id).unwrap_or_default()
+    }
+
+    /// The number of reactions to every event that has any, by key.
+    pub fn counts(&self) -> HashMap<EventId, HashMap<String, usize>> {
+        let mut senders: HashMap<(&EventId, &str), HashSet<&UserId>> = HashMap::new();
+
+        for (event_id, (sender, annotation)) in &self.reactions {
+            if self.redacted.contains(event_id) {
+                continue;
+            }
+
+            senders
+                .entry((&annotation.event_id, &annotation.key))
+                .or_default()
+                .insert(sender);
+        }
+
+        let mut counts: HashMap<EventId, HashMap<String, usize>> = HashMap::new();
+
+        for ((event_id, key), senders) in senders {
+            counts
+                .entry(event_id.clone())
+                .or_default()
+                .insert(key.to_string(), senders.len());
+        }
+
+        counts
+    }
+}
+
+/// Counts the reactions to events in the given events.
+///
+/// See `ReactionAggregator` for details.
+pub fn aggregate<'a, I>(events: I) -> HashMap<EventId, HashMap<String, usize>>
+where
+    I: IntoIterator<Item = &'a RoomEvent>,
+{
+    let mut aggregator = ReactionAggregator::new();
+
+    for event in events {
+        aggregator.add_event(event);
+    }
+
+    aggregator.counts()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ruma_identifiers::EventId;
+    use serde_json::{json, to_value};
+
+    use super::{aggregate, ReactionEvent};
+    use crate::collections::all::RoomEvent;
+
+    fn reaction(event_id: &str, sender: &str, target: &str, key: &str) -> RoomEvent {
+        json!({
+            "content": {
+                "m.relates_to": {"event_id": target, "key": key, "rel_type": "m.annotation"}
+            },
+            "event_id": event_id,
+            "origin_server_ts": 1,
+            "room_id": "!room:example.com",
+            "sender": sender,
+            "type": "m.reaction"
+        })
+        .to_string()
+        .parse()
+        .unwrap()
+    }
+
+    fn redaction(redacts: &str) -> RoomEvent {
+        json!({
+            "content": {},
+            "event_id": "$redaction:example.com",
+            "origin_server_ts": 2,
+            "redacts": redacts,
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "type": "m.room.redaction"
+        })
+        .to_string()
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn serialization() {
+        let event = reaction(
+            "$r1:example.com",
+            "@alice:example.com",
+            "$m:example.com",
+            "👍",
+        );
+
+        match event {
+            RoomEvent::Reaction(ref reaction) => {
+                assert_eq!(reaction.content.relates_to.as_ref().unwrap().key, "👍");
+                assert_eq!(
+                    to_value(reaction).unwrap()["content"],
+                    json!({
+                        "m.relates_to": {
+                            "event_id": "$m:example.com",
+                            "key": "👍",
+                            "rel_type": "m.annotation"
+                        }
+                    })
+                );
+            }
+            _ => panic!("expected a reaction"),
+        }
+    }
+
+    #[test]
+    fn wrong_relation_type() {
+        assert!(json!({
+            "content": {
+                "m.relates_to": {"event_id": "$m:example.com", "key": "👍", "rel_type": "m.replace"}
+            },
+            "event_id": "$r1:example.com",
+            "origin_server_ts": 1,
+            "sender": "@alice:example.com",
+            "type": "m.reaction"
+        })
+        .to_string()
+        .parse::<ReactionEvent>()
+        .is_err());
+    }
+
+    #[test]
+    fn aggregation() {
+        let events = vec![
+            reaction(
+                "$r1:example.com",
+                "@alice:example.com",
+                "$m1:example.com",
+                "👍",
+            ),
+            reaction(
+                "$r2:example.com",
+                "@bob:example.com",
+                "$m1:example.com",
+                "👍",
+            ),
+            reaction(
+                "$r3:example.com",
+                "@bob:example.com",
+                "$m1:example.com",
+                "👍",
+            ),
+            reaction(
+                "$r4:example.com",
+                "@carl:example.com",
+                "$m1:example.com",
+                "🎉",
+            ),
+            reaction(
+                "$r5:example.com",
+                "@carl:example.com",
+                "$m2:example.com",
+                "👍",
+            ),
+            redaction("$r4:example.com"),
+            redaction("$r6:example.com"),
+            reaction(
+                "$r6:example.com",
+                "@alice:example.com",
+                "$m2:example.com",
+                "👍",
+            ),
+        ];
+
+        let counts = aggregate(&events);
+        let m1 = EventId::try_from("$m1:example.com").unwrap();
+        let m2 = EventId::try_from("$m2:example.com").unwrap();
+
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&m1].len(), 1);
+        assert_eq!(counts[&m1]["👍"], 2);
+        assert_eq!(counts[&m2]["👍"], 1);
+    }
+
+    #[test]
+    fn redacted_reaction() {
+        let redacted: RoomEvent = json!({
+            "content": {},
+            "event_id": "$r1:example.com",
+            "origin_server_ts": 1,
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "type": "m.reaction",
+            "unsigned": {
+                "redacted_because": {
+                    "content": {},
+                    "event_id": "$redaction:example.com",
+                    "origin_server_ts": 2,
+                    "redacts": "$r1:example.com",
+                    "sender": "@alice:example.com",
+                    "type": "m.room.redaction"
+                }
+            }
+        })
+        .to_string()
+        .parse()
+        .unwrap();
+
+        match redacted {
+            RoomEvent::Reaction(ref reaction) => {
+                assert!(reaction.content.relates_to.is_none());
+                assert_eq!(to_value(reaction).unwrap()["content"], json!({}));
+            }
+            _ => panic!("expected a reaction"),
+        }
+
+        let events = vec![
+            redacted,
+            reaction(
+                "$r2:example.com",
+                "@bob:example.com",
+                "$m1:example.com",
+                "👍",
+            ),
+        ];
+
+        let counts = aggregate(&events);
+        let m1 = EventId::try_from("$m1:example.com").unwrap();
+
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts[&m1]["👍"], 1);
+    }
+}
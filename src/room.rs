@@ -2,10 +2,25 @@
 //!
 //! This module also contains types shared by events in its child namespaces.
 
-use std::collections::HashMap;
+#[cfg(feature = "crypto")]
+use std::io::Read;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
 
+#[cfg(feature = "crypto")]
+use aes::{
+    cipher::{KeyIvInit, StreamCipher},
+    Aes256,
+};
 use js_int::UInt;
+#[cfg(feature = "crypto")]
+use rand::{rngs::OsRng, RngCore};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "crypto")]
+use sha2::{Digest, Sha256};
 
 pub mod aliases;
 pub mod avatar;
@@ -103,6 +118,149 @@ pub struct EncryptedFile {
     pub v: String,
 }
 
+impl EncryptedFile {
+    /// Encrypts a file with a new random key and initialization vector.
+    ///
+    /// Returns the ciphertext to upload, and the `EncryptedFile` to send with the event. Its `url`
+    /// is empty, and should be set to the content URI of the uploaded ciphertext.
+    ///
+    /// This method is only available with the `crypto` feature.
+    #[cfg(feature = "crypto")]
+    pub fn encrypt<R>(mut reader: R) -> Result<(Vec<u8>, Self), AttachmentError>
+    where
+        R: Read,
+    {
+        let mut key = [0; 32];
+        let mut iv = [0; 16];
+
+        OsRng.fill_bytes(&mut key);
+        // Only the first half of the initialization vector is random, so that the 64-bit counter
+        // in the second half can't wrap around.
+        OsRng.fill_bytes(&mut iv[..8]);
+
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .map_err(|error| AttachmentError(error.to_string()))?;
+
+        apply_keystream(&key, &iv, &mut data);
+
+        let mut hashes = HashMap::new();
+        hashes.insert(
+            "sha256".to_string(),
+            base64::encode_config(Sha256::digest(&data), base64::STANDARD_NO_PAD),
+        );
+
+        let file = Self {
+            url: String::new(),
+            key: JsonWebKey {
+                kty: "oct".to_string(),
+                key_ops: vec!["encrypt".to_string(), "decrypt".to_string()],
+                alg: "A256CTR".to_string(),
+                k: base64::encode_config(key, base64::URL_SAFE_NO_PAD),
+                ext: true,
+            },
+            iv: base64::encode_config(iv, base64::STANDARD_NO_PAD),
+            hashes,
+            v: "v2".to_string(),
+        };
+
+        Ok((data, file))
+    }
+
+    /// Decrypts the downloaded ciphertext of the file.
+    ///
+    /// Fails if the file is not valid or the SHA-256 hash of the ciphertext doesn't match, in
+    /// which case nothing is decrypted.
+    ///
+    /// This method is only available with the `crypto` feature.
+    #[cfg(feature = "crypto")]
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, AttachmentError> {
+        let (key, iv, hash) = self.decode()?;
+
+        if Sha256::digest(ciphertext)[..] != hash[..] {
+            return Err(AttachmentError(
+                "the SHA-256 hash of the ciphertext does not match".to_string(),
+            ));
+        }
+
+        let mut data = ciphertext.to_vec();
+        apply_keystream(&key, &iv, &mut data);
+
+        Ok(data)
+    }
+
+    /// Checks that the file uses version 2 of the encrypted attachments protocol, a 256-bit
+    /// `A256CTR` key that can be used to encrypt and decrypt, a 128-bit initialization vector and
+    /// a 256-bit SHA-256 hash.
+    ///
+    /// This method is only available with the `crypto` feature.
+    #[cfg(feature = "crypto")]
+    pub fn validate(&self) -> Result<(), AttachmentError> {
+        self.decode().map(|_| ())
+    }
+
+    /// Validates the file, returning its decoded key, initialization vector and SHA-256 hash.
+    #[cfg(feature = "crypto")]
+    fn decode(&self) -> Result<DecodedFile, AttachmentError> {
+        if self.v != "v2" {
+            return Err(AttachmentError(format!(
+                "unsupported encrypted attachment version {}",
+                self.v
+            )));
+        }
+
+        let key = &self.key;
+
+        if key.kty != "oct" {
+            return Err(AttachmentError(format!(
+                "the key type must be oct, found {}",
+                key.kty
+            )));
+        }
+
+        if key.alg != "A256CTR" {
+            return Err(AttachmentError(format!(
+                "the key algorithm must be A256CTR, found {}",
+                key.alg
+            )));
+        }
+
+        for operation in &["encrypt", "decrypt"] {
+            if !key.key_ops.iter().any(|key_op| key_op == operation) {
+                return Err(AttachmentError(format!(
+                    "the key operations must include {}",
+                    operation
+                )));
+            }
+        }
+
+        if !key.ext {
+            return Err(AttachmentError("the key must be extractable".to_string()));
+        }
+
+        let hash = self
+            .hashes
+            .get("sha256")
+            .ok_or_else(|| AttachmentError("the file has no SHA-256 hash".to_string()))?;
+
+        Ok((
+            decode_bytes(&key.k, base64::URL_SAFE_NO_PAD, 32, "key")?,
+            decode_bytes(
+                &self.iv,
+                base64::STANDARD_NO_PAD,
+                16,
+                "initialization vector",
+            )?,
+            decode_bytes(hash, base64::STANDARD_NO_PAD, 32, "SHA-256 hash")?,
+        ))
+    }
+}
+
+/// The decoded key, initialization vector and SHA-256 hash of an `EncryptedFile`.
+#[cfg(feature = "crypto")]
+type DecodedFile = (Vec<u8>, Vec<u8>, Vec<u8>);
+
 /// A [JSON Web Key](https://tools.ietf.org/html/rfc7517#appendix-A.3) object.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct JsonWebKey {
@@ -122,3 +280,154 @@ pub struct JsonWebKey {
     /// [W3C extension](https://w3c.github.io/webcrypto/#iana-section-jwk).
     pub ext: bool,
 }
+
+/// An error when validating, encrypting or decrypting an `EncryptedFile`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttachmentError(String);
+
+impl AttachmentError {
+    /// A message describing the error.
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for AttachmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for AttachmentError {}
+
+/// Decodes unpadded base64 of the given length in bytes, also accepting padding.
+#[cfg(feature = "crypto")]
+fn decode_bytes(
+    encoded: &str,
+    config: base64::Config,
+    length: usize,
+    name: &str,
+) -> Result<Vec<u8>, AttachmentError> {
+    let bytes = base64::decode_config(encoded.trim_end_matches('='), config)
+        .map_err(|error| AttachmentError(format!("invalid {}: {}", name, error)))?;
+
+    if bytes.len() == length {
+        Ok(bytes)
+    } else {
+        Err(AttachmentError(format!(
+            "the {} must be {} bits long",
+            name,
+            length * 8
+        )))
+    }
+}
+
+/// Encrypts or decrypts data in place with AES-256 in counter mode, using the whole initialization
+/// vector as a 128-bit big-endian counter.
+#[cfg(feature = "crypto")]
+fn apply_keystream(key: &[u8], iv: &[u8], data: &mut [u8]) {
+    ctr::Ctr128BE::<Aes256>::new_from_slices(key, iv)
+        .expect("the key and initialization vector have been validated")
+        .apply_keystream(data);
+}
+
+#[cfg(all(test, feature = "crypto"))]
+mod tests {
+    use serde_json::{from_value, json};
+
+    use super::EncryptedFile;
+
+    #[test]
+    fn validation() {
+        let mut json = json!({
+            "url": "mxc://example.com/file",
+            "key": {
+                "kty": "oct",
+                "key_ops": ["encrypt", "decrypt"],
+                "alg": "A256CTR",
+                "k": "YAPrEBXKcb4rc67whXd3gR81LAc7YQjXLZgQowkU3_Q",
+                "ext": true
+            },
+            "iv": "8PHy8/T19vf4+fr7/P3+/w",
+            "hashes": {"sha256": "pnpqdUc9u4nmiBRI9u8xGbJL2DwADy1A9R4Ct/Z3h4U"},
+            "v": "v2"
+        });
+
+        let file: EncryptedFile = from_value(json.clone()).unwrap();
+        assert!(file.validate().is_ok());
+
+        for &(pointer, ref value) in &[
+            ("/v", json!("v1")),
+            ("/key/kty", json!("RSA")),
+            ("/key/alg", json!("A128CTR")),
+            ("/key/key_ops", json!(["encrypt"])),
+            ("/key/ext", json!(false)),
+            ("/key/k", json!("YAPrEBXKcb4rc67whXd3gR81LAc7YQjXLZgQowkU")),
+            ("/iv", json!("not base64!")),
+            ("/hashes", json!({})),
+        ] {
+            let mut invalid = json.clone();
+            *invalid.pointer_mut(pointer).unwrap() = value.clone();
+
+            let file: EncryptedFile = from_value(invalid).unwrap();
+            assert!(file.validate().is_err(), "{} was not rejected", pointer);
+        }
+
+        json["iv"] = json!("8PHy8/T19vf4+fr7/P3+/w==");
+
+        let file: EncryptedFile = from_value(json).unwrap();
+        assert!(file.validate().is_ok());
+    }
+
+    #[test]
+    fn nist_test_vector() {
+        // The first block of the AES-256 CTR example in NIST SP 800-38A, F.5.5.
+        let file: EncryptedFile = from_value(json!({
+            "url": "mxc://example.com/file",
+            "key": {
+                "kty": "oct",
+                "key_ops": ["encrypt", "decrypt"],
+                "alg": "A256CTR",
+                "k": "YD3rEBXKcb4rc67whX13gR81LAc7YQjXLZgQowkU3_Q",
+                "ext": true
+            },
+            "iv": "8PHy8/T19vf4+fr7/P3+/w",
+            "hashes": {"sha256": "tkEAvV+cSsTWQNMQu3a21Y87/8yAiyTOaF9Bj4Es+VQ"},
+            "v": "v2"
+        }))
+        .unwrap();
+
+        let ciphertext = [
+            0x60, 0x1e, 0xc3, 0x13, 0x77, 0x57, 0x89, 0xa5, 0xb7, 0xa7, 0xf5, 0x04, 0xbb, 0xf3,
+            0xd2, 0x28,
+        ];
+
+        assert_eq!(
+            file.decrypt(&ciphertext).unwrap(),
+            [
+                0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+                0x17, 0x2a,
+            ]
+        );
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let plaintext = b"the file contents".to_vec();
+        let (ciphertext, mut file) = EncryptedFile::encrypt(&plaintext[..]).unwrap();
+
+        assert!(file.validate().is_ok());
+        assert_eq!(file.v, "v2");
+        assert_ne!(ciphertext, plaintext);
+        assert_eq!(file.decrypt(&ciphertext).unwrap(), plaintext);
+
+        let mut tampered = ciphertext.clone();
+        tampered[0] ^= 1;
+
+        assert!(file.decrypt(&tampered).is_err());
+
+        file.key.alg = "A128CTR".to_string();
+
+        assert!(file.decrypt(&ciphertext).is_err());
+    }
+}
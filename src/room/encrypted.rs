@@ -5,9 +5,9 @@ use std::{convert::TryFrom, str::FromStr};
 use js_int::UInt;
 use ruma_identifiers::{DeviceId, EventId, RoomId, UserId};
 use serde::{de::Error, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
-use serde_json::{from_value, Value};
+use serde_json::{from_value, json, Value};
 
-use crate::{Algorithm, Event, EventType, InnerInvalidEvent, InvalidEvent, RoomEvent};
+use crate::{collections, Algorithm, Event, EventType, InnerInvalidEvent, InvalidEvent, RoomEvent};
 
 /// This event type is used when sending encrypted events.
 ///
@@ -336,11 +336,174 @@ pub struct MegolmV1AesSha2Content {
     pub session_id: String,
 }
 
+/// The decrypted payload of an event encrypted with *m.olm.v1.curve25519-aes-sha2*.
+///
+/// Olm is used for to-device events, and the payload carries the identities of the sender and
+/// recipient so they can be checked against the ones of the device that decrypted it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OlmV1Curve25519AesSha2Plaintext {
+    /// The user ID of the sender.
+    pub sender: UserId,
+
+    /// The user ID of the intended recipient.
+    pub recipient: UserId,
+
+    /// The keys of the intended recipient's device.
+    pub recipient_keys: OlmV1Keys,
+
+    /// The keys of the sender's device.
+    pub keys: OlmV1Keys,
+
+    /// The type of the encrypted event.
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+
+    /// The content of the encrypted event.
+    pub content: Value,
+}
+
+/// The Ed25519 key of a device, as included in Olm payloads.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OlmV1Keys {
+    /// The Ed25519 fingerprint key of the device.
+    pub ed25519: String,
+}
+
+/// The decrypted payload of an event encrypted with *m.megolm.v1.aes-sha2*.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MegolmV1AesSha2Plaintext {
+    /// The room the event was sent to.
+    pub room_id: RoomId,
+
+    /// The type of the encrypted event.
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+
+    /// The content of the encrypted event.
+    pub content: Value,
+}
+
+impl OlmV1Curve25519AesSha2Plaintext {
+    /// Converts the payload into the to-device event it encrypts.
+    ///
+    /// `sender` is the sender of the to-device event the payload was decrypted from, which must
+    /// match the sender in the payload. If the Ed25519 key of the sending device is known, it is
+    /// given as `sender_ed25519` and must match the key in the payload. `recipient` and
+    /// `recipient_ed25519` are the user ID and Ed25519 key of the device that decrypted the
+    /// payload, and must match the intended recipient, so that payloads can't be forwarded to
+    /// other users or devices.
+    pub fn into_to_device_event(
+        self,
+        sender: &UserId,
+        sender_ed25519: Option<&str>,
+        recipient: &UserId,
+        recipient_ed25519: &str,
+    ) -> Result<collections::all::Event, InvalidEvent> {
+        if self.sender != *sender {
+            return Err(validation_error(
+                json!(self),
+                format!(
+                    "the payload was sent by {}, but the event by {}",
+                    self.sender, sender
+                ),
+            ));
+        }
+
+        if let Some(sender_ed25519) = sender_ed25519 {
+            if self.keys.ed25519 != sender_ed25519 {
+                return Err(validation_error(
+                    json!(self),
+                    "the payload was sent by a different device".to_string(),
+                ));
+            }
+        }
+
+        if self.recipient != *recipient {
+            return Err(validation_error(
+                json!(self),
+                format!(
+                    "the payload is for {}, but was received by {}",
+                    self.recipient, recipient
+                ),
+            ));
+        }
+
+        if self.recipient_keys.ed25519 != recipient_ed25519 {
+            return Err(validation_error(
+                json!(self),
+                "the payload is for a different device".to_string(),
+            ));
+        }
+
+        json!({
+            "content": self.content,
+            "type": self.event_type,
+        })
+        .to_string()
+        .parse()
+    }
+}
+
+impl MegolmV1AesSha2Plaintext {
+    /// Converts the payload into the room event it encrypts, taking the `event_id`, `sender`,
+    /// `origin_server_ts` and `unsigned` data of the encrypted event it was decrypted from.
+    ///
+    /// If the encrypted event has a room ID, it must match the one in the payload, so that events
+    /// can't be replayed in other rooms.
+    pub fn into_room_event(
+        self,
+        envelope: &EncryptedEvent,
+    ) -> Result<collections::all::RoomEvent, InvalidEvent> {
+        if let Some(ref room_id) = envelope.room_id {
+            if self.room_id != *room_id {
+                return Err(validation_error(
+                    json!(self),
+                    format!(
+                        "the payload is for the room {}, but the event is in {}",
+                        self.room_id, room_id
+                    ),
+                ));
+            }
+        }
+
+        let mut event = json!({
+            "content": self.content,
+            "event_id": envelope.event_id,
+            "origin_server_ts": envelope.origin_server_ts,
+            "room_id": self.room_id,
+            "sender": envelope.sender,
+            "type": self.event_type,
+        });
+
+        if let Some(ref unsigned) = envelope.unsigned {
+            event["unsigned"] = unsigned.clone();
+        }
+
+        event.to_string().parse()
+    }
+}
+
+/// Creates an `InvalidEvent` for JSON that deserialized but is not valid.
+fn validation_error(json: Value, message: String) -> InvalidEvent {
+    InvalidEvent(InnerInvalidEvent::Validation { json, message })
+}
+
 #[cfg(test)]
 mod tests {
-    use serde_json::to_string;
+    use std::convert::TryFrom;
+
+    use js_int::UInt;
+    use ruma_identifiers::{EventId, RoomId, UserId};
+    use serde_json::{from_value, json, to_string};
 
-    use super::{Algorithm, EncryptedEventContent, MegolmV1AesSha2Content};
+    use super::{
+        Algorithm, EncryptedEvent, EncryptedEventContent, MegolmV1AesSha2Content,
+        MegolmV1AesSha2Plaintext, OlmV1Curve25519AesSha2Plaintext,
+    };
+    use crate::{
+        collections::all::{Event, RoomEvent},
+        RoomEvent as _,
+    };
 
     #[test]
     fn serializtion() {
@@ -384,4 +547,95 @@ mod tests {
             r#"{"algorithm":"m.megolm.v1.aes-sha2"}"#.parse::<EncryptedEventContent>().is_err()
         );
     }
+
+    fn envelope(room_id: &str) -> EncryptedEvent {
+        EncryptedEvent {
+            content: EncryptedEventContent::MegolmV1AesSha2(MegolmV1AesSha2Content {
+                algorithm: Algorithm::MegolmV1AesSha2,
+                ciphertext: "ciphertext".to_string(),
+                sender_key: "sender_key".to_string(),
+                device_id: "device_id".to_string(),
+                session_id: "session_id".to_string(),
+            }),
+            event_id: EventId::try_from("$event:example.com").unwrap(),
+            origin_server_ts: UInt::from(10u32),
+            room_id: Some(RoomId::try_from(room_id).unwrap()),
+            sender: UserId::try_from("@alice:example.com").unwrap(),
+            unsigned: Some(json!({"age": 5})),
+        }
+    }
+
+    #[test]
+    fn megolm_plaintext_to_room_event() {
+        let plaintext: MegolmV1AesSha2Plaintext = from_value(json!({
+            "content": {"body": "hello", "msgtype": "m.text"},
+            "room_id": "!room:example.com",
+            "type": "m.room.message"
+        }))
+        .unwrap();
+
+        match plaintext
+            .clone()
+            .into_room_event(&envelope("!room:example.com"))
+            .unwrap()
+        {
+            RoomEvent::RoomMessage(event) => {
+                assert_eq!(event.event_id().to_string(), "$event:example.com");
+                assert_eq!(event.sender().to_string(), "@alice:example.com");
+                assert_eq!(event.origin_server_ts(), UInt::from(10u32));
+                assert_eq!(event.room_id().unwrap().to_string(), "!room:example.com");
+                assert_eq!(event.unsigned(), Some(&json!({"age": 5})));
+            }
+            _ => panic!("expected a message event"),
+        }
+
+        assert!(plaintext
+            .into_room_event(&envelope("!other:example.com"))
+            .is_err());
+    }
+
+    #[test]
+    fn olm_plaintext_to_to_device_event() {
+        let plaintext: OlmV1Curve25519AesSha2Plaintext = from_value(json!({
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "room_id": "!room:example.com",
+                "session_id": "session_id",
+                "session_key": "session_key"
+            },
+            "keys": {"ed25519": "sender_ed25519"},
+            "recipient": "@bob:example.com",
+            "recipient_keys": {"ed25519": "recipient_ed25519"},
+            "sender": "@alice:example.com",
+            "type": "m.room_key"
+        }))
+        .unwrap();
+
+        assert_eq!(plaintext.keys.ed25519, "sender_ed25519");
+        assert_eq!(plaintext.recipient_keys.ed25519, "recipient_ed25519");
+
+        let alice = UserId::try_from("@alice:example.com").unwrap();
+        let bob = UserId::try_from("@bob:example.com").unwrap();
+        let to_device_event = |sender_ed25519, recipient, recipient_ed25519| {
+            plaintext.clone().into_to_device_event(
+                &alice,
+                sender_ed25519,
+                recipient,
+                recipient_ed25519,
+            )
+        };
+
+        match to_device_event(Some("sender_ed25519"), &bob, "recipient_ed25519").unwrap() {
+            Event::RoomKey(event) => assert_eq!(event.content.session_key, "session_key"),
+            _ => panic!("expected a room key event"),
+        }
+
+        assert!(to_device_event(None, &bob, "recipient_ed25519").is_ok());
+        assert!(to_device_event(Some("other_ed25519"), &bob, "recipient_ed25519").is_err());
+        assert!(to_device_event(Some("sender_ed25519"), &alice, "recipient_ed25519").is_err());
+        assert!(to_device_event(Some("sender_ed25519"), &bob, "other_ed25519").is_err());
+        assert!(plaintext
+            .into_to_device_event(&bob, None, &bob, "recipient_ed25519")
+            .is_err());
+    }
 }
@@ -1,21 +1,40 @@
 //! Types for the *m.room.message* event.
 
-use std::{convert::TryFrom, str::FromStr};
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+};
 
 use js_int::UInt;
 use ruma_identifiers::{EventId, RoomId, UserId};
 use serde::{
-    de::Error as _,
+    de::{Error as _, Visitor},
     ser::{Error as _, SerializeStruct},
     Deserialize, Deserializer, Serialize, Serializer,
 };
-use serde_json::{from_value, Value};
+use serde_json::{from_value, Map, Value};
 
 use super::{EncryptedFile, ImageInfo, ThumbnailInfo};
 use crate::{Event, EventType, InnerInvalidEvent, InvalidEvent, RoomEvent};
 
+pub use self::{
+    html::{html_to_plain_text, sanitize_html, RemoveReplyFallback},
+    thread::{threads, ThreadSummary, TimelineThread},
+};
+
+#[cfg(feature = "markdown")]
+pub use self::markdown::markdown_to_html;
+
 pub mod feedback;
 
+mod edit;
+mod html;
+#[cfg(feature = "markdown")]
+mod markdown;
+mod reply;
+mod thread;
+
 /// A message sent to a room.
 #[derive(Clone, Debug, PartialEq)]
 pub struct MessageEvent {
@@ -492,14 +511,24 @@ pub struct EmoteMessageEventContent {
     /// The emote action to perform.
     pub body: String,
 
-    /// The format used in the `formatted_body`. Currently only `org.matrix.custom.html` is
-    /// supported.
+    /// The format used in the `formatted_body`.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub format: Option<String>,
+    pub format: Option<MessageFormat>,
 
     /// The formatted version of the `body`. This is required if `format` is specified.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub formatted_body: Option<String>,
+
+    /// Information about related messages, such as the message this one edits.
+    #[serde(rename = "m.relates_to")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relates_to: Option<RelatesTo>,
+
+    /// The new content of the message this one edits, if it is an edit.
+    #[serde(rename = "m.new_content")]
+    #[serde(default, deserialize_with = "deserialize_new_content")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_content: Option<Box<MessageEventContent>>,
 }
 
 /// The payload for a file message.
@@ -608,11 +637,24 @@ pub struct NoticeMessageEventContent {
     /// The notice text to send.
     pub body: String,
 
-    /// Information about related messages for
-    /// [rich replies](https://matrix.org/docs/spec/client_server/r0.5.0#rich-replies).
+    /// The format used in the `formatted_body`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<MessageFormat>,
+
+    /// The formatted version of the `body`. This is required if `format` is specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted_body: Option<String>,
+
+    /// Information about related messages, such as the message this one replies to or edits.
     #[serde(rename = "m.relates_to")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub relates_to: Option<RelatesTo>,
+
+    /// The new content of the message this one edits, if it is an edit.
+    #[serde(rename = "m.new_content")]
+    #[serde(default, deserialize_with = "deserialize_new_content")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_content: Option<Box<MessageEventContent>>,
 }
 
 /// The payload for a server notice message.
@@ -672,20 +714,24 @@ pub struct TextMessageEventContent {
     /// The body of the message.
     pub body: String,
 
-    /// The format used in the `formatted_body`. Currently only `org.matrix.custom.html` is
-    /// supported.
+    /// The format used in the `formatted_body`.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub format: Option<String>,
+    pub format: Option<MessageFormat>,
 
     /// The formatted version of the `body`. This is required if `format` is specified.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub formatted_body: Option<String>,
 
-    /// Information about related messages for
-    /// [rich replies](https://matrix.org/docs/spec/client_server/r0.5.0#rich-replies).
+    /// Information about related messages, such as the message this one replies to or edits.
     #[serde(rename = "m.relates_to")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub relates_to: Option<RelatesTo>,
+
+    /// The new content of the message this one edits, if it is an edit.
+    #[serde(rename = "m.new_content")]
+    #[serde(default, deserialize_with = "deserialize_new_content")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_content: Option<Box<MessageEventContent>>,
 }
 
 /// The payload for a video message.
@@ -747,13 +793,45 @@ pub struct VideoInfo {
     pub thumbnail_file: Option<EncryptedFile>,
 }
 
-/// Information about related messages for
-/// [rich replies](https://matrix.org/docs/spec/client_server/r0.5.0#rich-replies).
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-pub struct RelatesTo {
-    /// Information about another message being replied to.
-    #[serde(rename = "m.in_reply_to")]
-    pub in_reply_to: InReplyTo,
+/// The format of the `formatted_body` of a message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MessageFormat {
+    /// HTML, restricted to the subset of tags and attributes allowed by the specification.
+    Html,
+
+    /// Any format that is not part of the specification.
+    Custom(String),
+
+    /// Additional variants may be added in the future and will not be considered breaking changes
+    /// to ruma-events.
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// Information about the relationship of a message to another event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RelatesTo {
+    /// The message is a [rich reply](https://matrix.org/docs/spec/client_server/r0.5.0#rich-replies),
+    /// with no `rel_type`.
+    Reply {
+        /// Information about another message being replied to.
+        in_reply_to: InReplyTo,
+    },
+
+    /// The message is an edit of another message, with `rel_type` set to `m.replace`.
+    Replacement(Replacement),
+
+    /// The message is part of a thread, with `rel_type` set to `m.thread`.
+    Thread(Thread),
+
+    /// A relation with a `rel_type` that is not part of the specification, holding the raw JSON
+    /// object. This includes such relations that also have an `m.in_reply_to`.
+    Custom(Map<String, Value>),
+
+    /// Additional variants may be added in the future and will not be considered breaking changes
+    /// to ruma-events.
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
 /// Information about the event a "rich reply" is replying to.
@@ -763,6 +841,238 @@ pub struct InReplyTo {
     pub event_id: EventId,
 }
 
+/// Information about the event a message edits.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Replacement {
+    /// The event being edited.
+    pub event_id: EventId,
+}
+
+/// Information about the thread a message is part of.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Thread {
+    /// The root event of the thread.
+    pub event_id: EventId,
+
+    /// The event being replied to.
+    ///
+    /// If `is_falling_back` is `true`, this is the latest event in the thread, which lets clients
+    /// that don't support threads show the message as a reply to it. Otherwise, the message is a
+    /// rich reply to this event within the thread.
+    #[serde(rename = "m.in_reply_to")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<InReplyTo>,
+
+    /// Whether `in_reply_to` is only a fallback for clients that don't support threads.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_false")]
+    pub is_falling_back: bool,
+}
+
+impl Thread {
+    /// Creates a relation for a message in the thread rooted at `root`.
+    ///
+    /// `latest_event` is the latest event in the thread, which clients that don't support threads
+    /// will show the message as a reply to.
+    pub fn new(root: EventId, latest_event: EventId) -> Self {
+        Self {
+            event_id: root,
+            in_reply_to: Some(InReplyTo {
+                event_id: latest_event,
+            }),
+            is_falling_back: true,
+        }
+    }
+
+    /// Creates a relation for a rich reply to `in_reply_to` in the thread rooted at `root`.
+    pub fn reply(root: EventId, in_reply_to: EventId) -> Self {
+        Self {
+            event_id: root,
+            in_reply_to: Some(InReplyTo {
+                event_id: in_reply_to,
+            }),
+            is_falling_back: false,
+        }
+    }
+}
+
+impl Serialize for RelatesTo {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            RelatesTo::Reply { ref in_reply_to } => {
+                let mut state = serializer.serialize_struct("RelatesTo", 1)?;
+
+                state.serialize_field("m.in_reply_to", in_reply_to)?;
+
+                state.end()
+            }
+            RelatesTo::Replacement(ref replacement) => {
+                let mut state = serializer.serialize_struct("RelatesTo", 2)?;
+
+                state.serialize_field("event_id", &replacement.event_id)?;
+                state.serialize_field("rel_type", "m.replace")?;
+
+                state.end()
+            }
+            RelatesTo::Thread(ref thread) => {
+                let mut len = 2;
+
+                if thread.in_reply_to.is_some() {
+                    len += 1;
+                }
+
+                if thread.is_falling_back {
+                    len += 1;
+                }
+
+                let mut state = serializer.serialize_struct("RelatesTo", len)?;
+
+                state.serialize_field("event_id", &thread.event_id)?;
+
+                if thread.is_falling_back {
+                    state.serialize_field("is_falling_back", &thread.is_falling_back)?;
+                }
+
+                if thread.in_reply_to.is_some() {
+                    state.serialize_field("m.in_reply_to", &thread.in_reply_to)?;
+                }
+
+                state.serialize_field("rel_type", "m.thread")?;
+
+                state.end()
+            }
+            RelatesTo::Custom(ref relation) => relation.serialize(serializer),
+            RelatesTo::__Nonexhaustive => Err(S::Error::custom(
+                "Attempted to serialize __Nonexhaustive variant.",
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RelatesTo {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value: Map<String, Value> = Deserialize::deserialize(deserializer)?;
+
+        match value.get("rel_type").and_then(Value::as_str) {
+            Some("m.replace") => match from_value(Value::Object(value)) {
+                Ok(replacement) => Ok(RelatesTo::Replacement(replacement)),
+                Err(error) => Err(D::Error::custom(error.to_string())),
+            },
+            Some("m.thread") => match from_value(Value::Object(value)) {
+                Ok(thread) => Ok(RelatesTo::Thread(thread)),
+                Err(error) => Err(D::Error::custom(error.to_string())),
+            },
+            Some(_) => Ok(RelatesTo::Custom(value)),
+            None => match value.get("m.in_reply_to") {
+                Some(in_reply_to) => match from_value(in_reply_to.clone()) {
+                    Ok(in_reply_to) => Ok(RelatesTo::Reply { in_reply_to }),
+                    Err(error) => Err(D::Error::custom(error.to_string())),
+                },
+                None => Err(D::Error::missing_field("m.in_reply_to")),
+            },
+        }
+    }
+}
+
+/// Used to skip serializing `bool` fields that are `false`.
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Deserializes the `m.new_content` of an edit.
+fn deserialize_new_content<'de, D>(
+    deserializer: D,
+) -> Result<Option<Box<MessageEventContent>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw: Option<raw::MessageEventContent> = Deserialize::deserialize(deserializer)?;
+
+    let content = match raw {
+        Some(raw::MessageEventContent::Audio(content)) => MessageEventContent::Audio(content),
+        Some(raw::MessageEventContent::Emote(content)) => MessageEventContent::Emote(content),
+        Some(raw::MessageEventContent::File(content)) => MessageEventContent::File(content),
+        Some(raw::MessageEventContent::Image(content)) => MessageEventContent::Image(content),
+        Some(raw::MessageEventContent::Location(content)) => MessageEventContent::Location(content),
+        Some(raw::MessageEventContent::Notice(content)) => MessageEventContent::Notice(content),
+        Some(raw::MessageEventContent::ServerNotice(content)) => {
+            MessageEventContent::ServerNotice(content)
+        }
+        Some(raw::MessageEventContent::Text(content)) => MessageEventContent::Text(content),
+        Some(raw::MessageEventContent::Video(content)) => MessageEventContent::Video(content),
+        Some(raw::MessageEventContent::__Nonexhaustive) => {
+            panic!("__Nonexhaustive enum variant is not intended for use.")
+        }
+        None => return Ok(None),
+    };
+
+    Ok(Some(Box::new(content)))
+}
+
+impl Display for MessageFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let format_str = match *self {
+            MessageFormat::Html => "org.matrix.custom.html",
+            MessageFormat::Custom(ref format) => format,
+            MessageFormat::__Nonexhaustive => {
+                panic!("__Nonexhaustive enum variant is not intended for use.")
+            }
+        };
+
+        write!(f, "{}", format_str)
+    }
+}
+
+impl<'a> From<&'a str> for MessageFormat {
+    fn from(s: &'a str) -> MessageFormat {
+        match s {
+            "org.matrix.custom.html" => MessageFormat::Html,
+            format => MessageFormat::Custom(format.to_string()),
+        }
+    }
+}
+
+impl Serialize for MessageFormat {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageFormat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MessageFormatVisitor;
+
+        impl<'de> Visitor<'de> for MessageFormatVisitor {
+            type Value = MessageFormat;
+
+            fn expecting(&self, formatter: &mut Formatter<'_>) -> FmtResult {
+                write!(formatter, "a message format as a string")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(MessageFormat::from(v))
+            }
+        }
+
+        deserializer.deserialize_str(MessageFormatVisitor)
+    }
+}
+
 impl_enum! {
     MessageType {
         Audio => "m.audio",
@@ -833,6 +1143,14 @@ impl Serialize for EmoteMessageEventContent {
             len += 1;
         }
 
+        if self.relates_to.is_some() {
+            len += 1;
+        }
+
+        if self.new_content.is_some() {
+            len += 1;
+        }
+
         let mut state = serializer.serialize_struct("EmoteMessageEventContent", len)?;
 
         state.serialize_field("body", &self.body)?;
@@ -847,6 +1165,14 @@ impl Serialize for EmoteMessageEventContent {
 
         state.serialize_field("msgtype", "m.emote")?;
 
+        if self.relates_to.is_some() {
+            state.serialize_field("m.relates_to", &self.relates_to)?;
+        }
+
+        if self.new_content.is_some() {
+            state.serialize_field("m.new_content", &self.new_content)?;
+        }
+
         state.end()
     }
 }
@@ -972,17 +1298,42 @@ impl Serialize for NoticeMessageEventContent {
     {
         let mut len = 2;
 
+        if self.format.is_some() {
+            len += 1;
+        }
+
+        if self.formatted_body.is_some() {
+            len += 1;
+        }
+
         if self.relates_to.is_some() {
             len += 1;
         }
 
+        if self.new_content.is_some() {
+            len += 1;
+        }
+
         let mut state = serializer.serialize_struct("NoticeMessageEventContent", len)?;
 
         state.serialize_field("body", &self.body)?;
+
+        if self.format.is_some() {
+            state.serialize_field("format", &self.format)?;
+        }
+
+        if self.formatted_body.is_some() {
+            state.serialize_field("formatted_body", &self.formatted_body)?;
+        }
+
         state.serialize_field("msgtype", "m.notice")?;
 
         if self.relates_to.is_some() {
-            state.serialize_field("relates_to", &self.relates_to)?;
+            state.serialize_field("m.relates_to", &self.relates_to)?;
+        }
+
+        if self.new_content.is_some() {
+            state.serialize_field("m.new_content", &self.new_content)?;
         }
 
         state.end()
@@ -1041,6 +1392,10 @@ impl Serialize for TextMessageEventContent {
             len += 1;
         }
 
+        if self.new_content.is_some() {
+            len += 1;
+        }
+
         let mut state = serializer.serialize_struct("TextMessageEventContent", len)?;
 
         state.serialize_field("body", &self.body)?;
@@ -1056,7 +1411,11 @@ impl Serialize for TextMessageEventContent {
         state.serialize_field("msgtype", "m.text")?;
 
         if self.relates_to.is_some() {
-            state.serialize_field("relates_to", &self.relates_to)?;
+            state.serialize_field("m.relates_to", &self.relates_to)?;
+        }
+
+        if self.new_content.is_some() {
+            state.serialize_field("m.new_content", &self.new_content)?;
         }
 
         state.end()
@@ -1105,9 +1464,15 @@ impl Serialize for VideoMessageEventContent {
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
+
+    use ruma_identifiers::EventId;
     use serde_json::to_string;
 
-    use super::{AudioMessageEventContent, MessageEventContent};
+    use super::{
+        AudioMessageEventContent, InReplyTo, MessageEventContent, MessageFormat,
+        NoticeMessageEventContent, RelatesTo,
+    };
 
     #[test]
     fn serialization() {
@@ -1124,6 +1489,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn relates_to_serialization() {
+        let message_event_content = MessageEventContent::Notice(NoticeMessageEventContent {
+            body: "test".to_string(),
+            format: None,
+            formatted_body: None,
+            relates_to: Some(RelatesTo::Reply {
+                in_reply_to: InReplyTo {
+                    event_id: EventId::try_from("$h29iv0s8:example.com").unwrap(),
+                },
+            }),
+            new_content: None,
+        });
+
+        assert_eq!(
+            to_string(&message_event_content).unwrap(),
+            r#"{"body":"test","msgtype":"m.notice","m.relates_to":{"m.in_reply_to":{"event_id":"$h29iv0s8:example.com"}}}"#
+        );
+    }
+
+    #[test]
+    fn format_serialization() {
+        let message_event_content = MessageEventContent::Notice(NoticeMessageEventContent {
+            body: "test".to_string(),
+            format: Some(MessageFormat::Html),
+            formatted_body: Some("<b>test</b>".to_string()),
+            relates_to: None,
+            new_content: None,
+        });
+
+        assert_eq!(
+            to_string(&message_event_content).unwrap(),
+            r#"{"body":"test","format":"org.matrix.custom.html","formatted_body":"<b>test</b>","msgtype":"m.notice"}"#
+        );
+
+        match r#"{"body":"test","format":"com.example.custom","formatted_body":"test","msgtype":"m.text"}"#
+            .parse::<MessageEventContent>()
+            .unwrap()
+        {
+            MessageEventContent::Text(content) => assert_eq!(
+                content.format,
+                Some(MessageFormat::Custom("com.example.custom".to_string()))
+            ),
+            _ => panic!("expected a text message"),
+        }
+    }
+
     #[test]
     fn deserialization() {
         let message_event_content = MessageEventContent::Audio(AudioMessageEventContent {
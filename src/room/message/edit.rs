@@ -0,0 +1,258 @@
+//! Support for editing messages by sending a replacement with `rel_type` set to `m.replace`.
+
+use super::{MessageEvent, MessageEventContent, RelatesTo};
+use crate::InvalidInput;
+
+impl MessageEvent {
+    /// Applies an edit to this message, returning the message as it looks after the edit.
+    ///
+    /// `edit` must have been sent by the same user as this message, in the same room, with
+    /// `relates_to` set to a `RelatesTo::Replacement` of this message and `new_content` set. If
+    /// either event has no `room_id`, the rooms are assumed to match.
+    ///
+    /// The edited message keeps this message's event ID, timestamp, unsigned data and
+    /// `relates_to`, so a reply that is edited is still a reply. Its content is otherwise the
+    /// `new_content` of the edit.
+    pub fn apply_edit(&self, edit: &MessageEvent) -> Result<MessageEvent, InvalidInput> {
+        if let Some(RelatesTo::Replacement(_)) = self.content.relates_to() {
+            return Err(InvalidInput(
+                "an edit cannot be applied to another edit".to_string(),
+            ));
+        }
+
+        match edit.content.relates_to() {
+            Some(RelatesTo::Replacement(ref replacement))
+                if replacement.event_id == self.event_id => {}
+            _ => {
+                return Err(InvalidInput(format!(
+                    "event {} is not an edit of event {}",
+                    edit.event_id, self.event_id
+                )));
+            }
+        }
+
+        if edit.sender != self.sender {
+            return Err(InvalidInput(format!(
+                "edit was sent by {}, but the original message was sent by {}",
+                edit.sender, self.sender
+            )));
+        }
+
+        if let (Some(ref edit_room_id), Some(ref room_id)) = (&edit.room_id, &self.room_id) {
+            if edit_room_id != room_id {
+                return Err(InvalidInput(format!(
+                    "edit was sent in {}, but the original message was sent in {}",
+                    edit_room_id, room_id
+                )));
+            }
+        }
+
+        let mut content = match edit.content.new_content() {
+            Some(new_content) => new_content.clone(),
+            None => {
+                return Err(InvalidInput("edit has no `m.new_content`".to_string()));
+            }
+        };
+
+        content.set_relation(self.content.relates_to().cloned(), None);
+
+        Ok(MessageEvent {
+            content,
+            event_id: self.event_id.clone(),
+            origin_server_ts: self.origin_server_ts,
+            room_id: self.room_id.clone(),
+            sender: self.sender.clone(),
+            unsigned: self.unsigned.clone(),
+        })
+    }
+}
+
+impl MessageEventContent {
+    /// Information about related messages, if the message type supports it.
+    pub fn relates_to(&self) -> Option<&RelatesTo> {
+        match *self {
+            MessageEventContent::Emote(ref content) => content.relates_to.as_ref(),
+            MessageEventContent::Notice(ref content) => content.relates_to.as_ref(),
+            MessageEventContent::Text(ref content) => content.relates_to.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The new content of the message this one edits, if it is an edit.
+    pub fn new_content(&self) -> Option<&MessageEventContent> {
+        match *self {
+            MessageEventContent::Emote(ref content) => content.new_content.as_ref(),
+            MessageEventContent::Notice(ref content) => content.new_content.as_ref(),
+            MessageEventContent::Text(ref content) => content.new_content.as_ref(),
+            _ => None,
+        }
+        .map(|new_content| &**new_content)
+    }
+
+    /// Replaces `relates_to` and `new_content`, if the message type supports them.
+    fn set_relation(
+        &mut self,
+        relates_to: Option<RelatesTo>,
+        new_content: Option<Box<MessageEventContent>>,
+    ) {
+        match *self {
+            MessageEventContent::Emote(ref mut content) => {
+                content.relates_to = relates_to;
+                content.new_content = new_content;
+            }
+            MessageEventContent::Notice(ref mut content) => {
+                content.relates_to = relates_to;
+                content.new_content = new_content;
+            }
+            MessageEventContent::Text(ref mut content) => {
+                content.relates_to = relates_to;
+                content.new_content = new_content;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, to_value};
+
+    use super::super::{MessageEvent, MessageEventContent, RelatesTo};
+
+    fn event(json: serde_json::Value) -> MessageEvent {
+        json.to_string().parse().unwrap()
+    }
+
+    fn original() -> MessageEvent {
+        event(json!({
+            "content": {
+                "body": "> <@bob:example.com> hi\n\nhello wrold",
+                "msgtype": "m.text",
+                "m.relates_to": {"m.in_reply_to": {"event_id": "$bob:example.com"}}
+            },
+            "event_id": "$original:example.com",
+            "origin_server_ts": 1,
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "type": "m.room.message"
+        }))
+    }
+
+    fn edit(sender: &str, replaces: &str) -> MessageEvent {
+        event(json!({
+            "content": {
+                "body": "* hello world",
+                "msgtype": "m.text",
+                "m.new_content": {"body": "hello world", "msgtype": "m.text"},
+                "m.relates_to": {"event_id": replaces, "rel_type": "m.replace"}
+            },
+            "event_id": "$edit:example.com",
+            "origin_server_ts": 2,
+            "room_id": "!room:example.com",
+            "sender": sender,
+            "type": "m.room.message"
+        }))
+    }
+
+    #[test]
+    fn edit_round_trips() {
+        let edit = edit("@alice:example.com", "$original:example.com");
+
+        match edit.content.relates_to() {
+            Some(RelatesTo::Replacement(replacement)) => {
+                assert_eq!(replacement.event_id.to_string(), "$original:example.com");
+            }
+            _ => panic!("expected a replacement"),
+        }
+
+        assert_eq!(
+            to_value(&edit.content).unwrap(),
+            json!({
+                "body": "* hello world",
+                "msgtype": "m.text",
+                "m.new_content": {"body": "hello world", "msgtype": "m.text"},
+                "m.relates_to": {"event_id": "$original:example.com", "rel_type": "m.replace"}
+            })
+        );
+    }
+
+    #[test]
+    fn apply_edit() {
+        let original = original();
+        let edited = original
+            .apply_edit(&edit("@alice:example.com", "$original:example.com"))
+            .unwrap();
+
+        assert_eq!(edited.event_id, original.event_id);
+        assert_eq!(edited.origin_server_ts, original.origin_server_ts);
+
+        match edited.content {
+            MessageEventContent::Text(ref content) => {
+                assert_eq!(content.body, "hello world");
+                assert_eq!(content.relates_to, original.content.relates_to().cloned());
+                assert!(content.new_content.is_none());
+            }
+            _ => panic!("expected a text message"),
+        }
+    }
+
+    #[test]
+    fn apply_invalid_edit() {
+        let original = original();
+
+        assert!(original
+            .apply_edit(&edit("@mallory:example.com", "$original:example.com"))
+            .is_err());
+        assert!(original
+            .apply_edit(&edit("@alice:example.com", "$other:example.com"))
+            .is_err());
+        assert!(original.apply_edit(&original).is_err());
+    }
+
+    #[test]
+    fn unknown_relation_type() {
+        let content = r#"{
+            "body": "hi",
+            "msgtype": "m.text",
+            "m.relates_to": {"event_id": "$original:example.com", "rel_type": "m.unknown"}
+        }"#
+        .parse::<MessageEventContent>()
+        .unwrap();
+
+        let relation = match content.relates_to() {
+            Some(RelatesTo::Custom(relation)) => relation,
+            _ => panic!("expected a custom relation"),
+        };
+        assert_eq!(relation["rel_type"], "m.unknown");
+        assert_eq!(
+            to_value(&content).unwrap()["m.relates_to"],
+            json!({"event_id": "$original:example.com", "rel_type": "m.unknown"})
+        );
+
+        let content = r#"{
+            "body": "hi",
+            "msgtype": "m.text",
+            "m.relates_to": {
+                "event_id": "$original:example.com",
+                "m.in_reply_to": {"event_id": "$replied:example.com"},
+                "rel_type": "m.unknown"
+            }
+        }"#
+        .parse::<MessageEventContent>()
+        .unwrap();
+
+        let relation = match content.relates_to() {
+            Some(RelatesTo::Custom(relation)) => relation,
+            _ => panic!("expected a custom relation"),
+        };
+        assert_eq!(relation["rel_type"], "m.unknown");
+        assert_eq!(
+            to_value(&content).unwrap()["m.relates_to"],
+            json!({
+                "event_id": "$original:example.com",
+                "m.in_reply_to": {"event_id": "$replied:example.com"},
+                "rel_type": "m.unknown"
+            })
+        );
+    }
+}
@@ -0,0 +1,920 @@
+//! Sanitizing and rendering of HTML in the `org.matrix.custom.html` format.
+
+/// The tags the specification allows in `formatted_body`.
+const ALLOWED_TAGS: &[&str] = &[
+    "font",
+    "del",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "blockquote",
+    "p",
+    "a",
+    "ul",
+    "ol",
+    "sup",
+    "sub",
+    "li",
+    "b",
+    "i",
+    "u",
+    "strong",
+    "em",
+    "strike",
+    "code",
+    "hr",
+    "br",
+    "div",
+    "table",
+    "thead",
+    "tbody",
+    "tr",
+    "th",
+    "td",
+    "caption",
+    "pre",
+    "span",
+    "img",
+    "mx-reply",
+];
+
+/// Tags whose content is removed along with the tag itself.
+const TAGS_WITHOUT_CONTENT: &[&str] = &[
+    "head", "iframe", "noscript", "object", "script", "style", "textarea", "title",
+];
+
+/// Tags that never have any content or closing tag.
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Tags whose content is read as text, without looking for tags in it.
+const RAW_TEXT_TAGS: &[&str] = &["script", "style", "textarea", "title"];
+
+/// The URL schemes allowed in the `href` attribute of links.
+const ALLOWED_SCHEMES: &[&str] = &["https", "http", "ftp", "mailto", "magnet"];
+
+/// Tags that start a new block of text when rendered as plain text.
+const BLOCK_TAGS: &[&str] = &[
+    "blockquote",
+    "caption",
+    "div",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "table",
+    "tr",
+    "ul",
+];
+
+/// The maximum depth of nested tags that are kept when parsing.
+const MAX_DEPTH: usize = 100;
+
+/// Whether `sanitize_html` removes the fallback of rich replies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RemoveReplyFallback {
+    /// Remove `<mx-reply>` elements and their content.
+    Yes,
+
+    /// Keep `<mx-reply>` elements.
+    No,
+}
+
+/// Sanitizes HTML to the subset the specification allows in `formatted_body`.
+///
+/// Tags that aren't allowed are removed, keeping their content, except for tags such as
+/// `<script>` whose content is removed as well. Attributes that aren't allowed are removed. Links
+/// are only kept for the `https`, `http`, `ftp`, `mailto` and `magnet` schemes, which includes
+/// `matrix.to` links, images only for `mxc://` URIs, and colors only in the `#rrggbb` format.
+/// Tags nested more than 100 levels deep are removed, keeping their content.
+pub fn sanitize_html(html: &str, remove_reply_fallback: RemoveReplyFallback) -> String {
+    let mut sanitized = String::with_capacity(html.len());
+
+    for node in &parse(html) {
+        sanitize_node(node, remove_reply_fallback, 0, &mut sanitized);
+    }
+
+    sanitized
+}
+
+/// Renders HTML as plain text, e.g. to generate `body` from `formatted_body`.
+///
+/// Blocks such as paragraphs and list items are put on separate lines, lines in block quotes are
+/// prefixed with `> `, and whitespace is collapsed outside of `<pre>` blocks. The fallback of rich
+/// replies is left out. Links keep their text, followed by their target in parentheses unless the
+/// two are the same or the link is a `matrix.to` link.
+pub fn html_to_plain_text(html: &str) -> String {
+    let mut renderer = PlainTextRenderer::default();
+
+    for node in &parse(html) {
+        renderer.render(node, false);
+    }
+
+    renderer.finish()
+}
+
+/// Escapes the characters that have a special meaning in HTML.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// A node of a parsed HTML document.
+#[derive(Debug, PartialEq)]
+enum Node {
+    /// An element, with its lowercase tag name, attributes and content.
+    Element {
+        name: String,
+        attributes: Vec<(String, String)>,
+        children: Vec<Node>,
+    },
+
+    /// Text, with character references decoded.
+    Text(String),
+}
+
+/// Parses HTML leniently into a list of nodes.
+///
+/// Unclosed tags are closed at the end of their parent, closing tags without a matching opening
+/// tag are ignored, and comments, doctypes and processing instructions are left out. Tags nested
+/// more than `MAX_DEPTH` levels deep are left out, keeping their content.
+fn parse(html: &str) -> Vec<Node> {
+    // The elements that are currently open, with the nodes parsed into them so far. The first
+    // entry is a nameless element holding the top-level nodes.
+    let mut stack = vec![Node::Element {
+        name: String::new(),
+        attributes: Vec::new(),
+        children: Vec::new(),
+    }];
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+
+            push_text(&mut stack, &decode_entities(&rest[..end]));
+            rest = &rest[end..];
+            continue;
+        }
+
+        if rest.starts_with("<!--") {
+            rest = match rest[4..].find("-->") {
+                Some(end) => &rest[4 + end + 3..],
+                None => "",
+            };
+            continue;
+        }
+
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = match rest.find('>') {
+                Some(end) => &rest[end + 1..],
+                None => "",
+            };
+            continue;
+        }
+
+        let tag = match parse_tag(rest) {
+            Some(tag) => tag,
+            None => {
+                push_text(&mut stack, "<");
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        rest = &rest[tag.len..];
+
+        if tag.is_closing {
+            let index = stack.iter().rposition(|node| match *node {
+                Node::Element { ref name, .. } => *name == tag.name,
+                Node::Text(_) => false,
+            });
+
+            if let Some(index) = index {
+                if index > 0 {
+                    while stack.len() > index {
+                        close_element(&mut stack);
+                    }
+                }
+            }
+
+            continue;
+        }
+
+        if VOID_TAGS.contains(&tag.name.as_str()) || tag.is_self_closing {
+            push_node(
+                &mut stack,
+                Node::Element {
+                    name: tag.name,
+                    attributes: tag.attributes,
+                    children: Vec::new(),
+                },
+            );
+            continue;
+        }
+
+        if RAW_TEXT_TAGS.contains(&tag.name.as_str()) {
+            let closing_tag = format!("</{}", tag.name);
+            let end = rest
+                .to_ascii_lowercase()
+                .find(&closing_tag)
+                .unwrap_or(rest.len());
+            let text = rest[..end].to_string();
+
+            rest = &rest[end..];
+            rest = match rest.find('>') {
+                Some(end) => &rest[end + 1..],
+                None => "",
+            };
+
+            push_node(
+                &mut stack,
+                Node::Element {
+                    name: tag.name,
+                    attributes: tag.attributes,
+                    children: vec![Node::Text(text)],
+                },
+            );
+            continue;
+        }
+
+        // Tags nested too deeply are left out, keeping their content, so that sanitizing,
+        // rendering and dropping the tree can't overflow the stack.
+        if stack.len() > MAX_DEPTH {
+            continue;
+        }
+
+        stack.push(Node::Element {
+            name: tag.name,
+            attributes: tag.attributes,
+            children: Vec::new(),
+        });
+    }
+
+    while stack.len() > 1 {
+        close_element(&mut stack);
+    }
+
+    match stack.pop() {
+        Some(Node::Element { children, .. }) => children,
+        _ => Vec::new(),
+    }
+}
+
+/// Closes the innermost open element, adding it to its parent.
+fn close_element(stack: &mut Vec<Node>) {
+    if let Some(element) = stack.pop() {
+        push_node(stack, element);
+    }
+}
+
+/// The nodes parsed into the innermost open element so far.
+fn open_children(stack: &mut [Node]) -> Option<&mut Vec<Node>> {
+    match stack.last_mut() {
+        Some(Node::Element {
+            ref mut children, ..
+        }) => Some(children),
+        _ => None,
+    }
+}
+
+/// Adds a node to the innermost open element.
+fn push_node(stack: &mut [Node], node: Node) {
+    if let Some(children) = open_children(stack) {
+        children.push(node);
+    }
+}
+
+/// Adds text to the innermost open element, merging it with preceding text.
+fn push_text(stack: &mut [Node], text: &str) {
+    if let Some(children) = open_children(stack) {
+        if let Some(Node::Text(ref mut previous)) = children.last_mut() {
+            previous.push_str(text);
+            return;
+        }
+
+        children.push(Node::Text(text.to_string()));
+    }
+}
+
+/// An opening or closing tag.
+struct Tag {
+    /// The lowercase name of the tag.
+    name: String,
+
+    /// The attributes of the tag, with lowercase names and decoded values.
+    attributes: Vec<(String, String)>,
+
+    /// Whether this is a closing tag.
+    is_closing: bool,
+
+    /// Whether the tag ends with `/>`.
+    is_self_closing: bool,
+
+    /// The length of the tag in the input.
+    len: usize,
+}
+
+/// Parses the tag at the start of `input`, which starts with `<`.
+fn parse_tag(input: &str) -> Option<Tag> {
+    let bytes = input.as_bytes();
+    let mut i = 1;
+
+    let is_closing = bytes.get(i) == Some(&b'/');
+
+    if is_closing {
+        i += 1;
+    }
+
+    let name_start = i;
+
+    while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'-') {
+        i += 1;
+    }
+
+    if i == name_start || !bytes[name_start].is_ascii_alphabetic() {
+        return None;
+    }
+
+    let name = input[name_start..i].to_ascii_lowercase();
+    let mut attributes = Vec::new();
+    let mut is_self_closing = false;
+
+    loop {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+
+        match bytes.get(i) {
+            None => return None,
+            Some(b'>') => {
+                i += 1;
+                break;
+            }
+            Some(b'/') => {
+                is_self_closing = true;
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let attribute_start = i;
+
+        while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !b"/>=".contains(&bytes[i]) {
+            i += 1;
+        }
+
+        let attribute_name = input[attribute_start..i].to_ascii_lowercase();
+
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+
+        let mut value = String::new();
+
+        if bytes.get(i) == Some(&b'=') {
+            i += 1;
+
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+
+            match bytes.get(i) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    let value_start = i + 1;
+                    let value_end = input[value_start..]
+                        .find(quote as char)
+                        .map(|end| value_start + end)?;
+
+                    value = decode_entities(&input[value_start..value_end]);
+                    i = value_end + 1;
+                }
+                _ => {
+                    let value_start = i;
+
+                    while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                        i += 1;
+                    }
+
+                    value = decode_entities(&input[value_start..i]);
+                }
+            }
+        }
+
+        if !attribute_name.is_empty() {
+            is_self_closing = false;
+            attributes.push((attribute_name, value));
+        }
+    }
+
+    Some(Tag {
+        name,
+        attributes,
+        is_closing,
+        is_self_closing,
+        len: i,
+    })
+}
+
+/// Decodes the character references in text.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find(';') {
+            Some(end) if end <= 10 => end,
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        let entity = &rest[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                u32::from_str_radix(&entity[2..], 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+            }
+            _ if entity.starts_with('#') => entity[1..]
+                .parse::<u32>()
+                .ok()
+                .and_then(std::char::from_u32),
+            _ => None,
+        };
+
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Writes the sanitized form of a node.
+fn sanitize_node(
+    node: &Node,
+    remove_reply_fallback: RemoveReplyFallback,
+    depth: usize,
+    output: &mut String,
+) {
+    let (name, attributes, children) = match *node {
+        Node::Text(ref text) => {
+            output.push_str(&escape_html(text));
+            return;
+        }
+        Node::Element {
+            ref name,
+            ref attributes,
+            ref children,
+        } => (name.as_str(), attributes, children),
+    };
+
+    if TAGS_WITHOUT_CONTENT.contains(&name)
+        || (name == "mx-reply" && remove_reply_fallback == RemoveReplyFallback::Yes)
+    {
+        return;
+    }
+
+    if !ALLOWED_TAGS.contains(&name) || depth >= MAX_DEPTH {
+        for child in children {
+            sanitize_node(child, remove_reply_fallback, depth, output);
+        }
+
+        return;
+    }
+
+    output.push('<');
+    output.push_str(name);
+
+    for (attribute, value) in attributes {
+        if is_allowed_attribute(name, attribute, value) {
+            output.push_str(&format!(" {}=\"{}\"", attribute, escape_html(value)));
+        }
+    }
+
+    if VOID_TAGS.contains(&name) {
+        output.push_str(" />");
+        return;
+    }
+
+    output.push('>');
+
+    for child in children {
+        sanitize_node(child, remove_reply_fallback, depth + 1, output);
+    }
+
+    output.push_str(&format!("</{}>", name));
+}
+
+/// Whether the given attribute and value are allowed on the given tag.
+fn is_allowed_attribute(tag: &str, attribute: &str, value: &str) -> bool {
+    match (tag, attribute) {
+        ("font", "data-mx-bg-color")
+        | ("font", "data-mx-color")
+        | ("font", "color")
+        | ("span", "data-mx-bg-color")
+        | ("span", "data-mx-color") => is_color(value),
+        ("span", "data-mx-spoiler") => true,
+        ("a", "name") | ("a", "target") => true,
+        ("a", "href") => match value.find(':') {
+            Some(index) => ALLOWED_SCHEMES.contains(&value[..index].to_ascii_lowercase().as_str()),
+            None => false,
+        },
+        ("img", "width") | ("img", "height") | ("img", "alt") | ("img", "title") => true,
+        ("img", "src") => value.starts_with("mxc://"),
+        ("ol", "start") => value.parse::<u32>().is_ok(),
+        ("code", "class") => value.starts_with("language-"),
+        _ => false,
+    }
+}
+
+/// Whether the value is a color in the `#rrggbb` format.
+fn is_color(value: &str) -> bool {
+    value.len() == 7 && value.starts_with('#') && value[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Renders nodes as plain text.
+#[derive(Default)]
+struct PlainTextRenderer {
+    /// The lines rendered so far, without the line currently being rendered.
+    lines: Vec<String>,
+
+    /// The line currently being rendered.
+    line: String,
+
+    /// The prefix of each line, e.g. `> ` in block quotes.
+    prefix: String,
+
+    /// The number of the next item of each open list, or `None` for unordered lists.
+    ///
+    /// Numbers are kept as `u64` so that counting up from any `u32` `start` can't overflow.
+    lists: Vec<Option<u64>>,
+}
+
+impl PlainTextRenderer {
+    fn render(&mut self, node: &Node, preformatted: bool) {
+        let (name, attributes, children) = match *node {
+            Node::Text(ref text) => {
+                self.push_text(text, preformatted);
+                return;
+            }
+            Node::Element {
+                ref name,
+                ref attributes,
+                ref children,
+            } => (name.as_str(), attributes, children),
+        };
+
+        let attribute = |wanted: &str| {
+            attributes
+                .iter()
+                .find(|(attribute, _)| attribute == wanted)
+                .map(|(_, value)| value.as_str())
+        };
+
+        if TAGS_WITHOUT_CONTENT.contains(&name) || name == "mx-reply" {
+            return;
+        }
+
+        let is_block = BLOCK_TAGS.contains(&name);
+
+        if is_block {
+            self.break_line();
+        }
+
+        match name {
+            "br" => self.new_line(),
+            "hr" => {
+                self.line.push_str("---");
+                self.break_line();
+            }
+            "img" => {
+                if let Some(alt) = attribute("alt") {
+                    self.push_text(alt, preformatted);
+                }
+            }
+            "blockquote" => {
+                let previous_len = self.prefix.len();
+
+                self.prefix.push_str("> ");
+                self.render_children(children, preformatted);
+                self.break_line();
+                self.prefix.truncate(previous_len);
+            }
+            "ol" | "ul" => {
+                let start = attribute("start")
+                    .and_then(|start| start.parse::<u32>().ok())
+                    .map(u64::from);
+
+                self.lists.push(if name == "ol" {
+                    Some(start.unwrap_or(1))
+                } else {
+                    None
+                });
+                self.render_children(children, preformatted);
+                self.lists.pop();
+            }
+            "li" => {
+                let marker = match self.lists.last_mut() {
+                    Some(Some(ref mut number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+
+                self.line.push_str(&marker);
+                self.render_children(children, preformatted);
+            }
+            "a" => {
+                self.render_children(children, preformatted);
+
+                if let Some(href) = attribute("href") {
+                    let mut text = String::new();
+                    text_content(children, &mut text);
+
+                    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+                    if !href.starts_with("https://matrix.to/") && text != href {
+                        if text.is_empty() {
+                            self.line.push_str(href);
+                        } else if self.line.trim().is_empty() {
+                            self.line.push_str(&format!("({})", href));
+                        } else {
+                            self.line.push_str(&format!(" ({})", href));
+                        }
+                    }
+                }
+            }
+            "pre" => self.render_children(children, true),
+            _ => self.render_children(children, preformatted),
+        }
+
+        if is_block {
+            self.break_line();
+        }
+    }
+
+    fn render_children(&mut self, children: &[Node], preformatted: bool) {
+        for child in children {
+            self.render(child, preformatted);
+        }
+    }
+
+    /// Adds text to the current line, collapsing whitespace unless it is preformatted.
+    fn push_text(&mut self, text: &str, preformatted: bool) {
+        if preformatted {
+            let mut lines = text.split('\n');
+
+            if let Some(first) = lines.next() {
+                self.line.push_str(first);
+            }
+
+            for line in lines {
+                self.new_line();
+                self.line.push_str(line);
+            }
+
+            return;
+        }
+
+        for (i, word) in text.split_whitespace().enumerate() {
+            let needs_space = if i == 0 {
+                text.starts_with(char::is_whitespace)
+            } else {
+                true
+            };
+
+            if needs_space && !self.line.is_empty() && !self.line.ends_with(' ') {
+                self.line.push(' ');
+            }
+
+            self.line.push_str(word);
+        }
+
+        if text.ends_with(char::is_whitespace) && !self.line.is_empty() && !self.line.ends_with(' ')
+        {
+            self.line.push(' ');
+        }
+    }
+
+    /// Ends the current line.
+    fn new_line(&mut self) {
+        let line = std::mem::replace(&mut self.line, String::new());
+
+        self.lines.push(
+            format!("{}{}", self.prefix, line.trim_end())
+                .trim_end()
+                .to_string(),
+        );
+    }
+
+    /// Ends the current line if it has any content, for the start or end of a block.
+    fn break_line(&mut self) {
+        if !self.line.trim().is_empty() {
+            self.new_line();
+        } else {
+            self.line.clear();
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.break_line();
+
+        let mut text = self.lines.join("\n");
+        let trimmed_len = text.trim_end().len();
+
+        text.truncate(trimmed_len);
+        text
+    }
+}
+
+/// Collects the text of nodes, including the alternative text of images.
+fn text_content(nodes: &[Node], text: &mut String) {
+    for node in nodes {
+        match *node {
+            Node::Text(ref node_text) => text.push_str(node_text),
+            Node::Element {
+                ref name,
+                ref attributes,
+                ref children,
+            } => {
+                if name == "img" {
+                    if let Some((_, alt)) = attributes.iter().find(|(name, _)| name == "alt") {
+                        text.push_str(alt);
+                    }
+                }
+
+                // Separate the text of blocks and line breaks like the rendered text does.
+                if name == "br" || BLOCK_TAGS.contains(&name.as_str()) {
+                    text.push(' ');
+                }
+
+                text_content(children, text);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{html_to_plain_text, sanitize_html, RemoveReplyFallback};
+
+    #[test]
+    fn sanitize_allowed_html() {
+        let html = "<p>Hello <strong>world</strong>!<br />\
+                    <font data-mx-color=\"#ff0000\">red</font> \
+                    <a href=\"https://matrix.to/#/@alice:example.com\">Alice</a></p>";
+
+        assert_eq!(sanitize_html(html, RemoveReplyFallback::No), html);
+    }
+
+    #[test]
+    fn sanitize_disallowed_html() {
+        assert_eq!(
+            sanitize_html(
+                "<div onclick=\"evil()\"><script>alert(1)</script><marquee>hi</marquee>\
+                 <a href=\"javascript:alert(1)\" target=\"_blank\">link</a>\
+                 <img src=\"https://example.com/a.png\" alt=\"pic\">\
+                 <span data-mx-color=\"red\">x</span><!-- comment --></div>",
+                RemoveReplyFallback::No,
+            ),
+            "<div>hi<a target=\"_blank\">link</a><img alt=\"pic\" /><span>x</span></div>"
+        );
+    }
+
+    #[test]
+    fn sanitize_malformed_html() {
+        assert_eq!(
+            sanitize_html("<b>bold <i>both</b> a < b &amp; c", RemoveReplyFallback::No),
+            "<b>bold <i>both</i></b> a &lt; b &amp; c"
+        );
+    }
+
+    #[test]
+    fn sanitize_reply_fallback() {
+        let html = "<mx-reply><blockquote>quoted</blockquote></mx-reply>reply";
+
+        assert_eq!(sanitize_html(html, RemoveReplyFallback::No), html);
+        assert_eq!(sanitize_html(html, RemoveReplyFallback::Yes), "reply");
+    }
+
+    #[test]
+    fn sanitize_deep_nesting() {
+        let html = format!("{}text{}", "<b>".repeat(150), "</b>".repeat(150));
+
+        assert_eq!(
+            sanitize_html(&html, RemoveReplyFallback::No),
+            format!("{}text{}", "<b>".repeat(100), "</b>".repeat(100))
+        );
+    }
+
+    #[test]
+    fn very_deep_nesting() {
+        let html = format!(
+            "{}<a href=\"https://example.com\">text</a>{}",
+            "<b><div>".repeat(20_000),
+            "</div></b>".repeat(20_000)
+        );
+
+        assert_eq!(
+            sanitize_html(&html, RemoveReplyFallback::No),
+            format!("{}text{}", "<b><div>".repeat(50), "</div></b>".repeat(50))
+        );
+        assert_eq!(html_to_plain_text(&html), "text");
+        assert_eq!(html_to_plain_text(&"<b>".repeat(20_000)), "");
+    }
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(
+            html_to_plain_text(
+                "<mx-reply><blockquote>quoted</blockquote></mx-reply>\
+                 <h1>Title</h1><p>Some   <em>formatted</em>\ntext &amp; a \
+                 <a href=\"https://example.com\">link</a> to \
+                 <a href=\"https://matrix.to/#/@alice:example.com\">Alice</a>.</p>\
+                 <ol start=\"3\"><li>three</li><li>four</li></ol>\
+                 <blockquote><p>quote</p><p>more</p></blockquote>\
+                 <pre><code>let x = 1;\n  x</code></pre>line<br>break"
+            ),
+            "Title\n\
+             Some formatted text & a link (https://example.com) to Alice.\n\
+             3. three\n\
+             4. four\n\
+             > quote\n\
+             > more\n\
+             let x = 1;\n  x\n\
+             line\n\
+             break"
+        );
+    }
+
+    #[test]
+    fn plain_text_large_list_start() {
+        assert_eq!(
+            html_to_plain_text("<ol start=\"4294967295\"><li>a</li><li>b</li></ol>"),
+            "4294967295. a\n4294967296. b"
+        );
+    }
+
+    #[test]
+    fn plain_text_line_breaks_in_links() {
+        assert_eq!(
+            html_to_plain_text("hello <a href=\"https://example.com\">a<br>b</a>"),
+            "hello a\nb (https://example.com)"
+        );
+        assert_eq!(
+            html_to_plain_text("<p>é <a href=\"https://example.com\"><div>ü</div><p>ö</p></a></p>"),
+            "é\nü\nö\n(https://example.com)"
+        );
+        assert_eq!(
+            html_to_plain_text("<a href=\"https://example.com\"><p>https://example.com</p></a>"),
+            "https://example.com"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Support for creating messages from [CommonMark](https://commonmark.org).
+
+use std::convert::TryFrom;
+
+use pulldown_cmark::{html::push_html, CowStr, Event, Parser, Tag};
+use ruma_identifiers::{RoomAliasId, UserId};
+
+use super::{
+    html::{escape_html, sanitize_html, RemoveReplyFallback},
+    EmoteMessageEventContent, MessageFormat, NoticeMessageEventContent, TextMessageEventContent,
+};
+
+impl TextMessageEventContent {
+    /// Creates a text message from CommonMark.
+    ///
+    /// See `markdown_to_html` for how `format` and `formatted_body` are set.
+    pub fn markdown(body: String) -> Self {
+        let formatted_body = markdown_to_html(&body);
+
+        Self {
+            body,
+            format: formatted_body.as_ref().map(|_| MessageFormat::Html),
+            formatted_body,
+            relates_to: None,
+            new_content: None,
+        }
+    }
+}
+
+impl NoticeMessageEventContent {
+    /// Creates a notice from CommonMark.
+    ///
+    /// See `markdown_to_html` for how `format` and `formatted_body` are set.
+    pub fn markdown(body: String) -> Self {
+        let formatted_body = markdown_to_html(&body);
+
+        Self {
+            body,
+            format: formatted_body.as_ref().map(|_| MessageFormat::Html),
+            formatted_body,
+            relates_to: None,
+            new_content: None,
+        }
+    }
+}
+
+impl EmoteMessageEventContent {
+    /// Creates an emote from CommonMark.
+    ///
+    /// See `markdown_to_html` for how `format` and `formatted_body` are set.
+    pub fn markdown(body: String) -> Self {
+        let formatted_body = markdown_to_html(&body);
+
+        Self {
+            body,
+            format: formatted_body.as_ref().map(|_| MessageFormat::Html),
+            formatted_body,
+            relates_to: None,
+            new_content: None,
+        }
+    }
+}
+
+/// Renders CommonMark as HTML for the `formatted_body` of a message.
+///
+/// Returns `None` if the markdown contains no formatting, i.e. if it consists only of paragraphs
+/// of text, since the plain `body` represents it just as well.
+///
+/// User IDs and room aliases outside of code and links become `matrix.to` links, which clients
+/// display as "pills". Any HTML in the markdown is sanitized with `sanitize_html`.
+pub fn markdown_to_html(markdown: &str) -> Option<String> {
+    let mut events = Vec::new();
+    let mut code_blocks = 0;
+    let mut links = 0;
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => code_blocks += 1,
+            Event::End(Tag::CodeBlock(_)) => code_blocks -= 1,
+            Event::Start(Tag::Link(..)) | Event::Start(Tag::Image(..)) => links += 1,
+            Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => links -= 1,
+            Event::Text(ref text) if code_blocks == 0 && links == 0 => {
+                push_text_with_pills(text, &mut events);
+                continue;
+            }
+            _ => {}
+        }
+
+        events.push(event);
+    }
+
+    let has_formatting = events.iter().any(|event| match *event {
+        Event::Start(Tag::Paragraph)
+        | Event::End(Tag::Paragraph)
+        | Event::Text(_)
+        | Event::SoftBreak => false,
+        _ => true,
+    });
+
+    if !has_formatting {
+        return None;
+    }
+
+    let mut html = String::new();
+
+    push_html(&mut html, events.into_iter());
+
+    Some(sanitize_html(html.trim_end(), RemoveReplyFallback::No))
+}
+
+/// Adds text to the events, turning the user IDs and room aliases in it into `matrix.to` links.
+fn push_text_with_pills<'a>(text: &str, events: &mut Vec<Event<'a>>) {
+    let mut start = 0;
+    let mut previous = None;
+
+    for (i, c) in text.char_indices() {
+        let is_boundary = !previous.map_or(false, is_id_char);
+
+        previous = Some(c);
+
+        if i < start || (c != '@' && c != '#') || !is_boundary {
+            continue;
+        }
+
+        let len = text[i + 1..]
+            .find(|c| !is_id_char(c))
+            .unwrap_or(text.len() - i - 1);
+        let id = text[i..=i + len].trim_end_matches(|c| ".,:;!?".contains(c));
+
+        let is_valid = match c {
+            '@' => UserId::try_from(id).is_ok(),
+            _ => RoomAliasId::try_from(id).is_ok(),
+        };
+
+        if !is_valid {
+            continue;
+        }
+
+        if start < i {
+            events.push(Event::Text(CowStr::from(text[start..i].to_string())));
+        }
+
+        events.push(Event::Html(CowStr::from(format!(
+            "<a href=\"https://matrix.to/#/{0}\">{0}</a>",
+            escape_html(id)
+        ))));
+
+        start = i + id.len();
+    }
+
+    if start < text.len() {
+        events.push(Event::Text(CowStr::from(text[start..].to_string())));
+    }
+}
+
+/// Whether the character can be part of a user ID or room alias.
+fn is_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "._=-/+:[]".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        EmoteMessageEventContent, MessageFormat, NoticeMessageEventContent, TextMessageEventContent,
+    };
+    use super::markdown_to_html;
+
+    #[test]
+    fn plain_markdown() {
+        let content = TextMessageEventContent::markdown("hello\nworld\n\nagain".to_string());
+
+        assert_eq!(content.body, "hello\nworld\n\nagain");
+        assert!(content.format.is_none());
+        assert!(content.formatted_body.is_none());
+    }
+
+    #[test]
+    fn formatted_markdown() {
+        let content = NoticeMessageEventContent::markdown("**bold** and `code`".to_string());
+
+        assert_eq!(content.body, "**bold** and `code`");
+        assert_eq!(content.format, Some(MessageFormat::Html));
+        assert_eq!(
+            content.formatted_body.unwrap(),
+            "<p><strong>bold</strong> and <code>code</code></p>"
+        );
+
+        let content = EmoteMessageEventContent::markdown("> quote\n\n- item".to_string());
+
+        assert_eq!(
+            content.formatted_body.unwrap(),
+            "<blockquote>\n<p>quote</p>\n</blockquote>\n<ul>\n<li>item</li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn pills() {
+        assert_eq!(
+            markdown_to_html("hi @alice:example.com, see #room:example.com.").unwrap(),
+            "<p>hi <a href=\"https://matrix.to/#/@alice:example.com\">@alice:example.com</a>, \
+             see <a href=\"https://matrix.to/#/#room:example.com\">#room:example.com</a>.</p>"
+        );
+
+        assert_eq!(markdown_to_html("mail bob@example.com or @bob"), None);
+        assert_eq!(
+            markdown_to_html("`@alice:example.com`").unwrap(),
+            "<p><code>@alice:example.com</code></p>"
+        );
+    }
+
+    #[test]
+    fn sanitized_html() {
+        assert_eq!(
+            markdown_to_html("<b onclick=\"evil()\">hi</b> <script>alert(1)</script>").unwrap(),
+            "<p><b>hi</b> </p>"
+        );
+    }
+}
@@ -0,0 +1,479 @@
+//! Support for [rich replies](https://matrix.org/docs/spec/client_server/r0.5.0#rich-replies).
+
+use super::{
+    html::escape_html, EmoteMessageEventContent, InReplyTo, MessageEvent, MessageEventContent,
+    MessageFormat, NoticeMessageEventContent, RelatesTo, TextMessageEventContent, Thread,
+};
+
+impl TextMessageEventContent {
+    /// Creates a rich reply to the given message.
+    ///
+    /// `body` and `formatted_body` are the text of the reply itself. The plain-text and HTML
+    /// fallbacks quoting `original` are prepended to them, and `relates_to` is set to point at
+    /// `original`. If `original` is itself a reply, its own fallback is left out of the quote. If
+    /// `original` is part of a thread, the reply is made within the same thread.
+    ///
+    /// If `formatted_body` is `None`, the HTML version of the reply is generated from `body`.
+    /// If `original` has no `room_id`, the "In reply to" link in the HTML fallback only contains
+    /// the event ID.
+    pub fn reply(original: &MessageEvent, body: String, formatted_body: Option<String>) -> Self {
+        let quote = Quote::of(&original.content);
+
+        let sender = original.sender.to_string();
+        let event_link = match original.room_id {
+            Some(ref room_id) => format!("https://matrix.to/#/{}/{}", room_id, original.event_id),
+            None => format!("https://matrix.to/#/{}", original.event_id),
+        };
+        let emote_prefix = if quote.is_emote { "* " } else { "" };
+
+        let mut plain_fallback = String::new();
+
+        for (i, line) in quote.body.split('\n').enumerate() {
+            if i == 0 {
+                plain_fallback.push_str(&format!("> {}<{}> {}\n", emote_prefix, sender, line));
+            } else {
+                plain_fallback.push_str(&format!("> {}\n", line));
+            }
+        }
+
+        let html_fallback = format!(
+            "<mx-reply><blockquote><a href=\"{}\">In reply to</a> {}<a href=\"https://matrix.to/#/{}\">{}</a><br />{}</blockquote></mx-reply>",
+            escape_html(&event_link),
+            emote_prefix,
+            escape_html(&sender),
+            escape_html(&sender),
+            quote.html,
+        );
+
+        let reply_html = formatted_body.unwrap_or_else(|| text_to_html(&body));
+
+        Self {
+            body: format!("{}\n{}", plain_fallback, body),
+            format: Some(MessageFormat::Html),
+            formatted_body: Some(format!("{}{}", html_fallback, reply_html)),
+            relates_to: Some(match original.content.relates_to() {
+                Some(RelatesTo::Thread(ref thread)) => RelatesTo::Thread(Thread::reply(
+                    thread.event_id.clone(),
+                    original.event_id.clone(),
+                )),
+                _ => RelatesTo::Reply {
+                    in_reply_to: InReplyTo {
+                        event_id: original.event_id.clone(),
+                    },
+                },
+            }),
+            new_content: None,
+        }
+    }
+
+    /// Removes the rich reply fallbacks from `body` and `formatted_body`, leaving only the text
+    /// of the reply itself.
+    ///
+    /// This does nothing if the message isn't a reply, i.e. if `relates_to` is neither a
+    /// `RelatesTo::Reply` nor a `RelatesTo::Thread` with a reply that isn't a fallback.
+    pub fn strip_reply_fallback(&mut self) {
+        strip_reply_fallback(
+            &self.relates_to,
+            &mut self.body,
+            self.format.as_ref(),
+            &mut self.formatted_body,
+        );
+    }
+}
+
+impl EmoteMessageEventContent {
+    /// Removes the rich reply fallbacks from `body` and `formatted_body`, leaving only the text
+    /// of the reply itself.
+    ///
+    /// This does nothing if the message isn't a reply, i.e. if `relates_to` is neither a
+    /// `RelatesTo::Reply` nor a `RelatesTo::Thread` with a reply that isn't a fallback.
+    pub fn strip_reply_fallback(&mut self) {
+        strip_reply_fallback(
+            &self.relates_to,
+            &mut self.body,
+            self.format.as_ref(),
+            &mut self.formatted_body,
+        );
+    }
+}
+
+impl NoticeMessageEventContent {
+    /// Removes the rich reply fallbacks from `body` and `formatted_body`, leaving only the text
+    /// of the reply itself.
+    ///
+    /// This does nothing if the message isn't a reply, i.e. if `relates_to` is neither a
+    /// `RelatesTo::Reply` nor a `RelatesTo::Thread` with a reply that isn't a fallback.
+    pub fn strip_reply_fallback(&mut self) {
+        strip_reply_fallback(
+            &self.relates_to,
+            &mut self.body,
+            self.format.as_ref(),
+            &mut self.formatted_body,
+        );
+    }
+}
+
+/// The parts of a message that are quoted in a reply fallback.
+struct Quote {
+    /// The plain-text version of the quoted message.
+    body: String,
+
+    /// The HTML version of the quoted message.
+    html: String,
+
+    /// Whether the quoted message is an emote.
+    is_emote: bool,
+}
+
+impl Quote {
+    fn of(content: &MessageEventContent) -> Self {
+        let plain = |body: &str| Quote {
+            body: body.to_string(),
+            html: text_to_html(body),
+            is_emote: false,
+        };
+
+        match *content {
+            MessageEventContent::Audio(_) => plain("sent an audio file."),
+            MessageEventContent::Emote(ref content) => {
+                let mut content = content.clone();
+                content.strip_reply_fallback();
+
+                Quote {
+                    html: html_or_text(
+                        &content.body,
+                        content.format.as_ref(),
+                        content.formatted_body.as_ref(),
+                    ),
+                    body: content.body,
+                    is_emote: true,
+                }
+            }
+            MessageEventContent::File(_) => plain("sent a file."),
+            MessageEventContent::Image(_) => plain("sent an image."),
+            MessageEventContent::Location(_) => plain("sent a location."),
+            MessageEventContent::Notice(ref content) => {
+                let mut content = content.clone();
+                content.strip_reply_fallback();
+
+                Quote {
+                    html: html_or_text(
+                        &content.body,
+                        content.format.as_ref(),
+                        content.formatted_body.as_ref(),
+                    ),
+                    body: content.body,
+                    is_emote: false,
+                }
+            }
+            MessageEventContent::ServerNotice(ref content) => plain(&content.body),
+            MessageEventContent::Text(ref content) => {
+                let mut content = content.clone();
+                content.strip_reply_fallback();
+
+                Quote {
+                    html: html_or_text(
+                        &content.body,
+                        content.format.as_ref(),
+                        content.formatted_body.as_ref(),
+                    ),
+                    body: content.body,
+                    is_emote: false,
+                }
+            }
+            MessageEventContent::Video(_) => plain("sent a video."),
+            MessageEventContent::__Nonexhaustive => {
+                panic!("__Nonexhaustive enum variant is not intended for use.")
+            }
+        }
+    }
+}
+
+/// Whether a message with the given relation is a rich reply, and so has a reply fallback.
+///
+/// Messages in a thread only have a reply fallback if they are a rich reply within the thread,
+/// not if their `in_reply_to` is just there for clients that don't support threads.
+fn is_reply(relates_to: &Option<RelatesTo>) -> bool {
+    match *relates_to {
+        Some(RelatesTo::Reply { .. }) => true,
+        Some(RelatesTo::Thread(ref thread)) => {
+            thread.in_reply_to.is_some() && !thread.is_falling_back
+        }
+        _ => false,
+    }
+}
+
+/// Removes the reply fallbacks from the body of a message with the given relation, if it is a
+/// rich reply.
+fn strip_reply_fallback(
+    relates_to: &Option<RelatesTo>,
+    body: &mut String,
+    format: Option<&MessageFormat>,
+    formatted_body: &mut Option<String>,
+) {
+    if !is_reply(relates_to) {
+        return;
+    }
+
+    *body = strip_plain_fallback(body).to_string();
+
+    if format == Some(&MessageFormat::Html) {
+        if let Some(ref mut formatted_body) = *formatted_body {
+            *formatted_body = strip_html_fallback(formatted_body);
+        }
+    }
+}
+
+/// Returns `formatted_body` if it is HTML, or an HTML version of `body` otherwise.
+fn html_or_text(
+    body: &str,
+    format: Option<&MessageFormat>,
+    formatted_body: Option<&String>,
+) -> String {
+    match (format, formatted_body) {
+        (Some(MessageFormat::Html), Some(formatted_body)) => formatted_body.clone(),
+        _ => text_to_html(body),
+    }
+}
+
+/// Converts plain text to HTML, escaping it and turning line breaks into `<br />` tags.
+fn text_to_html(text: &str) -> String {
+    escape_html(text).replace('\n', "<br />")
+}
+
+/// Removes the leading quoted lines of a plain-text reply fallback, along with the blank line
+/// separating them from the reply.
+fn strip_plain_fallback(body: &str) -> &str {
+    let mut rest = body;
+
+    while rest.starts_with('>') {
+        rest = match rest.find('\n') {
+            Some(index) => &rest[index + 1..],
+            None => "",
+        };
+    }
+
+    if rest.len() == body.len() {
+        return body;
+    }
+
+    if rest.starts_with('\n') {
+        &rest[1..]
+    } else {
+        rest
+    }
+}
+
+/// Removes the `<mx-reply>` element of an HTML reply fallback.
+fn strip_html_fallback(formatted_body: &str) -> String {
+    let start = match formatted_body.find("<mx-reply>") {
+        Some(start) => start,
+        None => return formatted_body.to_string(),
+    };
+
+    match formatted_body[start..].find("</mx-reply>") {
+        Some(end) => {
+            let end = start + end + "</mx-reply>".len();
+
+            format!("{}{}", &formatted_body[..start], &formatted_body[end..])
+        }
+        None => formatted_body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use js_int::UInt;
+    use ruma_identifiers::{EventId, RoomId, UserId};
+
+    use super::super::{
+        EmoteMessageEventContent, ImageMessageEventContent, InReplyTo, MessageEvent,
+        MessageEventContent, MessageFormat, RelatesTo, TextMessageEventContent,
+    };
+
+    fn message(content: MessageEventContent) -> MessageEvent {
+        MessageEvent {
+            content,
+            event_id: EventId::try_from("$original:example.com").unwrap(),
+            origin_server_ts: UInt::from(1u32),
+            room_id: Some(RoomId::try_from("!room:example.com").unwrap()),
+            sender: UserId::try_from("@alice:example.com").unwrap(),
+            unsigned: None,
+        }
+    }
+
+    fn text(body: &str) -> MessageEventContent {
+        MessageEventContent::Text(TextMessageEventContent {
+            body: body.to_string(),
+            format: None,
+            formatted_body: None,
+            relates_to: None,
+            new_content: None,
+        })
+    }
+
+    #[test]
+    fn reply_to_text() {
+        let original = message(text("first line\n<second> line"));
+        let reply = TextMessageEventContent::reply(&original, "my reply".to_string(), None);
+
+        assert_eq!(
+            reply.body,
+            "> <@alice:example.com> first line\n> <second> line\n\nmy reply"
+        );
+        assert_eq!(reply.format, Some(MessageFormat::Html));
+        assert_eq!(
+            reply.formatted_body.as_ref().unwrap(),
+            "<mx-reply><blockquote>\
+             <a href=\"https://matrix.to/#/!room:example.com/$original:example.com\">In reply to</a> \
+             <a href=\"https://matrix.to/#/@alice:example.com\">@alice:example.com</a><br />\
+             first line<br />&lt;second&gt; line\
+             </blockquote></mx-reply>my reply"
+        );
+        assert_eq!(
+            reply.relates_to,
+            Some(RelatesTo::Reply {
+                in_reply_to: InReplyTo {
+                    event_id: original.event_id.clone(),
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn reply_to_emote() {
+        let original = message(MessageEventContent::Emote(EmoteMessageEventContent {
+            body: "waves".to_string(),
+            format: Some(MessageFormat::Html),
+            formatted_body: Some("<em>waves</em>".to_string()),
+            relates_to: None,
+            new_content: None,
+        }));
+        let reply = TextMessageEventContent::reply(
+            &original,
+            "hi".to_string(),
+            Some("<b>hi</b>".to_string()),
+        );
+
+        assert_eq!(reply.body, "> * <@alice:example.com> waves\n\nhi");
+        assert!(reply
+            .formatted_body
+            .unwrap()
+            .ends_with("* <a href=\"https://matrix.to/#/@alice:example.com\">@alice:example.com</a><br /><em>waves</em></blockquote></mx-reply><b>hi</b>"));
+    }
+
+    #[test]
+    fn reply_to_image() {
+        let original = message(MessageEventContent::Image(ImageMessageEventContent {
+            body: "cat.png".to_string(),
+            info: None,
+            url: Some("mxc://example.com/cat".to_string()),
+            file: None,
+        }));
+        let reply = TextMessageEventContent::reply(&original, "cute".to_string(), None);
+
+        assert_eq!(reply.body, "> <@alice:example.com> sent an image.\n\ncute");
+    }
+
+    #[test]
+    fn reply_to_reply_quotes_only_the_reply() {
+        let first = message(text("original"));
+        let second = message(MessageEventContent::Text(TextMessageEventContent::reply(
+            &first,
+            "first reply".to_string(),
+            None,
+        )));
+        let reply = TextMessageEventContent::reply(&second, "second reply".to_string(), None);
+
+        assert_eq!(
+            reply.body,
+            "> <@alice:example.com> first reply\n\nsecond reply"
+        );
+        assert_eq!(
+            reply.formatted_body.unwrap().matches("<mx-reply>").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn strip_reply_fallback() {
+        let original = message(text("line one\n\nline three"));
+        let mut reply = TextMessageEventContent::reply(
+            &original,
+            "> not a quote\nreply".to_string(),
+            Some("<p>reply</p>".to_string()),
+        );
+
+        reply.strip_reply_fallback();
+
+        assert_eq!(reply.body, "> not a quote\nreply");
+        assert_eq!(reply.formatted_body.unwrap(), "<p>reply</p>");
+    }
+
+    #[test]
+    fn strip_reply_fallback_of_reply_to_emote() {
+        let original = message(MessageEventContent::Emote(EmoteMessageEventContent {
+            body: "waves".to_string(),
+            format: None,
+            formatted_body: None,
+            relates_to: None,
+            new_content: None,
+        }));
+        let mut reply = TextMessageEventContent::reply(&original, "hi".to_string(), None);
+
+        reply.strip_reply_fallback();
+
+        assert_eq!(reply.body, "hi");
+        assert_eq!(reply.formatted_body.unwrap(), "hi");
+    }
+
+    #[test]
+    fn strip_reply_fallback_of_emote() {
+        let original = message(text("hello"));
+        let reply = TextMessageEventContent::reply(
+            &original,
+            "waves back".to_string(),
+            Some("<em>waves</em> back".to_string()),
+        );
+        let mut emote = EmoteMessageEventContent {
+            body: reply.body,
+            format: reply.format,
+            formatted_body: reply.formatted_body,
+            relates_to: reply.relates_to,
+            new_content: None,
+        };
+
+        emote.strip_reply_fallback();
+
+        assert_eq!(emote.body, "waves back");
+        assert_eq!(
+            emote.formatted_body.as_ref().unwrap(),
+            "<em>waves</em> back"
+        );
+
+        let reply = TextMessageEventContent::reply(
+            &message(MessageEventContent::Emote(emote)),
+            "nice".to_string(),
+            None,
+        );
+
+        assert_eq!(reply.body, "> * <@alice:example.com> waves back\n\nnice");
+    }
+
+    #[test]
+    fn strip_reply_fallback_needs_relation() {
+        let mut content = TextMessageEventContent {
+            body: "> quoted\n\ntext".to_string(),
+            format: None,
+            formatted_body: None,
+            relates_to: None,
+            new_content: None,
+        };
+
+        content.strip_reply_fallback();
+
+        assert_eq!(content.body, "> quoted\n\ntext");
+    }
+}
@@ -0,0 +1,290 @@
+//! Grouping of the messages in a timeline into threads.
+
+use std::collections::{HashMap, HashSet};
+
+use js_int::UInt;
+use ruma_identifiers::{EventId, UserId};
+use serde::Serialize;
+
+use super::{MessageEvent, RelatesTo};
+use crate::collections::all::RoomEvent;
+
+/// A thread in a timeline of events.
+#[derive(Clone, Debug)]
+pub struct TimelineThread<'a> {
+    /// The ID of the root event of the thread.
+    pub root_id: EventId,
+
+    /// The root event of the thread, if it is an *m.room.message* event in the timeline.
+    pub root: Option<&'a MessageEvent>,
+
+    /// The messages in the thread that are in the timeline, in timeline order.
+    ///
+    /// This is never empty.
+    pub replies: Vec<&'a MessageEvent>,
+}
+
+/// A summary of a thread, in the form homeservers bundle it into the `m.thread` key of
+/// `unsigned.m.relations` on the root event.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ThreadSummary {
+    /// The latest message in the thread.
+    pub latest_event: MessageEvent,
+
+    /// The number of messages in the thread.
+    pub count: UInt,
+
+    /// Whether the user the summary was made for has sent the root or a message in the thread.
+    pub current_user_participated: bool,
+}
+
+impl<'a> TimelineThread<'a> {
+    /// The latest message in the thread.
+    pub fn latest_event(&self) -> &'a MessageEvent {
+        self.replies[self.replies.len() - 1]
+    }
+
+    /// Whether the given user has sent the root or a message in the thread.
+    pub fn participated(&self, user_id: &UserId) -> bool {
+        self.root.iter().any(|root| root.sender == *user_id)
+            || self.replies.iter().any(|reply| reply.sender == *user_id)
+    }
+
+    /// Summarizes the thread for the given user.
+    pub fn summary(&self, user_id: &UserId) -> ThreadSummary {
+        ThreadSummary {
+            latest_event: self.latest_event().clone(),
+            count: UInt::new(self.replies.len() as u64).unwrap_or(UInt::MAX),
+            current_user_participated: self.participated(user_id),
+        }
+    }
+}
+
+/// Groups the messages in a timeline into threads.
+///
+/// Threads are returned in the order they first appear in the timeline, either through their
+/// root or their first message. Only *m.room.message* events are taken into account, and threads
+/// whose root is in the timeline but that have no messages in it are left out.
+pub fn threads<'a, I>(events: I) -> Vec<TimelineThread<'a>>
+where
+    I: IntoIterator<Item = &'a RoomEvent>,
+{
+    let messages: Vec<&'a MessageEvent> = events
+        .into_iter()
+        .filter_map(|event| match *event {
+            RoomEvent::RoomMessage(ref message) => Some(message),
+            _ => None,
+        })
+        .collect();
+
+    let roots: HashSet<&EventId> = messages
+        .iter()
+        .filter_map(|message| thread_root(message))
+        .collect();
+
+    let mut threads: Vec<TimelineThread<'a>> = Vec::new();
+    let mut indices: HashMap<EventId, usize> = HashMap::new();
+
+    for message in messages {
+        let (root_id, is_root) = match thread_root(message) {
+            Some(root_id) => (root_id, false),
+            None if roots.contains(&message.event_id) => (&message.event_id, true),
+            None => continue,
+        };
+
+        let index = *indices.entry(root_id.clone()).or_insert_with(|| {
+            threads.push(TimelineThread {
+                root_id: root_id.clone(),
+                root: None,
+                replies: Vec::new(),
+            });
+
+            threads.len() - 1
+        });
+
+        if is_root {
+            threads[index].root = Some(message);
+        } else {
+            threads[index].replies.push(message);
+        }
+    }
+
+    threads
+}
+
+/// The ID of the root of the thread the message is in, if any.
+fn thread_root(message: &MessageEvent) -> Option<&EventId> {
+    match message.content.relates_to() {
+        Some(RelatesTo::Thread(ref thread)) => Some(&thread.event_id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use js_int::UInt;
+    use ruma_identifiers::{EventId, UserId};
+    use serde_json::{json, to_value, Value};
+
+    use super::threads;
+    use crate::{
+        collections::all::RoomEvent,
+        room::message::{
+            InReplyTo, MessageEventContent, RelatesTo, TextMessageEventContent, Thread,
+        },
+    };
+
+    fn message(event_id: &str, sender: &str, relates_to: Option<Value>) -> RoomEvent {
+        let mut content = json!({"body": "hello", "msgtype": "m.text"});
+
+        if let Some(relates_to) = relates_to {
+            content["m.relates_to"] = relates_to;
+        }
+
+        json!({
+            "content": content,
+            "event_id": event_id,
+            "origin_server_ts": 1,
+            "room_id": "!room:example.com",
+            "sender": sender,
+            "type": "m.room.message"
+        })
+        .to_string()
+        .parse()
+        .unwrap()
+    }
+
+    fn in_thread(root: &str, latest: &str) -> Option<Value> {
+        Some(json!({
+            "event_id": root,
+            "is_falling_back": true,
+            "m.in_reply_to": {"event_id": latest},
+            "rel_type": "m.thread"
+        }))
+    }
+
+    fn event_id(event_id: &str) -> EventId {
+        EventId::try_from(event_id).unwrap()
+    }
+
+    #[test]
+    fn thread_relation_round_trips() {
+        let content = r#"{
+            "body": "hello",
+            "msgtype": "m.text",
+            "m.relates_to": {
+                "event_id": "$root:example.com",
+                "is_falling_back": true,
+                "m.in_reply_to": {"event_id": "$latest:example.com"},
+                "rel_type": "m.thread"
+            }
+        }"#
+        .parse::<MessageEventContent>()
+        .unwrap();
+
+        assert_eq!(
+            content.relates_to(),
+            Some(&RelatesTo::Thread(Thread::new(
+                event_id("$root:example.com"),
+                event_id("$latest:example.com"),
+            )))
+        );
+
+        let thread = RelatesTo::Thread(Thread {
+            event_id: event_id("$root:example.com"),
+            in_reply_to: None,
+            is_falling_back: false,
+        });
+
+        assert_eq!(
+            to_value(&thread).unwrap(),
+            json!({"event_id": "$root:example.com", "rel_type": "m.thread"})
+        );
+    }
+
+    #[test]
+    fn group_threads() {
+        let timeline = vec![
+            message(
+                "$a1:example.com",
+                "@bob:example.com",
+                in_thread("$a:example.com", "$a:example.com"),
+            ),
+            message("$b:example.com", "@alice:example.com", None),
+            message("$other:example.com", "@bob:example.com", None),
+            message(
+                "$b1:example.com",
+                "@bob:example.com",
+                in_thread("$b:example.com", "$b:example.com"),
+            ),
+            message(
+                "$a2:example.com",
+                "@carl:example.com",
+                in_thread("$a:example.com", "$a1:example.com"),
+            ),
+            message(
+                "$b2:example.com",
+                "@bob:example.com",
+                in_thread("$b:example.com", "$b1:example.com"),
+            ),
+        ];
+
+        let threads = threads(&timeline);
+        let alice = UserId::try_from("@alice:example.com").unwrap();
+
+        assert_eq!(threads.len(), 2);
+
+        assert_eq!(threads[0].root_id, event_id("$a:example.com"));
+        assert!(threads[0].root.is_none());
+        assert_eq!(threads[0].replies.len(), 2);
+        assert!(!threads[0].participated(&alice));
+
+        let summary = threads[1].summary(&alice);
+
+        assert_eq!(
+            threads[1].root.unwrap().event_id,
+            event_id("$b:example.com")
+        );
+        assert_eq!(summary.latest_event.event_id, event_id("$b2:example.com"));
+        assert_eq!(summary.count, UInt::from(2u32));
+        assert!(summary.current_user_participated);
+    }
+
+    #[test]
+    fn reply_within_thread() {
+        let original = match message(
+            "$a1:example.com",
+            "@bob:example.com",
+            in_thread("$a:example.com", "$a:example.com"),
+        ) {
+            RoomEvent::RoomMessage(message) => message,
+            _ => unreachable!(),
+        };
+
+        let mut reply = original.clone();
+        reply.content = MessageEventContent::Text(TextMessageEventContent::reply(
+            &original,
+            "reply".to_string(),
+            None,
+        ));
+
+        assert_eq!(
+            reply.content.relates_to(),
+            Some(&RelatesTo::Thread(Thread {
+                event_id: event_id("$a:example.com"),
+                in_reply_to: Some(InReplyTo {
+                    event_id: event_id("$a1:example.com"),
+                }),
+                is_falling_back: false,
+            }))
+        );
+
+        if let MessageEventContent::Text(ref mut content) = reply.content {
+            content.strip_reply_fallback();
+
+            assert_eq!(content.body, "reply");
+        }
+    }
+}
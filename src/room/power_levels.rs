@@ -7,6 +7,7 @@ use ruma_identifiers::{EventId, RoomId, UserId};
 use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 use serde_json::Value;
 
+use super::create::CreateEventContent;
 use crate::{Event, EventType, InnerInvalidEvent, InvalidEvent, RoomEvent, StateEvent};
 
 /// Defines the power levels (privileges) of users in the room.
@@ -48,6 +49,7 @@ pub struct PowerLevelsEventContent {
     /// The level required to send specific event types.
     ///
     /// This is a mapping from event type to power level required.
+    #[serde(default)]
     pub events: HashMap<EventType, Int>,
 
     /// The default level required to send message events.
@@ -73,6 +75,7 @@ pub struct PowerLevelsEventContent {
     /// The power levels for specific users.
     ///
     /// This is a mapping from `user_id` to power level for that user.
+    #[serde(default)]
     pub users: HashMap<UserId, Int>,
 
     /// The default power level for every user in the room.
@@ -82,6 +85,7 @@ pub struct PowerLevelsEventContent {
     /// The power level requirements for specific notification types.
     ///
     /// This is a mapping from `key` to power level for that notifications key.
+    #[serde(default)]
     pub notifications: NotificationPowerLevels,
 }
 
@@ -244,6 +248,109 @@ impl<'a> TryFrom<&'a str> for PowerLevelsEventContent {
     }
 }
 
+impl PowerLevelsEventContent {
+    /// The power levels of a room without an *m.room.power_levels* event.
+    ///
+    /// The creator of the room has power level 100 and all other users have power level 0. Any
+    /// user may send state events and message events, while the other actions require the
+    /// default power level of 50.
+    pub fn without_event(create: &CreateEventContent) -> Self {
+        let mut users = HashMap::new();
+        users.insert(create.creator.clone(), Int::from(100));
+
+        Self {
+            ban: default_power_level(),
+            events: HashMap::new(),
+            events_default: Int::from(0),
+            invite: default_power_level(),
+            kick: default_power_level(),
+            redact: default_power_level(),
+            state_default: Int::from(0),
+            users,
+            users_default: Int::from(0),
+            notifications: NotificationPowerLevels::default(),
+        }
+    }
+
+    /// The power level of the given user.
+    pub fn user_level(&self, user_id: &UserId) -> Int {
+        self.users
+            .get(user_id)
+            .cloned()
+            .unwrap_or(self.users_default)
+    }
+
+    /// The power level required to send an event of the given type.
+    pub fn event_level(&self, event_type: &EventType, is_state: bool) -> Int {
+        match self.events.get(event_type) {
+            Some(&level) => level,
+            None if is_state => self.state_default,
+            None => self.events_default,
+        }
+    }
+
+    /// Whether the given user may send an event of the given type.
+    pub fn can_send_event(&self, user_id: &UserId, event_type: &EventType, is_state: bool) -> bool {
+        self.user_level(user_id) >= self.event_level(event_type, is_state)
+    }
+
+    /// Whether the given user may ban the target user.
+    ///
+    /// Users can only be banned by users with a higher power level than their own.
+    pub fn can_ban(&self, user_id: &UserId, target: &UserId) -> bool {
+        let user_level = self.user_level(user_id);
+
+        user_level >= self.ban && user_level > self.user_level(target)
+    }
+
+    /// Whether the given user may kick the target user.
+    ///
+    /// Users can only be kicked by users with a higher power level than their own.
+    pub fn can_kick(&self, user_id: &UserId, target: &UserId) -> bool {
+        let user_level = self.user_level(user_id);
+
+        user_level >= self.kick && user_level > self.user_level(target)
+    }
+
+    /// Whether the given user may redact an event, which is their own event if `own_event` is
+    /// `true`.
+    ///
+    /// Redacting any event requires the power level for sending *m.room.redaction* events, and
+    /// redacting other users' events additionally requires the `redact` power level.
+    pub fn can_redact(&self, user_id: &UserId, own_event: bool) -> bool {
+        self.can_send_event(user_id, &EventType::RoomRedaction, false)
+            && (own_event || self.user_level(user_id) >= self.redact)
+    }
+
+    /// Whether the given user may invite users.
+    pub fn can_invite(&self, user_id: &UserId) -> bool {
+        self.user_level(user_id) >= self.invite
+    }
+
+    /// Whether the given user may trigger an `@room` notification.
+    pub fn can_notify_room(&self, user_id: &UserId) -> bool {
+        self.user_level(user_id) >= self.notifications.room
+    }
+
+    /// The highest power level the given user may give to the target user, or `None` if they
+    /// can't change the target's power level at all.
+    ///
+    /// Changing power levels requires the power level for sending *m.room.power_levels* events.
+    /// Users may lower their own power level, but may only change the power level of users whose
+    /// power level is lower than their own, and to no higher than their own.
+    pub fn max_level_change_allowed(&self, user_id: &UserId, target: &UserId) -> Option<Int> {
+        let user_level = self.user_level(user_id);
+
+        if !self.can_send_event(user_id, &EventType::RoomPowerLevels, true)
+            || (user_id != target && self.user_level(target) >= user_level)
+        {
+            return None;
+        }
+
+        Some(user_level)
+    }
+}
+
 mod raw {
     use super::*;
 
@@ -286,6 +393,7 @@ mod raw {
         /// The level required to send specific event types.
         ///
         /// This is a mapping from event type to power level required.
+        #[serde(default)]
         pub events: HashMap<EventType, Int>,
 
         /// The default level required to send message events.
@@ -311,6 +419,7 @@ mod raw {
         /// The power levels for specific users.
         ///
         /// This is a mapping from `user_id` to power level for that user.
+        #[serde(default)]
         pub users: HashMap<UserId, Int>,
 
         /// The default power level for every user in the room.
@@ -320,6 +429,7 @@ mod raw {
         /// The power level requirements for specific notification types.
         ///
         /// This is a mapping from `key` to power level for that notifications key.
+        #[serde(default)]
         pub notifications: NotificationPowerLevels,
     }
 }
@@ -332,6 +442,14 @@ pub struct NotificationPowerLevels {
     pub room: Int,
 }
 
+impl Default for NotificationPowerLevels {
+    fn default() -> Self {
+        Self {
+            room: default_power_level(),
+        }
+    }
+}
+
 /// Used to default power levels to 50 during deserialization.
 fn default_power_level() -> Int {
     Int::from(50)
@@ -342,14 +460,15 @@ mod tests {
     use std::{collections::HashMap, convert::TryFrom};
 
     use js_int::{Int, UInt};
-    use ruma_identifiers::{EventId, RoomId, UserId};
+    use ruma_identifiers::{EventId, RoomId, RoomVersionId, UserId};
     use serde_json::Value;
 
     use super::{NotificationPowerLevels, PowerLevelsEvent, PowerLevelsEventContent};
+    use crate::{room::create::CreateEventContent, EventType};
 
     #[test]
     fn serialization_with_optional_fields_as_none() {
-        let default = Int::try_from(50).unwrap();
+        let default = Int::from(50);
 
         let power_levels_event = PowerLevelsEvent {
             content: PowerLevelsEventContent {
@@ -381,7 +500,7 @@ mod tests {
 
     #[test]
     fn serialization_with_all_fields() {
-        let default = Int::try_from(50).unwrap();
+        let default = Int::from(50);
 
         let power_levels_event = PowerLevelsEvent {
             content: PowerLevelsEventContent {
@@ -400,7 +519,7 @@ mod tests {
             origin_server_ts: UInt::try_from(1).unwrap(),
             prev_content: Some(PowerLevelsEventContent {
                 // Make just one field different so we at least know they're two different objects.
-                ban: Int::try_from(75).unwrap(),
+                ban: Int::from(75),
                 events: HashMap::new(),
                 events_default: default,
                 invite: default,
@@ -422,4 +541,73 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn level_queries() {
+        let alice = UserId::try_from("@alice:example.com").unwrap();
+        let bob = UserId::try_from("@bob:example.com").unwrap();
+        let carl = UserId::try_from("@carl:example.com").unwrap();
+
+        let mut users = HashMap::new();
+        users.insert(alice.clone(), Int::from(100));
+        users.insert(bob.clone(), Int::from(50));
+
+        let mut events = HashMap::new();
+        events.insert(EventType::RoomName, Int::from(0));
+
+        let power_levels = PowerLevelsEventContent {
+            ban: Int::from(50),
+            events,
+            events_default: Int::from(0),
+            invite: Int::from(0),
+            kick: Int::from(50),
+            redact: Int::from(50),
+            state_default: Int::from(50),
+            users,
+            users_default: Int::from(0),
+            notifications: NotificationPowerLevels::default(),
+        };
+
+        assert_eq!(power_levels.user_level(&carl), Int::from(0));
+        assert!(power_levels.can_send_event(&carl, &EventType::RoomMessage, false));
+        assert!(power_levels.can_send_event(&carl, &EventType::RoomName, true));
+        assert!(!power_levels.can_send_event(&carl, &EventType::RoomTopic, true));
+        assert!(power_levels.can_ban(&bob, &carl) && !power_levels.can_ban(&carl, &carl));
+        assert!(power_levels.can_kick(&bob, &carl) && !power_levels.can_kick(&carl, &carl));
+        assert!(!power_levels.can_ban(&bob, &alice) && !power_levels.can_kick(&bob, &bob));
+        assert!(power_levels.can_ban(&alice, &bob) && power_levels.can_kick(&alice, &bob));
+        assert!(power_levels.can_redact(&carl, true));
+        assert!(!power_levels.can_redact(&carl, false));
+        assert!(power_levels.can_invite(&carl));
+        assert!(power_levels.can_notify_room(&bob) && !power_levels.can_notify_room(&carl));
+
+        assert_eq!(
+            power_levels.max_level_change_allowed(&bob, &carl),
+            Some(Int::from(50))
+        );
+        assert_eq!(
+            power_levels.max_level_change_allowed(&bob, &bob),
+            Some(Int::from(50))
+        );
+        assert_eq!(power_levels.max_level_change_allowed(&bob, &alice), None);
+        assert_eq!(power_levels.max_level_change_allowed(&carl, &carl), None);
+    }
+
+    #[test]
+    fn without_event() {
+        let create = CreateEventContent {
+            creator: UserId::try_from("@alice:example.com").unwrap(),
+            federate: true,
+            room_version: RoomVersionId::version_1(),
+            predecessor: None,
+        };
+        let power_levels = PowerLevelsEventContent::without_event(&create);
+        let bob = UserId::try_from("@bob:example.com").unwrap();
+
+        assert_eq!(power_levels.user_level(&create.creator), Int::from(100));
+        assert_eq!(power_levels.user_level(&bob), Int::from(0));
+        assert!(power_levels.can_send_event(&bob, &EventType::RoomTopic, true));
+        assert!(!power_levels.can_kick(&bob, &bob));
+        assert!(power_levels.can_kick(&create.creator, &bob));
+    }
 }
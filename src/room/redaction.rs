@@ -1,7 +1,11 @@
 //! Types for the *m.room.redaction* event.
+//!
+//! This module also implements the redaction algorithm used to strip events down to the keys
+//! that are needed for authorization and signature checks.
 
 use ruma_events_macros::ruma_event;
-use ruma_identifiers::EventId;
+use ruma_identifiers::{EventId, RoomVersionId};
+use serde_json::{to_value, Map, Value};
 
 ruma_event! {
     /// A redaction of an event.
@@ -19,3 +23,348 @@ ruma_event! {
         }
     }
 }
+
+/// The differences in the redaction algorithm between room versions.
+struct RedactionRules {
+    /// Whether `aliases` is kept in *m.room.aliases* events (room versions 1 to 5).
+    keep_aliases: bool,
+
+    /// Whether `allow` is kept in *m.room.join_rules* events (room versions 8 and later).
+    keep_join_rules_allow: bool,
+
+    /// Whether `join_authorised_via_users_server` is kept in *m.room.member* events (room
+    /// versions 9 and later).
+    keep_member_join_authorised: bool,
+
+    /// Whether the rules introduced in room version 11 apply.
+    ///
+    /// These keep the whole content of *m.room.create*, `invite` in *m.room.power_levels*,
+    /// `signed` in the `third_party_invite` of *m.room.member*, and `redacts` in
+    /// *m.room.redaction*, and no longer keep the top-level `origin`, `membership` and
+    /// `prev_state` keys.
+    v11: bool,
+}
+
+impl RedactionRules {
+    fn for_version(version: &RoomVersionId) -> Self {
+        let version = version.to_string().parse::<u32>().unwrap_or(1);
+
+        Self {
+            keep_aliases: version <= 5,
+            keep_join_rules_allow: version >= 8,
+            keep_member_join_authorised: version >= 9,
+            v11: version >= 11,
+        }
+    }
+}
+
+/// Applies the redaction algorithm to the JSON representation of an event.
+///
+/// All top-level keys that aren't needed for authorization and signature checks are removed,
+/// which includes `unsigned`, and `content` is stripped down to the keys the specification
+/// preserves for the event's type in the given room version. Room versions this crate doesn't
+/// know about are redacted following the rules of room version 1.
+pub fn redact(event: &Value, version: &RoomVersionId) -> Value {
+    let rules = RedactionRules::for_version(version);
+
+    let mut top_level_keys = vec![
+        "event_id",
+        "type",
+        "room_id",
+        "sender",
+        "state_key",
+        "content",
+        "hashes",
+        "signatures",
+        "depth",
+        "prev_events",
+        "auth_events",
+        "origin_server_ts",
+    ];
+
+    if !rules.v11 {
+        top_level_keys.extend_from_slice(&["prev_state", "origin", "membership"]);
+    }
+
+    let object = match event.as_object() {
+        Some(object) => object,
+        None => return event.clone(),
+    };
+
+    let mut redacted: Map<String, Value> = object
+        .iter()
+        .filter(|(key, _)| top_level_keys.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+
+    let event_type = object.get("type").and_then(Value::as_str).unwrap_or("");
+
+    if let Some(content) = redacted.get_mut("content") {
+        *content = redact_content(event_type, content, &rules);
+    }
+
+    Value::Object(redacted)
+}
+
+/// Redacts the JSON representation of an event the way clients see redacted events.
+///
+/// In addition to applying the redaction algorithm, this keeps the event's `unsigned` data and
+/// adds the redaction event to it as `redacted_because`.
+pub(crate) fn redact_for_client(
+    event: &Value,
+    redaction: &RedactionEvent,
+    version: &RoomVersionId,
+) -> Value {
+    let mut redacted = redact(event, version);
+
+    let mut unsigned = match event.get("unsigned") {
+        Some(Value::Object(unsigned)) => unsigned.clone(),
+        _ => Map::new(),
+    };
+
+    if let Ok(redaction) = to_value(redaction) {
+        unsigned.insert("redacted_because".to_string(), redaction);
+    }
+
+    if let Value::Object(ref mut object) = redacted {
+        object.insert("unsigned".to_string(), Value::Object(unsigned));
+    }
+
+    redacted
+}
+
+/// Strips an event's content down to the keys that are preserved for its type.
+fn redact_content(event_type: &str, content: &Value, rules: &RedactionRules) -> Value {
+    let mut keys: Vec<&str> = match event_type {
+        "m.room.member" => vec!["membership"],
+        "m.room.create" => vec!["creator"],
+        "m.room.join_rules" => vec!["join_rule"],
+        "m.room.power_levels" => vec![
+            "ban",
+            "events",
+            "events_default",
+            "kick",
+            "redact",
+            "state_default",
+            "users",
+            "users_default",
+        ],
+        "m.room.aliases" if rules.keep_aliases => vec!["aliases"],
+        "m.room.history_visibility" => vec!["history_visibility"],
+        "m.room.redaction" if rules.v11 => vec!["redacts"],
+        _ => Vec::new(),
+    };
+
+    match event_type {
+        "m.room.join_rules" if rules.keep_join_rules_allow => keys.push("allow"),
+        "m.room.member" if rules.keep_member_join_authorised => {
+            keys.push("join_authorised_via_users_server")
+        }
+        "m.room.power_levels" if rules.v11 => keys.push("invite"),
+        "m.room.create" if rules.v11 => return content.clone(),
+        _ => {}
+    }
+
+    let object = match content.as_object() {
+        Some(object) => object,
+        None => return Value::Object(Map::new()),
+    };
+
+    let mut redacted: Map<String, Value> = object
+        .iter()
+        .filter(|(key, _)| keys.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+
+    if event_type == "m.room.member" && rules.v11 {
+        let signed = object
+            .get("third_party_invite")
+            .and_then(|invite| invite.get("signed"));
+
+        if let Some(signed) = signed {
+            let mut invite = Map::new();
+            invite.insert("signed".to_string(), signed.clone());
+            redacted.insert("third_party_invite".to_string(), Value::Object(invite));
+        }
+    }
+
+    Value::Object(redacted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use js_int::{Int, UInt};
+    use ruma_identifiers::{EventId, RoomId, RoomVersionId, UserId};
+    use serde_json::{from_str, json, Value};
+
+    use super::{redact, RedactionEvent, RedactionEventContent};
+    use crate::{
+        collections::all::{Event, RoomEvent, StateEvent},
+        RoomEvent as _,
+    };
+
+    fn redaction() -> RedactionEvent {
+        RedactionEvent {
+            content: RedactionEventContent {
+                reason: Some("spam".to_string()),
+            },
+            event_id: EventId::try_from("$redaction:example.com").unwrap(),
+            origin_server_ts: UInt::from(2u32),
+            redacts: EventId::try_from("$h29iv0s8:example.com").unwrap(),
+            room_id: Some(RoomId::try_from("!n8f893n9:example.com").unwrap()),
+            sender: UserId::try_from("@carl:example.com").unwrap(),
+            unsigned: None,
+        }
+    }
+
+    #[test]
+    fn redact_json() {
+        let event: Value = from_str(
+            r#"{
+                "content": {"join_rule": "public", "allow": [], "other": true},
+                "event_id": "$h29iv0s8:example.com",
+                "hashes": {"sha256": "abc"},
+                "origin": "example.com",
+                "origin_server_ts": 1,
+                "room_id": "!n8f893n9:example.com",
+                "sender": "@carl:example.com",
+                "state_key": "",
+                "type": "m.room.join_rules",
+                "unsigned": {"age": 1}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            redact(&event, &RoomVersionId::version_4()),
+            json!({
+                "content": {"join_rule": "public"},
+                "event_id": "$h29iv0s8:example.com",
+                "hashes": {"sha256": "abc"},
+                "origin": "example.com",
+                "origin_server_ts": 1,
+                "room_id": "!n8f893n9:example.com",
+                "sender": "@carl:example.com",
+                "state_key": "",
+                "type": "m.room.join_rules"
+            })
+        );
+
+        let v11 = redact(&event, &RoomVersionId::try_from("11").unwrap());
+
+        assert_eq!(v11["content"], json!({"join_rule": "public", "allow": []}));
+        assert!(v11.get("origin").is_none());
+    }
+
+    #[test]
+    fn redact_aliases_depends_on_room_version() {
+        let event = json!({
+            "content": {"aliases": ["#somewhere:example.com"]},
+            "type": "m.room.aliases"
+        });
+
+        assert_eq!(
+            redact(&event, &RoomVersionId::version_5())["content"],
+            json!({"aliases": ["#somewhere:example.com"]})
+        );
+        assert_eq!(
+            redact(&event, &RoomVersionId::try_from("6").unwrap())["content"],
+            json!({})
+        );
+    }
+
+    #[test]
+    fn redact_member_event_stays_typed() {
+        let event = r#"{
+            "content": {"displayname": "Carl", "membership": "join"},
+            "event_id": "$h29iv0s8:example.com",
+            "origin_server_ts": 1,
+            "room_id": "!n8f893n9:example.com",
+            "sender": "@carl:example.com",
+            "state_key": "@carl:example.com",
+            "type": "m.room.member"
+        }"#
+        .parse::<StateEvent>()
+        .unwrap();
+
+        match event.redact(&redaction(), &RoomVersionId::version_4()) {
+            StateEvent::RoomMember(member) => {
+                assert_eq!(member.content.displayname, None);
+                assert_eq!(
+                    member.unsigned.unwrap()["redacted_because"]["event_id"],
+                    json!("$redaction:example.com")
+                );
+            }
+            _ => panic!("expected a member event"),
+        }
+    }
+
+    #[test]
+    fn redact_power_levels_event_stays_typed() {
+        let event = r#"{
+            "content": {"ban": 50, "events": {}, "notifications": {"room": 100}, "users": {}},
+            "event_id": "$h29iv0s8:example.com",
+            "origin_server_ts": 1,
+            "room_id": "!n8f893n9:example.com",
+            "sender": "@carl:example.com",
+            "state_key": "",
+            "type": "m.room.power_levels"
+        }"#
+        .parse::<StateEvent>()
+        .unwrap();
+
+        match event.redact(&redaction(), &RoomVersionId::version_4()) {
+            StateEvent::RoomPowerLevels(power_levels) => {
+                assert_eq!(power_levels.content.notifications.room, Int::from(50));
+            }
+            _ => panic!("expected a power levels event"),
+        }
+    }
+
+    #[test]
+    fn redact_message_event() {
+        let event = r#"{
+            "content": {"body": "spam", "msgtype": "m.text"},
+            "event_id": "$h29iv0s8:example.com",
+            "origin_server_ts": 1,
+            "room_id": "!n8f893n9:example.com",
+            "sender": "@carl:example.com",
+            "type": "m.room.message",
+            "unsigned": {"age": 5}
+        }"#
+        .parse::<Event>()
+        .unwrap();
+
+        match event.redact(&redaction(), &RoomVersionId::version_4()) {
+            Event::CustomRoom(redacted) => {
+                assert_eq!(redacted.event_type, "m.room.message");
+                assert_eq!(redacted.content, json!({}));
+                assert_eq!(redacted.unsigned().unwrap()["age"], json!(5));
+                assert_eq!(
+                    redacted.unsigned().unwrap()["redacted_because"]["content"]["reason"],
+                    json!("spam")
+                );
+            }
+            _ => panic!("expected a custom room event"),
+        }
+
+        let event = r#"{
+            "content": {"body": "spam", "msgtype": "m.text"},
+            "event_id": "$h29iv0s8:example.com",
+            "origin_server_ts": 1,
+            "sender": "@carl:example.com",
+            "type": "m.room.message"
+        }"#
+        .parse::<RoomEvent>()
+        .unwrap();
+
+        assert!(
+            match event.redact(&redaction(), &RoomVersionId::version_1()) {
+                RoomEvent::CustomRoom(redacted) => redacted.room_id.is_none(),
+                _ => false,
+            }
+        );
+    }
+}
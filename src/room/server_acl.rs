@@ -249,7 +249,7 @@ mod tests {
             r#"{"content":{},"event_id":"$h29iv0s8:example.com","origin_server_ts":1,"sender":"@carl:example.com","state_key":"","type":"m.room.server_acl"}"#
             .parse().unwrap();
 
-        assert_eq!(server_acl_event.content.allow_ip_literals, true);
+        assert!(server_acl_event.content.allow_ip_literals);
         assert!(server_acl_event.content.allow.is_empty());
         assert!(server_acl_event.content.deny.is_empty());
     }
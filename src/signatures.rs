@@ -0,0 +1,635 @@
+//! [Signing](https://matrix.org/docs/spec/appendices#signing-json) of JSON objects and events with
+//! Ed25519 keys, and verification of their signatures.
+//!
+//! Signatures are computed over the canonical JSON of an object without its `signatures` and
+//! `unsigned` keys, and added to its `signatures` as a map from server names to key IDs to
+//! signatures. Events are redacted before signing, so their signatures stay valid when they are
+//! redacted.
+//!
+//! This module is only available with the `signatures` feature.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    error::Error,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use ruma_identifiers::RoomVersionId;
+use serde_json::{to_value, Map, Value};
+
+use crate::{
+    canonical_json::to_canonical_json,
+    hashes::content_hash,
+    room::{member::MemberEvent, redaction::redact, third_party_invite::ThirdPartyInviteEvent},
+    InvalidInput,
+};
+
+/// Public keys, as a map from server names to key IDs to public keys encoded as unpadded base64.
+pub type PublicKeyMap = HashMap<String, HashMap<String, String>>;
+
+/// An Ed25519 key pair used to sign JSON objects and events.
+pub struct Ed25519KeyPair {
+    /// The key used to sign.
+    signing_key: SigningKey,
+
+    /// The version of the key, which makes up its key ID.
+    version: String,
+}
+
+impl Ed25519KeyPair {
+    /// Creates a key pair from the 32-byte seed of its private key.
+    ///
+    /// The key ID of the key pair is `ed25519:` followed by `version`.
+    pub fn new(seed: &[u8], version: String) -> Result<Self, InvalidInput> {
+        let seed = <[u8; 32]>::try_from(seed)
+            .map_err(|_| InvalidInput("Ed25519 seeds must be 32 bytes long".to_string()))?;
+
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&seed),
+            version,
+        })
+    }
+
+    /// The ID of the key, e.g. `ed25519:1`.
+    pub fn key_id(&self) -> String {
+        format!("ed25519:{}", self.version)
+    }
+
+    /// The public key, encoded as unpadded base64.
+    pub fn public_key(&self) -> String {
+        encode(self.signing_key.verifying_key().as_bytes())
+    }
+
+    /// Signs a message, returning the signature encoded as unpadded base64.
+    pub fn sign(&self, message: &[u8]) -> String {
+        encode(&self.signing_key.sign(message).to_bytes())
+    }
+}
+
+impl Debug for Ed25519KeyPair {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("Ed25519KeyPair")
+            .field("key_id", &self.key_id())
+            .field("public_key", &self.public_key())
+            .finish()
+    }
+}
+
+/// The reason a signature could not be verified.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignatureError(String);
+
+impl SignatureError {
+    /// A message describing why verification failed.
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for SignatureError {}
+
+/// The outcome of a successful verification of an event's signatures.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Verified {
+    /// The signatures and the content hash are valid.
+    All,
+
+    /// The signatures are valid but the content hash is not, so the event must be redacted
+    /// before it is used.
+    Signatures,
+}
+
+/// Signs a JSON object, adding the signature of `server_name` to its `signatures`.
+///
+/// Returns an error if `json` is not an object or cannot be encoded as canonical JSON.
+pub fn sign_json(
+    server_name: &str,
+    key_pair: &Ed25519KeyPair,
+    json: &mut Value,
+) -> Result<(), InvalidInput> {
+    let signature = key_pair.sign(signable_json(json)?.as_bytes());
+
+    add_signature(json, server_name, key_pair.key_id(), signature)
+}
+
+/// Verifies the signatures of a JSON object.
+///
+/// For each server in `public_keys`, the object must have a signature by at least one of the
+/// server's keys, and every signature by one of its keys must be valid. Signatures by other servers
+/// and keys are ignored.
+pub fn verify_json(public_keys: &PublicKeyMap, json: &Value) -> Result<(), SignatureError> {
+    let message = signable_json(json).map_err(|error| SignatureError(error.to_string()))?;
+
+    for (server_name, keys) in public_keys {
+        verify_server_signatures(server_name, keys, json, &message)?;
+    }
+
+    Ok(())
+}
+
+/// Hashes and signs an event given as JSON.
+///
+/// The content hash of the event is added to its `hashes`, and the signature of `server_name`
+/// over the redacted event is added to its `signatures`. The JSON representation of a PDU from
+/// the `pdu` module can be signed with this.
+pub fn sign_event(
+    server_name: &str,
+    key_pair: &Ed25519KeyPair,
+    event: &mut Value,
+    version: &RoomVersionId,
+) -> Result<(), InvalidInput> {
+    let hash = content_hash(event, version)?;
+
+    match *event {
+        Value::Object(ref mut object) => {
+            let mut hashes = Map::new();
+            hashes.insert("sha256".to_string(), Value::String(hash.sha256));
+            object.insert("hashes".to_string(), Value::Object(hashes));
+        }
+        _ => return Err(InvalidInput("events must be JSON objects".to_string())),
+    }
+
+    let signature = key_pair.sign(signable_json(&redact(event, version))?.as_bytes());
+
+    add_signature(event, server_name, key_pair.key_id(), signature)
+}
+
+/// Verifies the signatures and the content hash of an event given as JSON.
+///
+/// The event must be signed by the server of its sender, and in room versions 1 and 2 also by the
+/// server that created its event ID. The public keys of those servers must be in `public_keys`,
+/// and their signatures are verified as with `verify_json` over the redacted event.
+///
+/// If the signatures are valid but the content hash is not, `Verified::Signatures` is returned and
+/// the event must be redacted before it is used.
+pub fn verify_event_signatures(
+    public_keys: &PublicKeyMap,
+    event: &Value,
+    version: &RoomVersionId,
+) -> Result<Verified, SignatureError> {
+    let redacted = redact(event, version);
+    let message = signable_json(&redacted).map_err(|error| SignatureError(error.to_string()))?;
+
+    for server_name in required_servers(event, version)? {
+        let keys = public_keys.get(&server_name).ok_or_else(|| {
+            SignatureError(format!("no public keys for the server {}", server_name))
+        })?;
+
+        verify_server_signatures(&server_name, keys, &redacted, &message)?;
+    }
+
+    let hash = content_hash(event, version).map_err(|error| SignatureError(error.to_string()))?;
+    let expected_hash = event
+        .get("hashes")
+        .and_then(|hashes| hashes.get("sha256"))
+        .and_then(Value::as_str);
+
+    if expected_hash == Some(hash.sha256.as_str()) {
+        Ok(Verified::All)
+    } else {
+        Ok(Verified::Signatures)
+    }
+}
+
+/// Verifies the `signed` block of an *m.room.member* event that upgrades a third-party invite.
+///
+/// The `token` of the block must be the state key of the given *m.room.third_party_invite* event,
+/// its `mxid` must be the state key of the member event, and at least one of its signatures must
+/// be valid for one of the public keys of the invite. Key IDs are not taken into account, since
+/// the invite doesn't list them.
+pub fn verify_third_party_invite(
+    member: &MemberEvent,
+    invite: &ThirdPartyInviteEvent,
+) -> Result<(), SignatureError> {
+    let signed = match member.content.third_party_invite {
+        Some(ref third_party_invite) => &third_party_invite.signed,
+        None => {
+            return Err(SignatureError(
+                "the member event has no third-party invite".to_string(),
+            ));
+        }
+    };
+
+    if signed.token != invite.state_key {
+        return Err(SignatureError(format!(
+            "the token {} does not match the third-party invite {}",
+            signed.token, invite.state_key
+        )));
+    }
+
+    if signed.mxid.to_string() != member.state_key {
+        return Err(SignatureError(format!(
+            "the mxid {} does not match the state key {}",
+            signed.mxid, member.state_key
+        )));
+    }
+
+    let json = to_value(signed).map_err(|error| SignatureError(error.to_string()))?;
+    let message = signable_json(&json).map_err(|error| SignatureError(error.to_string()))?;
+
+    let public_keys = std::iter::once(&invite.content.public_key).chain(
+        invite
+            .content
+            .public_keys
+            .iter()
+            .flatten()
+            .map(|public_key| &public_key.public_key),
+    );
+
+    for public_key in public_keys {
+        for (key_id, signature) in signed.signatures.values().flatten() {
+            if key_id.starts_with("ed25519:")
+                && verify_signature(public_key, signature, message.as_bytes()).is_ok()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    Err(SignatureError(
+        "no signature matches a public key of the third-party invite".to_string(),
+    ))
+}
+
+/// The canonical JSON a signature is computed over, i.e. the object without `signatures` and
+/// `unsigned`.
+fn signable_json(json: &Value) -> Result<String, InvalidInput> {
+    match *json {
+        Value::Object(ref object) => {
+            let mut object = object.clone();
+            object.remove("signatures");
+            object.remove("unsigned");
+
+            to_canonical_json(&object)
+        }
+        _ => Err(InvalidInput("only JSON objects can be signed".to_string())),
+    }
+}
+
+/// Adds a signature to the `signatures` of a JSON object.
+fn add_signature(
+    json: &mut Value,
+    server_name: &str,
+    key_id: String,
+    signature: String,
+) -> Result<(), InvalidInput> {
+    let object = match *json {
+        Value::Object(ref mut object) => object,
+        _ => return Err(InvalidInput("only JSON objects can be signed".to_string())),
+    };
+
+    let signatures = object
+        .entry("signatures")
+        .or_insert_with(|| Value::Object(Map::new()));
+
+    if !signatures.is_object() {
+        return Err(InvalidInput(
+            "`signatures` must be a JSON object".to_string(),
+        ));
+    }
+
+    let server_signatures = signatures
+        .as_object_mut()
+        .expect("signatures is an object")
+        .entry(server_name)
+        .or_insert_with(|| Value::Object(Map::new()));
+
+    match *server_signatures {
+        Value::Object(ref mut server_signatures) => {
+            server_signatures.insert(key_id, Value::String(signature));
+
+            Ok(())
+        }
+        _ => Err(InvalidInput(format!(
+            "the signatures of {} must be a JSON object",
+            server_name
+        ))),
+    }
+}
+
+/// Verifies the signatures of one server over a message, requiring at least one signature by a
+/// known key.
+fn verify_server_signatures(
+    server_name: &str,
+    keys: &HashMap<String, String>,
+    json: &Value,
+    message: &str,
+) -> Result<(), SignatureError> {
+    let signatures = json
+        .get("signatures")
+        .and_then(|signatures| signatures.get(server_name))
+        .and_then(Value::as_object)
+        .ok_or_else(|| SignatureError(format!("no signatures by the server {}", server_name)))?;
+
+    let mut verified = false;
+
+    for (key_id, signature) in signatures {
+        let public_key = match keys.get(key_id) {
+            Some(public_key) if key_id.starts_with("ed25519:") => public_key,
+            _ => continue,
+        };
+
+        let signature = signature.as_str().ok_or_else(|| {
+            SignatureError(format!(
+                "the signature of {} by {} is not a string",
+                server_name, key_id
+            ))
+        })?;
+
+        verify_signature(public_key, signature, message.as_bytes()).map_err(|error| {
+            SignatureError(format!(
+                "the signature of {} by {} is invalid: {}",
+                server_name, key_id, error
+            ))
+        })?;
+
+        verified = true;
+    }
+
+    if verified {
+        Ok(())
+    } else {
+        Err(SignatureError(format!(
+            "no signatures by a known key of the server {}",
+            server_name
+        )))
+    }
+}
+
+/// Verifies an Ed25519 signature, with the public key and signature encoded as base64.
+pub(crate) fn verify_signature(
+    public_key: &str,
+    signature: &str,
+    message: &[u8],
+) -> Result<(), SignatureError> {
+    let public_key = <[u8; 32]>::try_from(decode(public_key)?.as_slice())
+        .map_err(|_| SignatureError("Ed25519 public keys must be 32 bytes long".to_string()))
+        .and_then(|bytes| {
+            VerifyingKey::from_bytes(&bytes).map_err(|error| SignatureError(error.to_string()))
+        })?;
+    let signature = Signature::from_slice(&decode(signature)?)
+        .map_err(|error| SignatureError(error.to_string()))?;
+
+    public_key
+        .verify(message, &signature)
+        .map_err(|error| SignatureError(error.to_string()))
+}
+
+/// Encodes bytes as unpadded base64.
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::STANDARD_NO_PAD)
+}
+
+/// Decodes padded or unpadded base64, in either the standard or the URL-safe alphabet.
+fn decode(encoded: &str) -> Result<Vec<u8>, SignatureError> {
+    let config = if encoded.contains(['-', '_']) {
+        base64::URL_SAFE_NO_PAD
+    } else {
+        base64::STANDARD_NO_PAD
+    };
+
+    base64::decode_config(encoded.trim_end_matches('='), config)
+        .map_err(|error| SignatureError(error.to_string()))
+}
+
+/// The servers whose signatures an event needs.
+fn required_servers(event: &Value, version: &RoomVersionId) -> Result<Vec<String>, SignatureError> {
+    let mut servers = Vec::new();
+
+    let sender = event
+        .get("sender")
+        .and_then(Value::as_str)
+        .ok_or_else(|| SignatureError("the event has no sender".to_string()))?;
+    servers.push(server_name(sender)?);
+
+    if version.is_version_1() || version.is_version_2() {
+        let event_id = event
+            .get("event_id")
+            .and_then(Value::as_str)
+            .ok_or_else(|| SignatureError("the event has no event_id".to_string()))?;
+        let server = server_name(event_id)?;
+
+        if !servers.contains(&server) {
+            servers.push(server);
+        }
+    }
+
+    Ok(servers)
+}
+
+/// The server name part of a user or event ID.
+fn server_name(id: &str) -> Result<String, SignatureError> {
+    id.splitn(2, ':')
+        .nth(1)
+        .map(|server_name| server_name.to_string())
+        .ok_or_else(|| SignatureError(format!("{} has no server name", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ruma_identifiers::RoomVersionId;
+    use serde_json::json;
+
+    use super::{
+        sign_event, sign_json, verify_event_signatures, verify_json, verify_third_party_invite,
+        Ed25519KeyPair, PublicKeyMap, Verified,
+    };
+    use crate::room::{member::MemberEvent, third_party_invite::ThirdPartyInviteEvent};
+
+    /// The key used by the examples in the specification.
+    fn key_pair() -> Ed25519KeyPair {
+        let seed = base64::decode_config(
+            "YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA1",
+            base64::STANDARD_NO_PAD.decode_allow_trailing_bits(true),
+        )
+        .unwrap();
+
+        Ed25519KeyPair::new(&seed, "1".to_string()).unwrap()
+    }
+
+    fn public_keys(server_name: &str, key_pair: &Ed25519KeyPair) -> PublicKeyMap {
+        let mut keys = HashMap::new();
+        keys.insert(key_pair.key_id(), key_pair.public_key());
+
+        let mut public_keys = HashMap::new();
+        public_keys.insert(server_name.to_string(), keys);
+        public_keys
+    }
+
+    #[test]
+    fn spec_json_signatures() {
+        let key_pair = key_pair();
+
+        let mut json = json!({});
+        sign_json("domain", &key_pair, &mut json).unwrap();
+
+        assert_eq!(
+            json,
+            json!({
+                "signatures": {
+                    "domain": {
+                        "ed25519:1": "K8280/U9SSy9IVtjBuVeLr+HpOB4BQFWbg+UZaADMtTdGYI7Geitb76LTrr5QV/7Xg4ahLwYGYZzuHGZKM5ZAQ"
+                    }
+                }
+            })
+        );
+
+        let mut json = json!({"one": 1, "two": "Two"});
+        sign_json("domain", &key_pair, &mut json).unwrap();
+
+        assert_eq!(
+            json["signatures"]["domain"]["ed25519:1"],
+            "KqmLSbO39/Bzb0QIYE82zqLwsA+PDzYIpIRA2sRQ4sL53+sN6/fpNSoqE7BP7vBZhG6kYdD13EIMJpvhJI+6Bw"
+        );
+
+        json["unsigned"] = json!({"age": 1});
+        verify_json(&public_keys("domain", &key_pair), &json).unwrap();
+
+        json["one"] = json!(2);
+        assert!(verify_json(&public_keys("domain", &key_pair), &json).is_err());
+    }
+
+    #[test]
+    fn event_signatures() {
+        let key_pair = key_pair();
+        let public_keys = public_keys("example.com", &key_pair);
+        let version = RoomVersionId::version_1();
+
+        let mut event = json!({
+            "auth_events": [],
+            "content": {"body": "hello", "msgtype": "m.text"},
+            "depth": 1,
+            "event_id": "$event:example.com",
+            "origin": "example.com",
+            "origin_server_ts": 1,
+            "prev_events": [],
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "type": "m.room.message",
+            "unsigned": {"age": 5}
+        });
+
+        sign_event("example.com", &key_pair, &mut event, &version).unwrap();
+
+        assert!(event["hashes"]["sha256"].is_string());
+        assert_eq!(
+            verify_event_signatures(&public_keys, &event, &version),
+            Ok(Verified::All)
+        );
+
+        event["content"]["body"] = json!("changed");
+
+        assert_eq!(
+            verify_event_signatures(&public_keys, &event, &version),
+            Ok(Verified::Signatures)
+        );
+
+        event["origin_server_ts"] = json!(2);
+
+        assert!(verify_event_signatures(&public_keys, &event, &version).is_err());
+        assert!(verify_event_signatures(&HashMap::new(), &event, &version).is_err());
+    }
+
+    #[test]
+    fn third_party_invites() {
+        let identity_key = Ed25519KeyPair::new(&[1; 32], "0".to_string()).unwrap();
+        let other_key = Ed25519KeyPair::new(&[2; 32], "0".to_string()).unwrap();
+
+        let invite = |public_key: String, public_keys: Vec<String>| -> ThirdPartyInviteEvent {
+            json!({
+                "content": {
+                    "display_name": "dave",
+                    "key_validity_url": "https://identity.example.com/isvalid",
+                    "public_key": public_key,
+                    "public_keys": public_keys
+                        .into_iter()
+                        .map(|public_key| json!({"public_key": public_key}))
+                        .collect::<Vec<_>>()
+                },
+                "event_id": "$invite:example.com",
+                "origin_server_ts": 1,
+                "room_id": "!room:example.com",
+                "sender": "@bob:example.com",
+                "state_key": "token",
+                "type": "m.room.third_party_invite"
+            })
+            .to_string()
+            .parse()
+            .unwrap()
+        };
+
+        let member = |key_pair: &Ed25519KeyPair, mxid: &str, token: &str| -> MemberEvent {
+            let mut signed = json!({"mxid": mxid, "token": token});
+            sign_json("identity.example.com", key_pair, &mut signed).unwrap();
+
+            json!({
+                "content": {
+                    "membership": "invite",
+                    "third_party_invite": {"display_name": "dave", "signed": signed}
+                },
+                "event_id": "$member:example.com",
+                "origin_server_ts": 2,
+                "room_id": "!room:example.com",
+                "sender": "@bob:example.com",
+                "state_key": "@dave:example.com",
+                "type": "m.room.member"
+            })
+            .to_string()
+            .parse()
+            .unwrap()
+        };
+
+        let valid = member(&identity_key, "@dave:example.com", "token");
+
+        assert!(
+            verify_third_party_invite(&valid, &invite(identity_key.public_key(), vec![])).is_ok()
+        );
+        assert!(verify_third_party_invite(
+            &valid,
+            &invite(other_key.public_key(), vec![identity_key.public_key()])
+        )
+        .is_ok());
+        assert!(
+            verify_third_party_invite(&valid, &invite(other_key.public_key(), vec![])).is_err()
+        );
+        assert!(verify_third_party_invite(
+            &member(&identity_key, "@dave:example.com", "other"),
+            &invite(identity_key.public_key(), vec![])
+        )
+        .is_err());
+        assert!(verify_third_party_invite(
+            &member(&identity_key, "@erin:example.com", "token"),
+            &invite(identity_key.public_key(), vec![])
+        )
+        .is_err());
+
+        let mut tampered = valid.clone();
+        tampered
+            .content
+            .third_party_invite
+            .as_mut()
+            .unwrap()
+            .signed
+            .token = "token2".to_string();
+
+        assert!(
+            verify_third_party_invite(&tampered, &invite(identity_key.public_key(), vec![]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert!(Ed25519KeyPair::new(&[0; 31], "1".to_string()).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! A materialized room state, holding the current state event for every `(event_type, state_key)`
+//! pair.
+
+use std::iter::FromIterator;
+
+use ruma_identifiers::UserId;
+use serde_json::to_value;
+
+use crate::{
+    auth::{auth_check, AuthError, StateMap},
+    collections::all::{RoomEvent, StateEvent},
+    room::{
+        canonical_alias::CanonicalAliasEvent, create::CreateEvent, encryption::EncryptionEvent,
+        join_rules::JoinRulesEvent, member::MemberEvent, name::NameEvent,
+        power_levels::PowerLevelsEvent, topic::TopicEvent,
+    },
+    EventType,
+};
+
+/// The state of a room.
+///
+/// Within a room, a state event replaces any previous state event with the same type and state
+/// key, so the state is a map from `(event_type, state_key)` to the latest state event.
+#[derive(Clone, Debug, Default)]
+pub struct RoomState {
+    /// The state events, by type and state key.
+    events: StateMap,
+}
+
+impl RoomState {
+    /// Creates an empty room state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies a state event, returning the event it replaces, if any.
+    ///
+    /// The `prev_content` of the event is set to the content of the event it replaces, or to
+    /// `None` if there is no such event. If the replaced event has a different representation,
+    /// such as a typed event being replaced by a custom one, `prev_content` is only set for
+    /// custom events.
+    pub fn apply(&mut self, mut event: StateEvent) -> Option<StateEvent> {
+        let key = (event.event_type(), event.state_key().to_string());
+
+        set_prev_content(&mut event, self.events.get(&key));
+
+        self.events.insert(key, event)
+    }
+
+    /// The state event with the given type and state key.
+    pub fn get(&self, event_type: &EventType, state_key: &str) -> Option<&StateEvent> {
+        self.events
+            .get(&(event_type.clone(), state_key.to_string()))
+    }
+
+    /// Removes the state event with the given type and state key, returning it.
+    pub fn remove(&mut self, event_type: &EventType, state_key: &str) -> Option<StateEvent> {
+        self.events
+            .remove(&(event_type.clone(), state_key.to_string()))
+    }
+
+    /// An iterator over all state events, in arbitrary order.
+    pub fn events(&self) -> impl Iterator<Item = &StateEvent> {
+        self.events.values()
+    }
+
+    /// The number of state events.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Whether there are no state events.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The state as a map from `(event_type, state_key)` to state events.
+    pub fn as_map(&self) -> &StateMap {
+        &self.events
+    }
+
+    /// Checks whether an event is allowed in the room with this state by the authorization
+    /// rules.
+    ///
+    /// See `auth::auth_check` for details.
+    pub fn auth_check(&self, event: &RoomEvent) -> Result<(), AuthError> {
+        auth_check(event, &self.events)
+    }
+
+    /// The *m.room.name* event.
+    pub fn name(&self) -> Option<&NameEvent> {
+        match self.get(&EventType::RoomName, "") {
+            Some(StateEvent::RoomName(ref event)) => Some(event),
+            _ => None,
+        }
+    }
+
+    /// The *m.room.topic* event.
+    pub fn topic(&self) -> Option<&TopicEvent> {
+        match self.get(&EventType::RoomTopic, "") {
+            Some(StateEvent::RoomTopic(ref event)) => Some(event),
+            _ => None,
+        }
+    }
+
+    /// The *m.room.power_levels* event.
+    pub fn power_levels(&self) -> Option<&PowerLevelsEvent> {
+        match self.get(&EventType::RoomPowerLevels, "") {
+            Some(StateEvent::RoomPowerLevels(ref event)) => Some(event),
+            _ => None,
+        }
+    }
+
+    /// The *m.room.join_rules* event.
+    pub fn join_rules(&self) -> Option<&JoinRulesEvent> {
+        match self.get(&EventType::RoomJoinRules, "") {
+            Some(StateEvent::RoomJoinRules(ref event)) => Some(event),
+            _ => None,
+        }
+    }
+
+    /// The *m.room.member* events of all users with a membership in the room, in arbitrary order.
+    pub fn members(&self) -> impl Iterator<Item = &MemberEvent> {
+        self.events.values().filter_map(|event| match *event {
+            StateEvent::RoomMember(ref event) => Some(event),
+            _ => None,
+        })
+    }
+
+    /// The *m.room.member* event of the given user.
+    pub fn member(&self, user_id: &UserId) -> Option<&MemberEvent> {
+        match self.get(&EventType::RoomMember, &user_id.to_string()) {
+            Some(StateEvent::RoomMember(ref event)) => Some(event),
+            _ => None,
+        }
+    }
+
+    /// The *m.room.canonical_alias* event.
+    pub fn canonical_alias(&self) -> Option<&CanonicalAliasEvent> {
+        match self.get(&EventType::RoomCanonicalAlias, "") {
+            Some(StateEvent::RoomCanonicalAlias(ref event)) => Some(event),
+            _ => None,
+        }
+    }
+
+    /// The *m.room.encryption* event.
+    pub fn encryption(&self) -> Option<&EncryptionEvent> {
+        match self.get(&EventType::RoomEncryption, "") {
+            Some(StateEvent::RoomEncryption(ref event)) => Some(event),
+            _ => None,
+        }
+    }
+
+    /// The *m.room.create* event.
+    pub fn create(&self) -> Option<&CreateEvent> {
+        match self.get(&EventType::RoomCreate, "") {
+            Some(StateEvent::RoomCreate(ref event)) => Some(event),
+            _ => None,
+        }
+    }
+}
+
+impl From<StateMap> for RoomState {
+    fn from(events: StateMap) -> Self {
+        Self { events }
+    }
+}
+
+impl FromIterator<StateEvent> for RoomState {
+    fn from_iter<I>(events: I) -> Self
+    where
+        I: IntoIterator<Item = StateEvent>,
+    {
+        let mut state = Self::new();
+        state.extend(events);
+        state
+    }
+}
+
+impl Extend<StateEvent> for RoomState {
+    fn extend<I>(&mut self, events: I)
+    where
+        I: IntoIterator<Item = StateEvent>,
+    {
+        for event in events {
+            self.apply(event);
+        }
+    }
+}
+
+/// Sets the `prev_content` of a state event to the content of the event it replaces.
+fn set_prev_content(event: &mut StateEvent, replaced: Option<&StateEvent>) {
+    macro_rules! set_prev_content {
+        ($($variant:ident),*) => {
+            match *event {
+                $(
+                    StateEvent::$variant(ref mut event) => {
+                        event.prev_content = match replaced {
+                            Some(StateEvent::$variant(ref replaced)) => {
+                                Some(replaced.content.clone())
+                            }
+                            _ => None,
+                        };
+                    }
+                )*
+                StateEvent::CustomState(ref mut event) => {
+                    event.prev_content = replaced.map(|replaced| {
+                        to_value(replaced).expect("events always serialize to JSON")["content"]
+                            .clone()
+                    });
+                }
+            }
+        };
+    }
+
+    set_prev_content!(
+        RoomAliases,
+        RoomAvatar,
+        RoomCanonicalAlias,
+        RoomCreate,
+        RoomEncryption,
+        RoomGuestAccess,
+        RoomHistoryVisibility,
+        RoomJoinRules,
+        RoomMember,
+        RoomName,
+        RoomPinnedEvents,
+        RoomPowerLevels,
+        RoomServerAcl,
+        RoomThirdPartyInvite,
+        RoomTombstone,
+        RoomTopic
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ruma_identifiers::UserId;
+    use serde_json::{json, Value};
+
+    use super::RoomState;
+    use crate::{
+        collections::all::StateEvent, room::member::MembershipState, EventType, StateEvent as _,
+    };
+
+    fn state_event(event_type: &str, state_key: &str, content: Value, id: &str) -> StateEvent {
+        // Custom state events read their type from `event_type` rather than `type`.
+        json!({
+            "content": content,
+            "event_id": format!("${}:example.com", id),
+            "event_type": event_type,
+            "origin_server_ts": 1,
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "state_key": state_key,
+            "type": event_type
+        })
+        .to_string()
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn typed_accessors() {
+        let state: RoomState = vec![
+            state_event(
+                "m.room.create",
+                "",
+                json!({"creator": "@alice:example.com"}),
+                "create",
+            ),
+            state_event("m.room.name", "", json!({"name": "Room"}), "name"),
+            state_event("m.room.topic", "", json!({"topic": "Topic"}), "topic"),
+            state_event(
+                "m.room.member",
+                "@alice:example.com",
+                json!({"membership": "join"}),
+                "alice",
+            ),
+            state_event(
+                "m.room.member",
+                "@bob:example.com",
+                json!({"membership": "invite"}),
+                "bob",
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let bob = UserId::try_from("@bob:example.com").unwrap();
+
+        assert_eq!(state.len(), 5);
+        assert_eq!(state.name().unwrap().content.name, Some("Room".to_string()));
+        assert_eq!(state.topic().unwrap().content.topic, "Topic");
+        assert_eq!(state.create().unwrap().content.creator.localpart(), "alice");
+        assert_eq!(state.members().count(), 2);
+        assert_eq!(
+            state.member(&bob).unwrap().content.membership,
+            MembershipState::Invite
+        );
+        assert!(state.power_levels().is_none());
+        assert!(state.join_rules().is_none());
+        assert!(state.canonical_alias().is_none());
+        assert!(state.encryption().is_none());
+    }
+
+    #[test]
+    fn apply_sets_prev_content() {
+        let mut state = RoomState::new();
+
+        assert!(state
+            .apply(state_event(
+                "m.room.topic",
+                "",
+                json!({"topic": "First"}),
+                "first"
+            ))
+            .is_none());
+        assert!(state.topic().unwrap().prev_content.is_none());
+
+        let mut second = state_event("m.room.topic", "", json!({"topic": "Second"}), "second");
+
+        if let StateEvent::RoomTopic(ref mut event) = second {
+            event.prev_content = Some(event.content.clone());
+        }
+
+        let replaced = state.apply(second).unwrap();
+        let topic = state.topic().unwrap();
+
+        assert_eq!(replaced.event_id().to_string(), "$first:example.com");
+        assert_eq!(topic.content.topic, "Second");
+        assert_eq!(topic.prev_content.as_ref().unwrap().topic, "First");
+
+        state.apply(state_event(
+            "com.example.custom",
+            "",
+            json!({"a": 1}),
+            "custom1",
+        ));
+        state.apply(state_event(
+            "com.example.custom",
+            "",
+            json!({"a": 2}),
+            "custom2",
+        ));
+
+        match state.get(&EventType::Custom("com.example.custom".to_string()), "") {
+            Some(StateEvent::CustomState(ref event)) => {
+                assert_eq!(event.content, json!({"a": 2}));
+                assert_eq!(event.prev_content, Some(json!({"a": 1})));
+                assert_eq!(event.state_key(), "");
+            }
+            _ => panic!("expected a custom state event"),
+        }
+    }
+}
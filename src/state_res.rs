@@ -0,0 +1,773 @@
+//! [State resolution v2](https://matrix.org/docs/spec/rooms/v2#state-resolution), which merges
+//! the conflicting states of a room at the tips of diverging branches of its event graph.
+//!
+//! This algorithm is used by room versions 2 and later.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use js_int::{Int, UInt};
+use ruma_identifiers::EventId;
+
+use crate::{
+    auth::{auth_check, StateMap},
+    collections::all::{RoomEvent, StateEvent},
+    room::{member::MembershipState, power_levels::PowerLevelsEventContent},
+    EventType, InvalidInput,
+};
+
+/// The state of a room, as a map from `(event_type, state_key)` to event IDs.
+pub type StateIds = HashMap<(EventType, String), EventId>;
+
+/// A state event along with the IDs of its auth events, which state resolution needs to order
+/// events and check them against the authorization rules.
+///
+/// A `pdu::Pdu<collections::all::StateEvent>` can be converted into this type.
+#[derive(Clone, Debug)]
+pub struct ResolvableEvent {
+    /// The event itself.
+    pub event: StateEvent,
+
+    /// The IDs of the event's auth events.
+    pub auth_events: Vec<EventId>,
+}
+
+impl ResolvableEvent {
+    /// The `(event_type, state_key)` pair of the event.
+    fn key(&self) -> (EventType, String) {
+        (self.event.event_type(), self.event.state_key().to_string())
+    }
+}
+
+/// Resolves several states of a room into one.
+///
+/// `auth_chains` must hold the full auth chain of each state in `state_sets`, in the same order,
+/// and `events` must contain every event in the states and their auth chains. An error is
+/// returned if an event is missing, or if the auth events of the conflicted power events form a
+/// cycle.
+///
+/// Events are checked against the authorization rules with `auth::auth_check`, so the
+/// limitations described there apply.
+pub fn resolve(
+    state_sets: &[StateIds],
+    auth_chains: &[HashSet<EventId>],
+    events: &HashMap<EventId, ResolvableEvent>,
+) -> Result<StateIds, InvalidInput> {
+    let (unconflicted, conflicted) = separate(state_sets);
+
+    if conflicted.is_empty() {
+        return Ok(unconflicted);
+    }
+
+    let full_conflicted: HashSet<EventId> = conflicted
+        .into_iter()
+        .chain(auth_difference(auth_chains))
+        .collect();
+
+    let mut control_events = HashSet::new();
+
+    for event_id in &full_conflicted {
+        if is_power_event(&get(events, event_id)?.event) {
+            add_with_auth_chain(event_id, &full_conflicted, events, &mut control_events)?;
+        }
+    }
+
+    let sorted_control_events = reverse_topological_power_sort(&control_events, events)?;
+    let mut resolved = iterative_auth_checks(&sorted_control_events, unconflicted.clone(), events)?;
+
+    let other_events: Vec<EventId> = full_conflicted
+        .difference(&control_events)
+        .cloned()
+        .collect();
+    let power_levels = resolved
+        .get(&(EventType::RoomPowerLevels, String::new()))
+        .cloned();
+    let sorted_other_events = mainline_sort(&other_events, power_levels, events)?;
+
+    resolved = iterative_auth_checks(&sorted_other_events, resolved, events)?;
+    resolved.extend(unconflicted);
+
+    Ok(resolved)
+}
+
+/// Computes the full auth chain of the given events, i.e. their auth events, the auth events of
+/// those, and so on, not including the events themselves unless they are in the auth chain of
+/// another one.
+pub fn auth_chain<'a, I>(
+    event_ids: I,
+    events: &HashMap<EventId, ResolvableEvent>,
+) -> Result<HashSet<EventId>, InvalidInput>
+where
+    I: IntoIterator<Item = &'a EventId>,
+{
+    let mut chain = HashSet::new();
+    let mut pending: Vec<EventId> = Vec::new();
+
+    for event_id in event_ids {
+        pending.extend(get(events, event_id)?.auth_events.iter().cloned());
+    }
+
+    while let Some(event_id) = pending.pop() {
+        if chain.insert(event_id.clone()) {
+            pending.extend(get(events, &event_id)?.auth_events.iter().cloned());
+        }
+    }
+
+    Ok(chain)
+}
+
+/// Splits the states into the unconflicted state, whose keys have the same event in every state,
+/// and the IDs of the conflicted events.
+fn separate(state_sets: &[StateIds]) -> (StateIds, HashSet<EventId>) {
+    let mut unconflicted = StateIds::new();
+    let mut conflicted = HashSet::new();
+
+    let keys: HashSet<&(EventType, String)> =
+        state_sets.iter().flat_map(|state| state.keys()).collect();
+
+    for key in keys {
+        let event_ids: Vec<Option<&EventId>> =
+            state_sets.iter().map(|state| state.get(key)).collect();
+
+        match event_ids[0] {
+            Some(event_id) if event_ids.iter().all(|other| *other == Some(event_id)) => {
+                unconflicted.insert(key.clone(), event_id.clone());
+            }
+            _ => conflicted.extend(event_ids.into_iter().flatten().cloned()),
+        }
+    }
+
+    (unconflicted, conflicted)
+}
+
+/// The events that are in some but not all of the auth chains.
+fn auth_difference(auth_chains: &[HashSet<EventId>]) -> HashSet<EventId> {
+    let union: HashSet<&EventId> = auth_chains.iter().flatten().collect();
+
+    union
+        .into_iter()
+        .filter(|event_id| !auth_chains.iter().all(|chain| chain.contains(*event_id)))
+        .cloned()
+        .collect()
+}
+
+/// Whether an event affects who may do what in the room.
+fn is_power_event(event: &StateEvent) -> bool {
+    match *event {
+        StateEvent::RoomCreate(_)
+        | StateEvent::RoomJoinRules(_)
+        | StateEvent::RoomPowerLevels(_) => true,
+        StateEvent::RoomMember(ref member) => {
+            let membership = member.content.membership;
+
+            (membership == MembershipState::Leave || membership == MembershipState::Ban)
+                && member.sender.to_string() != member.state_key
+        }
+        _ => false,
+    }
+}
+
+/// Adds an event and the events in its auth chain that are in the full conflicted set.
+fn add_with_auth_chain(
+    event_id: &EventId,
+    full_conflicted: &HashSet<EventId>,
+    events: &HashMap<EventId, ResolvableEvent>,
+    added: &mut HashSet<EventId>,
+) -> Result<(), InvalidInput> {
+    let mut pending = vec![event_id.clone()];
+    let mut visited = HashSet::new();
+
+    while let Some(event_id) = pending.pop() {
+        if !visited.insert(event_id.clone()) {
+            continue;
+        }
+
+        if full_conflicted.contains(&event_id) {
+            added.insert(event_id.clone());
+        }
+
+        pending.extend(get(events, &event_id)?.auth_events.iter().cloned());
+    }
+
+    Ok(())
+}
+
+/// Sorts events so that each comes after its auth events, breaking ties by putting events whose
+/// sender has a higher power level first, then older events first, then events with a
+/// lexicographically smaller ID first.
+///
+/// Fails if the auth events form a cycle, since the events in it can't be ordered.
+fn reverse_topological_power_sort(
+    event_ids: &HashSet<EventId>,
+    events: &HashMap<EventId, ResolvableEvent>,
+) -> Result<Vec<EventId>, InvalidInput> {
+    let mut remaining_auth_events: HashMap<&EventId, usize> = HashMap::new();
+    let mut dependents: HashMap<&EventId, Vec<&EventId>> = HashMap::new();
+    let mut keys = HashMap::new();
+    let mut ids = HashMap::new();
+
+    for event_id in event_ids {
+        let event = get(events, event_id)?;
+        let auth_events: Vec<&EventId> = event
+            .auth_events
+            .iter()
+            .filter(|auth_event| event_ids.contains(*auth_event))
+            .collect();
+
+        remaining_auth_events.insert(event_id, auth_events.len());
+
+        for auth_event in auth_events {
+            dependents.entry(auth_event).or_default().push(event_id);
+        }
+
+        keys.insert(event_id, sort_key(event, events)?);
+        ids.insert(event_id.to_string(), event_id);
+    }
+
+    let mut ready: BinaryHeap<Reverse<(Reverse<Int>, UInt, String)>> = remaining_auth_events
+        .iter()
+        .filter(|&(_, &count)| count == 0)
+        .map(|(&event_id, _)| Reverse(keyed(&keys, event_id)))
+        .collect();
+    let mut sorted = Vec::with_capacity(event_ids.len());
+
+    while let Some(Reverse((_, _, event_id))) = ready.pop() {
+        let event_id = ids[&event_id];
+
+        sorted.push(event_id.clone());
+
+        for &dependent in dependents.get(event_id).into_iter().flatten() {
+            let count = remaining_auth_events
+                .get_mut(dependent)
+                .expect("dependents are in the set");
+            *count -= 1;
+
+            if *count == 0 {
+                ready.push(Reverse(keyed(&keys, dependent)));
+            }
+        }
+    }
+
+    if sorted.len() < event_ids.len() {
+        return Err(InvalidInput(
+            "the auth events of the conflicted power events form a cycle".to_string(),
+        ));
+    }
+
+    Ok(sorted)
+}
+
+/// The key events are sorted by in `reverse_topological_power_sort`.
+fn keyed(
+    keys: &HashMap<&EventId, (Reverse<Int>, UInt)>,
+    event_id: &EventId,
+) -> (Reverse<Int>, UInt, String) {
+    let (power_level, origin_server_ts) = keys[event_id];
+
+    (power_level, origin_server_ts, event_id.to_string())
+}
+
+/// The sender's power level, reversed so higher levels sort first, and the timestamp of an event.
+fn sort_key(
+    event: &ResolvableEvent,
+    events: &HashMap<EventId, ResolvableEvent>,
+) -> Result<(Reverse<Int>, UInt), InvalidInput> {
+    let sender = event.event.sender();
+    let mut power_level = Int::from(0);
+
+    for auth_event_id in &event.auth_events {
+        match get(events, auth_event_id)?.event {
+            StateEvent::RoomPowerLevels(ref power_levels) => {
+                power_level = power_levels.content.user_level(sender);
+                break;
+            }
+            StateEvent::RoomCreate(ref create) => {
+                power_level =
+                    PowerLevelsEventContent::without_event(&create.content).user_level(sender);
+            }
+            _ => {}
+        }
+    }
+
+    Ok((Reverse(power_level), event.event.origin_server_ts()))
+}
+
+/// Sorts events by the position of their closest power levels event on the mainline of the
+/// given power levels event, then by timestamp, then by ID.
+fn mainline_sort(
+    event_ids: &[EventId],
+    power_levels: Option<EventId>,
+    events: &HashMap<EventId, ResolvableEvent>,
+) -> Result<Vec<EventId>, InvalidInput> {
+    let mut mainline = Vec::new();
+    let mut current = power_levels;
+
+    while let Some(event_id) = current {
+        current = power_levels_auth_event(get(events, &event_id)?, events)?;
+        mainline.push(event_id);
+    }
+
+    let positions: HashMap<EventId, usize> = mainline
+        .into_iter()
+        .rev()
+        .enumerate()
+        .map(|(index, event_id)| (event_id, index + 1))
+        .collect();
+
+    let mut keyed_events = Vec::with_capacity(event_ids.len());
+
+    for event_id in event_ids {
+        let event = get(events, event_id)?;
+        let mut position = 0;
+        let mut current = Some(event_id.clone());
+
+        while let Some(closest) = current {
+            if let Some(&closest_position) = positions.get(&closest) {
+                position = closest_position;
+                break;
+            }
+
+            current = power_levels_auth_event(get(events, &closest)?, events)?;
+        }
+
+        keyed_events.push((
+            position,
+            event.event.origin_server_ts(),
+            event_id.to_string(),
+            event_id.clone(),
+        ));
+    }
+
+    keyed_events.sort_by(|a, b| (a.0, a.1, &a.2).cmp(&(b.0, b.1, &b.2)));
+
+    Ok(keyed_events
+        .into_iter()
+        .map(|(_, _, _, event_id)| event_id)
+        .collect())
+}
+
+/// The ID of the *m.room.power_levels* event among the auth events of an event.
+fn power_levels_auth_event(
+    event: &ResolvableEvent,
+    events: &HashMap<EventId, ResolvableEvent>,
+) -> Result<Option<EventId>, InvalidInput> {
+    for auth_event_id in &event.auth_events {
+        if let StateEvent::RoomPowerLevels(_) = get(events, auth_event_id)?.event {
+            return Ok(Some(auth_event_id.clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Checks each event in order against the authorization rules, adding it to the state if it
+/// passes.
+///
+/// Each event is checked against its auth events, with the events in the state taking precedence
+/// for the keys the authorization rules look at.
+fn iterative_auth_checks(
+    event_ids: &[EventId],
+    mut state: StateIds,
+    events: &HashMap<EventId, ResolvableEvent>,
+) -> Result<StateIds, InvalidInput> {
+    for event_id in event_ids {
+        let event = get(events, event_id)?;
+        let mut auth_state = StateMap::new();
+
+        for auth_event_id in &event.auth_events {
+            let auth_event = get(events, auth_event_id)?;
+
+            auth_state.insert(auth_event.key(), auth_event.event.clone());
+        }
+
+        for key in auth_types(&event.event) {
+            if let Some(state_event_id) = state.get(&key) {
+                auth_state.insert(key, get(events, state_event_id)?.event.clone());
+            }
+        }
+
+        if auth_check(&RoomEvent::from(event.event.clone()), &auth_state).is_ok() {
+            state.insert(event.key(), event_id.clone());
+        }
+    }
+
+    Ok(state)
+}
+
+/// The `(event_type, state_key)` pairs of the state events the authorization rules look at for
+/// an event.
+fn auth_types(event: &StateEvent) -> Vec<(EventType, String)> {
+    let mut auth_types = vec![
+        (EventType::RoomCreate, String::new()),
+        (EventType::RoomPowerLevels, String::new()),
+        (EventType::RoomMember, event.sender().to_string()),
+    ];
+
+    if let StateEvent::RoomMember(ref member) = *event {
+        auth_types.push((EventType::RoomMember, member.state_key.clone()));
+
+        match member.content.membership {
+            MembershipState::Join | MembershipState::Invite | MembershipState::Knock => {
+                auth_types.push((EventType::RoomJoinRules, String::new()));
+            }
+            _ => {}
+        }
+
+        if let Some(ref third_party_invite) = member.content.third_party_invite {
+            auth_types.push((
+                EventType::RoomThirdPartyInvite,
+                third_party_invite.signed.token.clone(),
+            ));
+        }
+    }
+
+    auth_types
+}
+
+/// Looks up an event, failing if it is missing.
+fn get<'a>(
+    events: &'a HashMap<EventId, ResolvableEvent>,
+    event_id: &EventId,
+) -> Result<&'a ResolvableEvent, InvalidInput> {
+    events
+        .get(event_id)
+        .ok_or_else(|| InvalidInput(format!("event {} is missing", event_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        convert::TryFrom,
+    };
+
+    use ruma_identifiers::EventId;
+    use serde_json::{json, Value};
+
+    use super::{auth_chain, resolve, ResolvableEvent, StateIds};
+    use crate::EventType;
+
+    /// Builds events and states for tests, with each event's auth events taken from the state
+    /// it is added to.
+    #[derive(Default)]
+    struct Room {
+        events: HashMap<EventId, ResolvableEvent>,
+        time: u32,
+    }
+
+    impl Room {
+        fn add(
+            &mut self,
+            state: &mut StateIds,
+            id: &str,
+            event_type: &str,
+            state_key: &str,
+            sender: &str,
+            content: Value,
+        ) -> EventId {
+            self.time += 1;
+
+            let event_id = EventId::try_from(format!("${}:example.com", id).as_str()).unwrap();
+            let event = json!({
+                "content": content,
+                "event_id": event_id,
+                "origin_server_ts": self.time,
+                "room_id": "!room:example.com",
+                "sender": sender,
+                "state_key": state_key,
+                "type": event_type
+            })
+            .to_string()
+            .parse()
+            .unwrap();
+
+            let mut auth_keys = vec![
+                ("m.room.create".to_string(), String::new()),
+                ("m.room.power_levels".to_string(), String::new()),
+                ("m.room.member".to_string(), sender.to_string()),
+            ];
+
+            if event_type == "m.room.member" {
+                auth_keys.push(("m.room.member".to_string(), state_key.to_string()));
+                auth_keys.push(("m.room.join_rules".to_string(), String::new()));
+            }
+
+            let auth_events = auth_keys
+                .into_iter()
+                .filter_map(|(event_type, state_key)| {
+                    state
+                        .get(&(EventType::from(event_type.as_str()), state_key))
+                        .cloned()
+                })
+                .collect();
+
+            self.events
+                .insert(event_id.clone(), ResolvableEvent { event, auth_events });
+            state.insert(
+                (EventType::from(event_type), state_key.to_string()),
+                event_id.clone(),
+            );
+
+            event_id
+        }
+
+        /// A room created by Alice, with Bob as a moderator and Carl as a regular member.
+        fn new() -> (Self, StateIds) {
+            let mut room = Room::default();
+            let mut state = StateIds::new();
+            let alice = "@alice:example.com";
+
+            room.add(
+                &mut state,
+                "create",
+                "m.room.create",
+                "",
+                alice,
+                json!({"creator": alice, "room_version": "2"}),
+            );
+            room.add(
+                &mut state,
+                "alice",
+                "m.room.member",
+                alice,
+                alice,
+                json!({"membership": "join"}),
+            );
+            room.add(
+                &mut state,
+                "pl",
+                "m.room.power_levels",
+                "",
+                alice,
+                json!({"users": {alice: 100, "@bob:example.com": 50}}),
+            );
+            room.add(
+                &mut state,
+                "join_rules",
+                "m.room.join_rules",
+                "",
+                alice,
+                json!({"join_rule": "public"}),
+            );
+
+            for user in &["bob", "carl"] {
+                let user_id = format!("@{}:example.com", user);
+
+                room.add(
+                    &mut state,
+                    user,
+                    "m.room.member",
+                    &user_id,
+                    &user_id,
+                    json!({"membership": "join"}),
+                );
+            }
+
+            (room, state)
+        }
+
+        fn resolve(&self, state_sets: &[StateIds]) -> StateIds {
+            let auth_chains: Vec<HashSet<EventId>> = state_sets
+                .iter()
+                .map(|state| auth_chain(state.values(), &self.events).unwrap())
+                .collect();
+
+            resolve(state_sets, &auth_chains, &self.events).unwrap()
+        }
+    }
+
+    fn topic(state: &StateIds) -> String {
+        state[&(EventType::RoomTopic, String::new())].to_string()
+    }
+
+    #[test]
+    fn unconflicted() {
+        let (room, state) = Room::new();
+
+        assert_eq!(room.resolve(&[state.clone(), state.clone()]), state);
+    }
+
+    #[test]
+    fn later_topic_wins() {
+        let (mut room, mut base) = Room::new();
+
+        room.add(
+            &mut base,
+            "t0",
+            "m.room.topic",
+            "",
+            "@alice:example.com",
+            json!({"topic": "0"}),
+        );
+
+        let mut a = base.clone();
+        let mut b = base.clone();
+
+        room.add(
+            &mut a,
+            "t1",
+            "m.room.topic",
+            "",
+            "@alice:example.com",
+            json!({"topic": "1"}),
+        );
+        room.add(
+            &mut b,
+            "t2",
+            "m.room.topic",
+            "",
+            "@bob:example.com",
+            json!({"topic": "2"}),
+        );
+
+        assert_eq!(
+            topic(&room.resolve(&[a.clone(), b.clone()])),
+            "$t2:example.com"
+        );
+        assert_eq!(topic(&room.resolve(&[b, a])), "$t2:example.com");
+    }
+
+    #[test]
+    fn demotion_beats_topic_change() {
+        let (mut room, mut base) = Room::new();
+
+        room.add(
+            &mut base,
+            "t0",
+            "m.room.topic",
+            "",
+            "@alice:example.com",
+            json!({"topic": "0"}),
+        );
+
+        let mut a = base.clone();
+        let mut b = base.clone();
+
+        let demotion = room.add(
+            &mut a,
+            "pl2",
+            "m.room.power_levels",
+            "",
+            "@alice:example.com",
+            json!({"users": {"@alice:example.com": 100}}),
+        );
+        room.add(
+            &mut b,
+            "t2",
+            "m.room.topic",
+            "",
+            "@bob:example.com",
+            json!({"topic": "2"}),
+        );
+
+        let resolved = room.resolve(&[a, b]);
+
+        assert_eq!(
+            resolved[&(EventType::RoomPowerLevels, String::new())],
+            demotion
+        );
+        assert_eq!(topic(&resolved), "$t0:example.com");
+    }
+
+    #[test]
+    fn higher_power_ban_applies_first() {
+        let (mut room, base) = Room::new();
+
+        let mut a = base.clone();
+        let mut b = base.clone();
+
+        room.add(
+            &mut a,
+            "ban_bob",
+            "m.room.member",
+            "@bob:example.com",
+            "@alice:example.com",
+            json!({"membership": "ban"}),
+        );
+        room.add(
+            &mut b,
+            "pl2",
+            "m.room.power_levels",
+            "",
+            "@bob:example.com",
+            json!({"users": {"@alice:example.com": 100, "@bob:example.com": 50, "@carl:example.com": 50}}),
+        );
+        room.add(
+            &mut b,
+            "ban_alice",
+            "m.room.member",
+            "@alice:example.com",
+            "@bob:example.com",
+            json!({"membership": "ban"}),
+        );
+
+        let resolved = room.resolve(&[a, b]);
+
+        assert_eq!(
+            resolved[&(EventType::RoomMember, "@bob:example.com".to_string())].to_string(),
+            "$ban_bob:example.com"
+        );
+        assert_eq!(
+            resolved[&(EventType::RoomMember, "@alice:example.com".to_string())].to_string(),
+            "$alice:example.com"
+        );
+        assert_eq!(
+            resolved[&(EventType::RoomPowerLevels, String::new())].to_string(),
+            "$pl:example.com"
+        );
+    }
+
+    #[test]
+    fn auth_cycle() {
+        let (mut room, base) = Room::new();
+
+        let mut a = base.clone();
+        let mut b = base.clone();
+
+        let pl2 = room.add(
+            &mut a,
+            "pl2",
+            "m.room.power_levels",
+            "",
+            "@alice:example.com",
+            json!({"users": {"@alice:example.com": 100}}),
+        );
+        let pl3 = room.add(
+            &mut b,
+            "pl3",
+            "m.room.power_levels",
+            "",
+            "@alice:example.com",
+            json!({"users": {"@alice:example.com": 100, "@carl:example.com": 50}}),
+        );
+
+        room.events
+            .get_mut(&pl2)
+            .unwrap()
+            .auth_events
+            .push(pl3.clone());
+        room.events.get_mut(&pl3).unwrap().auth_events.push(pl2);
+
+        let auth_chains: Vec<HashSet<EventId>> = [&a, &b]
+            .iter()
+            .map(|state| auth_chain(state.values(), &room.events).unwrap())
+            .collect();
+
+        assert!(resolve(&[a, b], &auth_chains, &room.events).is_err());
+    }
+
+    #[test]
+    fn missing_events() {
+        let (room, state) = Room::new();
+        let mut other = state.clone();
+
+        other.insert(
+            (EventType::RoomTopic, String::new()),
+            EventId::try_from("$missing:example.com").unwrap(),
+        );
+
+        assert!(resolve(&[state, other], &[], &room.events).is_err());
+    }
+}
@@ -62,6 +62,10 @@ pub enum StrippedState {
 
     /// A stripped-down version of the *m.room.topic* event.
     RoomTopic(StrippedRoomTopic),
+
+    /// A stripped-down event that isn't one of the above, or that failed to parse as one, holding
+    /// the raw JSON.
+    Custom(Value),
 }
 
 /// A "stripped-down" version of a core state event.
@@ -173,6 +177,7 @@ impl Serialize for StrippedState {
             StrippedState::RoomPowerLevels(ref event) => event.serialize(serializer),
             StrippedState::RoomThirdPartyInvite(ref event) => event.serialize(serializer),
             StrippedState::RoomTopic(ref event) => event.serialize(serializer),
+            StrippedState::Custom(ref event) => event.serialize(serializer),
         }
     }
 }
@@ -0,0 +1,278 @@
+//! Types for the `unsigned` data of room events.
+
+use std::{collections::HashMap, convert::TryFrom, str::FromStr};
+
+use js_int::Int;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use serde_json::{from_value, Value};
+
+use crate::{
+    room::redaction::RedactionEvent, stripped::StrippedState, InnerInvalidEvent, InvalidEvent,
+};
+
+/// Additional key-value pairs on a room event that are not signed by the homeserver.
+///
+/// Room events still keep their unsigned data as a `serde_json::Value`, since that is the type the
+/// `ruma_event!` macro of ruma-events-macros generates for the field, and can be deserialized
+/// into this type.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct UnsignedData {
+    /// The time in milliseconds that has elapsed since the event was sent.
+    ///
+    /// This is set by the homeserver delivering the event, and can be negative if the clocks of
+    /// the servers involved are out of sync.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age: Option<Int>,
+
+    /// The client-supplied transaction ID, if the event was sent by the client being given the
+    /// event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_id: Option<String>,
+
+    /// The previous content of a state event, if any.
+    ///
+    /// This is left as a `serde_json::Value` because its type depends on the type of the event.
+    /// State events also expose it as `StateEvent::prev_content`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_content: Option<Value>,
+
+    /// The event that redacted this event, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redacted_because: Option<Box<RedactionEvent>>,
+
+    /// A subset of the state of the room, included with *m.room.member* events for invites.
+    ///
+    /// Entries of unsupported types, or that fail to parse, are kept as `StrippedState::Custom`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub invite_room_state: Vec<StrippedState>,
+
+    /// Any other keys in the unsigned data, including known keys with values of the wrong type.
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+impl UnsignedData {
+    /// Creates `UnsignedData` from a JSON object.
+    ///
+    /// Known keys whose values don't have the expected type are kept in `other` rather than
+    /// failing the whole conversion.
+    fn from_json(json: Value) -> Result<Self, InvalidEvent> {
+        let object = match json {
+            Value::Object(object) => object,
+            json => {
+                return Err(InvalidEvent(InnerInvalidEvent::Validation {
+                    json,
+                    message: "unsigned data must be a JSON object".to_string(),
+                }));
+            }
+        };
+
+        let mut unsigned = Self::default();
+
+        for (key, value) in object {
+            match key.as_str() {
+                "age" => {
+                    if let Ok(age) = from_value(value.clone()) {
+                        unsigned.age = age;
+                        continue;
+                    }
+                }
+                "transaction_id" => {
+                    if let Ok(transaction_id) = from_value(value.clone()) {
+                        unsigned.transaction_id = transaction_id;
+                        continue;
+                    }
+                }
+                "prev_content" => {
+                    unsigned.prev_content = Some(value).filter(|value| !value.is_null());
+                    continue;
+                }
+                "redacted_because" => {
+                    if let Ok(redaction) = value.to_string().parse() {
+                        unsigned.redacted_because = Some(Box::new(redaction));
+                        continue;
+                    }
+                }
+                "invite_room_state" => {
+                    if let Value::Array(ref states) = value {
+                        unsigned.invite_room_state = states
+                            .iter()
+                            .map(|state| {
+                                state
+                                    .to_string()
+                                    .parse()
+                                    .unwrap_or_else(|_| StrippedState::Custom(state.clone()))
+                            })
+                            .collect();
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+
+            unsigned.other.insert(key, value);
+        }
+
+        Ok(unsigned)
+    }
+}
+
+impl<'de> Deserialize<'de> for UnsignedData {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Self::from_json(Deserialize::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+impl FromStr for UnsignedData {
+    type Err = InvalidEvent;
+
+    /// Attempt to create `Self` from parsing a string of JSON data.
+    fn from_str(json: &str) -> Result<Self, Self::Err> {
+        match serde_json::from_str::<Value>(json) {
+            Ok(value) => Self::from_json(value),
+            Err(error) => Err(InvalidEvent(InnerInvalidEvent::Deserialization { error })),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for UnsignedData {
+    type Error = InvalidEvent;
+
+    /// Attempt to create `Self` from parsing a string of JSON data.
+    fn try_from(json: &'a str) -> Result<Self, Self::Error> {
+        FromStr::from_str(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use js_int::Int;
+    use serde_json::{from_value, json, to_value};
+
+    use super::UnsignedData;
+    use crate::{room::member::MemberEvent, stripped::StrippedState, RoomEvent};
+
+    #[test]
+    fn invite_room_state() {
+        let event = r#"{
+            "content": {"membership": "invite"},
+            "event_id": "$h29iv0s8:example.com",
+            "origin_server_ts": 1,
+            "room_id": "!n8f893n9:example.com",
+            "sender": "@carl:example.com",
+            "state_key": "@alice:example.com",
+            "type": "m.room.member",
+            "unsigned": {
+                "age": 1234,
+                "invite_room_state": [
+                    {
+                        "content": {"name": "Example Room"},
+                        "sender": "@carl:example.com",
+                        "state_key": "",
+                        "type": "m.room.name"
+                    },
+                    {
+                        "content": {"algorithm": "m.megolm.v1.aes-sha2"},
+                        "sender": "@carl:example.com",
+                        "state_key": "",
+                        "type": "m.room.encryption"
+                    }
+                ],
+                "io.example.custom": true
+            }
+        }"#
+        .parse::<MemberEvent>()
+        .unwrap();
+
+        let unsigned: UnsignedData = from_value(event.unsigned().unwrap().clone()).unwrap();
+
+        assert_eq!(unsigned.age, Some(Int::from(1234)));
+        assert_eq!(unsigned.invite_room_state.len(), 2);
+        assert_eq!(unsigned.other["io.example.custom"], json!(true));
+
+        match unsigned.invite_room_state[0] {
+            StrippedState::RoomName(ref name) => {
+                assert_eq!(name.content.name(), Some("Example Room"));
+            }
+            _ => panic!("expected a stripped m.room.name event"),
+        }
+
+        match unsigned.invite_room_state[1] {
+            StrippedState::Custom(ref event) => {
+                assert_eq!(event["type"], json!("m.room.encryption"));
+            }
+            _ => panic!("expected a custom stripped event"),
+        }
+
+        assert_eq!(
+            to_value(&unsigned).unwrap()["invite_room_state"][1]["content"],
+            json!({"algorithm": "m.megolm.v1.aes-sha2"})
+        );
+    }
+
+    #[test]
+    fn redacted_because() {
+        let unsigned = r#"{
+            "redacted_because": {
+                "content": {"reason": "spam"},
+                "event_id": "$redaction:example.com",
+                "origin_server_ts": 2,
+                "redacts": "$h29iv0s8:example.com",
+                "sender": "@carl:example.com",
+                "type": "m.room.redaction"
+            },
+            "transaction_id": "m1234"
+        }"#
+        .parse::<UnsignedData>()
+        .unwrap();
+
+        let redaction = unsigned.redacted_because.as_ref().unwrap();
+
+        assert_eq!(redaction.content.reason, Some("spam".to_string()));
+        assert_eq!(unsigned.transaction_id, Some("m1234".to_string()));
+        assert_eq!(
+            to_value(&unsigned).unwrap()["redacted_because"]["redacts"],
+            json!("$h29iv0s8:example.com")
+        );
+    }
+
+    #[test]
+    fn invalid_known_keys() {
+        let unsigned = r#"{
+            "age": "old",
+            "invite_room_state": [{"content": {}, "type": "m.room.name"}],
+            "redacted_because": {"type": "m.room.redaction"},
+            "transaction_id": "m1234"
+        }"#
+        .parse::<UnsignedData>()
+        .unwrap();
+
+        assert_eq!(unsigned.age, None);
+        assert!(unsigned.redacted_because.is_none());
+        assert_eq!(unsigned.transaction_id, Some("m1234".to_string()));
+        assert_eq!(unsigned.other["age"], json!("old"));
+        assert_eq!(
+            unsigned.other["redacted_because"],
+            json!({"type": "m.room.redaction"})
+        );
+
+        match unsigned.invite_room_state[0] {
+            StrippedState::Custom(ref event) => assert_eq!(event["type"], json!("m.room.name")),
+            _ => panic!("expected a custom stripped event"),
+        }
+
+        assert!("[]".parse::<UnsignedData>().is_err());
+    }
+
+    #[test]
+    fn empty_unsigned_data() {
+        let unsigned = "{}".parse::<UnsignedData>().unwrap();
+
+        assert_eq!(unsigned.age, None);
+        assert_eq!(to_value(&unsigned).unwrap(), json!({}));
+        assert!(from_value::<UnsignedData>(json!("unsigned")).is_err());
+    }
+}